@@ -0,0 +1,78 @@
+use url::Url;
+use clap::Parser;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+use crate::shared::sandbox::SandboxPaths;
+use crate::features::save_to_local::cli::parse_url;
+use crate::shared::network::{http::config::HttpArgs,factory::TransportType};
+
+#[derive(Clone,Debug,Parser)]
+pub struct CatArgs{
+    ///Http url of the file to print.
+    #[arg(value_parser=parse_url,)]
+    pub url:Url,
+    #[command(flatten)]
+    pub http_args:HttpArgs,
+    ///Transport to use for send and receiving data. It can be http/https.
+    #[arg(short='t',long,value_enum,default_value_t=TransportType::Http)]
+    pub transport:TransportType,
+    ///Print files over 100 MiB and content that doesn't look like text too.
+    #[arg(long)]
+    pub force:bool,
+    ///Decompress gzip content while printing it. The default for `.gz` urls and `Content-Encoding: gzip` responses.
+    #[arg(long,overrides_with="no_decompress")]
+    pub decompress:bool,
+    ///Print gzip content as is.
+    #[arg(long,overrides_with="decompress")]
+    pub no_decompress:bool,
+    ///Print only the first N bytes, of the decompressed content when decompressing.
+    #[arg(long,value_name="N",value_parser=clap::value_parser!(u64).range(1..),conflicts_with="tail_bytes")]
+    pub head_bytes:Option<u64>,
+    ///Print only the last N bytes, fetched with a range request so the rest is never transferred.
+    #[arg(long,value_name="N",value_parser=clap::value_parser!(u64).range(1..))]
+    pub tail_bytes:Option<u64>,
+}
+impl CatArgs{
+    ///Whether the content is decompressed, `None` when it depends on the response's Content-Encoding.
+    pub fn decompress_choice(&self)->Option<bool>{
+        if self.no_decompress {
+            Some(false)
+        } else if self.decompress || self.url.path().ends_with(".gz") {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    ///Paths a sandboxed `cat` needs, nothing is written besides a recording.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    pub fn sandbox_paths(&self)->SandboxPaths{
+        let mut paths = SandboxPaths::default();
+        paths.writable.extend(self.http_args.record.clone());
+        paths.readable.extend(self.http_args.replay.clone());
+        paths.readable.extend(self.http_args.proxy_cacert.clone());
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_choice() {
+        let choice = |args: &[&str]| CatArgs::parse_from(args).decompress_choice();
+        assert_eq!(choice(&["cat", "https://example.com/app.log"]), None);
+        assert_eq!(choice(&["cat", "https://example.com/app.log.gz"]), Some(true));
+        assert_eq!(choice(&["cat", "https://example.com/app.log.gz", "--no-decompress"]), Some(false));
+        assert_eq!(choice(&["cat", "https://example.com/app.log", "--decompress"]), Some(true));
+        // The last of the two flags wins.
+        assert_eq!(choice(&["cat", "https://example.com/app.log.gz", "--no-decompress", "--decompress"]), Some(true));
+    }
+
+    #[test]
+    fn test_windows_are_exclusive_and_non_empty() {
+        assert!(CatArgs::try_parse_from(["cat", "example.com/a", "--head-bytes", "1", "--tail-bytes", "1"]).is_err());
+        assert!(CatArgs::try_parse_from(["cat", "example.com/a", "--head-bytes", "0"]).is_err());
+        assert!(CatArgs::try_parse_from(["cat", "example.com/a", "--tail-bytes", "512"]).is_ok());
+    }
+}
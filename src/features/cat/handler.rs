@@ -0,0 +1,320 @@
+//! Printing a remote file to stdout
+//!
+//! `cliant cat` is tuned for a quick look at logs, configs and other text
+//! artifacts rather than for saving them: there is no progress output, gzip
+//! content is decompressed on the fly, and `--head-bytes`/`--tail-bytes`
+//! fetch only a window of the file with a range request.
+//!
+//! # Guards
+//!
+//! Content that doesn't look like text, and full transfers over
+//! [`MAX_PRINT_BYTES`], are refused unless `--force`, so a mistyped url doesn't
+//! flood the terminal with binary data.
+//!
+//! # Pipes
+//!
+//! A reader that goes away early (`| head`, quitting `less`) ends the command
+//! successfully, the rest of the content is simply not fetched.
+
+use std::io::ErrorKind;
+
+use super::cli::CatArgs;
+use crate::shared::errors::CliantError;
+use crate::shared::gzip::GzipDecoder;
+use crate::shared::network::{
+    ByteRange, DataTransport, DownloadInfo,
+    factory::{TransportType, handle_http},
+};
+use crate::shared::redact::{Redact, Redacted};
+use crate::shared::text_mode::is_text;
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, instrument};
+
+/// Full transfers larger than this are refused without `--force`.
+pub const MAX_PRINT_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Bytes at the start of untyped content checked for binary data.
+const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Types a gzip file is commonly served as, its content is then checked once decompressed.
+const GZIP_TYPES: &[&str] = &["application/gzip", "application/x-gzip", "application/octet-stream"];
+
+/// Prints the file at `args.url` to stdout.
+#[instrument(name = "handle_cat", fields(args = %Redacted(&args.url)), skip(args))]
+pub async fn handle(args: CatArgs) -> Result<()> {
+    let mut stdout = tokio::io::stdout();
+    print(args, &mut stdout).await
+}
+
+/// Writes the content, or the window of it `args` asks for, to `out`.
+pub async fn print<W: AsyncWrite + Unpin>(args: CatArgs, out: &mut W) -> Result<()> {
+    // Reject misconfigured request headers before any network I/O.
+    args.http_args.validate()?;
+    if args.tail_bytes.is_some() && args.decompress_choice() == Some(true) {
+        return Err(gzip_tail_error(&args).into());
+    }
+    let transport = match args.transport {
+        TransportType::Http => handle_http(args.http_args.clone(), &TransportType::Http),
+    }?;
+    let range = match (args.head_bytes, args.tail_bytes) {
+        (_, Some(len)) => Some(ByteRange::Tail(len)),
+        // A window of the decompressed content isn't a range of the compressed bytes.
+        (Some(len), _) if args.decompress_choice() != Some(true) => Some(ByteRange::Head(len)),
+        _ => None,
+    };
+    // Both arms return different stream types.
+    match range {
+        Some(range) => {
+            let (info, stream) = transport.receive_range(args.url.clone(), range).await?;
+            write_content(&args, &info, stream, out).await
+        }
+        None => {
+            let (info, stream) = transport.receive_data(args.url.clone()).await?;
+            write_content(&args, &info, stream, out).await
+        }
+    }
+}
+
+async fn write_content<W, S>(args: &CatArgs, info: &DownloadInfo, mut stream: S, out: &mut W) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    S: Stream<Item = Result<Bytes, CliantError>> + Unpin,
+{
+    let decompress = args.decompress_choice().unwrap_or_else(|| is_gzip_encoded(info));
+    if args.tail_bytes.is_some() {
+        if !info.partial {
+            return Err(CliantError::TailUnavailable {
+                url: args.url.redact(),
+                reason: "the server doesn't support range requests. Use --head-bytes, \
+                    or `cliant download` to fetch the whole file"
+                    .into(),
+            }
+            .into());
+        }
+        if decompress {
+            return Err(gzip_tail_error(args).into());
+        }
+    }
+    let full_transfer = args.head_bytes.is_none() && args.tail_bytes.is_none();
+    let mut sniff = !args.force && check_printable(args, info, decompress, full_transfer)?;
+    let mut decoder = decompress.then(GzipDecoder::default);
+    // A server ignoring the Range header sends everything, the window is then cut here.
+    let mut remaining = args.head_bytes.unwrap_or(u64::MAX);
+    while remaining > 0
+        && let Some(chunk) = stream.next().await
+    {
+        let chunk = chunk?;
+        let mut data = match &mut decoder {
+            Some(decoder) => Bytes::from(decoder.push(&chunk)?),
+            None => chunk,
+        };
+        if sniff && !data.is_empty() {
+            check_looks_like_text(args, &data)?;
+            sniff = false;
+        }
+        data.truncate(usize::try_from(remaining).unwrap_or(usize::MAX));
+        remaining -= data.len() as u64;
+        if !emit(out, &data).await? {
+            return Ok(());
+        }
+    }
+    if let Err(err) = out.flush().await
+        && err.kind() != ErrorKind::BrokenPipe
+    {
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Writes `data` to `out`, `false` once the reader is gone.
+async fn emit<W: AsyncWrite + Unpin>(out: &mut W, data: &[u8]) -> std::io::Result<bool> {
+    match out.write_all(data).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+            debug!("The reader closed the pipe, not printing the rest");
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn is_gzip_encoded(info: &DownloadInfo) -> bool {
+    info.content_encoding.as_deref().is_some_and(|encoding| encoding.trim().eq_ignore_ascii_case("gzip"))
+}
+
+fn gzip_tail_error(args: &CatArgs) -> CliantError {
+    CliantError::TailUnavailable {
+        url: args.url.redact(),
+        reason: "the content is gzip-compressed and its end can't be decompressed on its own. \
+            Use --no-decompress to print the compressed bytes"
+            .into(),
+    }
+}
+
+/// Refuses content types that aren't text, and full transfers over [`MAX_PRINT_BYTES`].
+///
+/// Returns whether the content itself must be checked, for types that don't
+/// tell, e.g the `application/octet-stream` servers send for unknown extensions.
+fn check_printable(args: &CatArgs, info: &DownloadInfo, decompress: bool, full_transfer: bool) -> Result<bool, CliantError> {
+    let refuse = |reason: String| CliantError::Unprintable { url: args.url.redact(), reason };
+    if let Some(size) = info.total_bytes.filter(|&size| full_transfer && size > MAX_PRINT_BYTES) {
+        return Err(refuse(format!(
+            "it is {size} bytes, over the {} MiB limit. --head-bytes and --tail-bytes print a part of it",
+            MAX_PRINT_BYTES / 1024 / 1024
+        )));
+    }
+    let Some(content_type) = &info.content_type else {
+        return Ok(true);
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    if is_text(&mime) {
+        Ok(false)
+    } else if decompress && GZIP_TYPES.contains(&mime.as_str()) || mime == "application/octet-stream" {
+        Ok(true)
+    } else {
+        Err(refuse(format!("its content type {content_type} doesn't look like text")))
+    }
+}
+
+/// Refuses content with NUL bytes at its start, text never has any.
+fn check_looks_like_text(args: &CatArgs, data: &[u8]) -> Result<(), CliantError> {
+    if data.iter().take(SNIFF_BYTES).any(|&byte| byte == 0) {
+        return Err(CliantError::Unprintable { url: args.url.redact(), reason: "its content looks binary".into() });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::gzip::gzip_fixture;
+    use crate::shared::test_server::{Route, TestServer};
+    use clap::Parser;
+
+    const LOG: &str = "2026-10-01 start\n2026-10-01 working\n2026-10-01 done\n";
+
+    fn text_route(body: impl Into<Bytes>, headers: &[(&str, &str)]) -> Route {
+        let mut route = Route::ok(body);
+        route.headers = headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        route
+    }
+
+    async fn cat(url: &url::Url, flags: &[&str]) -> Result<Vec<u8>> {
+        let args = CatArgs::parse_from(["cat", url.as_str()].iter().chain(flags));
+        let mut out = Vec::new();
+        print(args, &mut out).await?;
+        Ok(out)
+    }
+
+    fn range_header(server: &TestServer) -> Option<String> {
+        let requests = server.requests();
+        let headers = &requests.last().unwrap().headers;
+        headers.iter().find(|(name, _)| name == "range").map(|(_, value)| value.clone())
+    }
+
+    #[tokio::test]
+    async fn test_prints_the_whole_file() -> Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/app.log", text_route(LOG, &[("Content-Type", "text/plain; charset=utf-8")]));
+        assert_eq!(cat(&server.url("/app.log"), &[]).await?, LOG.as_bytes());
+        assert_eq!(range_header(&server), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_head_and_tail_windows_use_ranges() -> Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/app.log", text_route(LOG, &[("Content-Type", "text/plain"), ("Accept-Ranges", "bytes")]));
+        let url = server.url("/app.log");
+
+        assert_eq!(cat(&url, &["--head-bytes", "16"]).await?, b"2026-10-01 start");
+        assert_eq!(range_header(&server).as_deref(), Some("bytes=0-15"));
+        assert_eq!(cat(&url, &["--tail-bytes", "5"]).await?, b"done\n");
+        assert_eq!(range_header(&server).as_deref(), Some("bytes=-5"));
+        // Windows larger than the file print all of it.
+        assert_eq!(cat(&url, &["--tail-bytes", "4096"]).await?, LOG.as_bytes());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_windows_without_range_support() -> Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/app.log", text_route(LOG, &[("Content-Type", "text/plain")]));
+        let url = server.url("/app.log");
+
+        // The full response is cut to the window.
+        assert_eq!(cat(&url, &["--head-bytes", "16"]).await?, b"2026-10-01 start");
+        let err = cat(&url, &["--tail-bytes", "5"]).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(CliantError::TailUnavailable { .. })), "{err}");
+        assert!(err.to_string().contains("--head-bytes"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_type_guard() -> Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/logo.png", text_route("\u{89}PNG", &[("Content-Type", "image/png")]));
+        server.route("/tool.bin", text_route(vec![0x7f, b'E', b'L', b'F', 0, 0], &[("Content-Type", "application/octet-stream")]));
+        server.route("/app.log", text_route(LOG, &[("Content-Type", "application/octet-stream")]));
+        server.route("/data.json", text_route("{}", &[("Content-Type", "application/vnd.api+json")]));
+        server.route("/untyped", Route::ok("plain"));
+
+        for path in ["/logo.png", "/tool.bin"] {
+            let err = cat(&server.url(path), &[]).await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(CliantError::Unprintable { .. })), "{path}: {err}");
+        }
+        assert_eq!(cat(&server.url("/tool.bin"), &["--force"]).await?, [0x7f, b'E', b'L', b'F', 0, 0]);
+        // Servers send unknown extensions as octet-stream, the content tells it is text.
+        assert_eq!(cat(&server.url("/app.log"), &[]).await?, LOG.as_bytes());
+        assert_eq!(cat(&server.url("/data.json"), &[]).await?, b"{}");
+        assert_eq!(cat(&server.url("/untyped"), &[]).await?, b"plain");
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_guard_applies_to_full_transfers() {
+        let args = CatArgs::parse_from(["cat", "https://example.com/huge.log"]);
+        let info = DownloadInfo {
+            total_bytes: Some(MAX_PRINT_BYTES + 1),
+            content_type: Some("text/plain".into()),
+            ..DownloadInfo::default()
+        };
+        assert!(matches!(check_printable(&args, &info, false, true), Err(CliantError::Unprintable { .. })));
+        assert!(matches!(check_printable(&args, &info, false, false), Ok(false)));
+        let small = DownloadInfo { total_bytes: Some(MAX_PRINT_BYTES), ..info };
+        assert!(matches!(check_printable(&args, &small, false, true), Ok(false)));
+    }
+
+    #[tokio::test]
+    async fn test_gzip_is_decompressed() -> Result<()> {
+        let server = TestServer::start().await?;
+        let gz = gzip_fixture(LOG.as_bytes());
+        server.route("/app.log.gz", text_route(gz.clone(), &[("Content-Type", "application/gzip"), ("Accept-Ranges", "bytes")]));
+        server.route("/app.log", text_route(gz.clone(), &[("Content-Type", "text/plain"), ("Content-Encoding", "gzip")]));
+
+        assert_eq!(cat(&server.url("/app.log.gz"), &[]).await?, LOG.as_bytes());
+        assert_eq!(cat(&server.url("/app.log"), &[]).await?, LOG.as_bytes());
+        assert_eq!(cat(&server.url("/app.log"), &["--no-decompress"]).await?, gz);
+        // The window is of the decompressed content, so the whole file is requested.
+        assert_eq!(cat(&server.url("/app.log.gz"), &["--head-bytes", "16"]).await?, b"2026-10-01 start");
+        assert_eq!(range_header(&server), None);
+
+        let err = cat(&server.url("/app.log.gz"), &["--tail-bytes", "5"]).await.unwrap_err();
+        assert!(err.to_string().contains("--no-decompress"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closed_pipe_ends_quietly() -> Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/app.log", text_route(LOG, &[("Content-Type", "text/plain")]));
+        let (mut writer, reader) = tokio::io::duplex(8);
+        drop(reader);
+        let args = CatArgs::parse_from(["cat", server.url("/app.log").as_str()]);
+        print(args, &mut writer).await?;
+        Ok(())
+    }
+}
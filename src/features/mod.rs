@@ -1 +1,4 @@
-pub mod save_to_local;
\ No newline at end of file
+pub mod save_to_local;
+pub mod cat;
+pub mod page;
+pub mod self_test;
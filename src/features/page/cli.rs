@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+use url::Url;
+use clap::Parser;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+use crate::shared::sandbox::SandboxPaths;
+use crate::features::save_to_local::cli::parse_url;
+use crate::shared::network::{http::config::HttpArgs,factory::TransportType};
+
+#[derive(Clone,Debug,Parser)]
+pub struct PageArgs{
+    ///Http url of the page to save.
+    #[arg(value_parser=parse_url,)]
+    pub url:Url,
+    ///Directory to save the page and its assets in, created if missing.
+    #[arg(short='o',value_parser=parse_output_dir)]
+    pub output:PathBuf,
+    #[command(flatten)]
+    pub http_args:HttpArgs,
+    ///Transport to use for send and receiving data. It can be http/https.
+    #[arg(short='t',long,value_enum,default_value_t=TransportType::Http)]
+    pub transport:TransportType,
+    ///Also fetch assets served by other hosts than the page's.
+    #[arg(long)]
+    pub span_hosts:bool,
+}
+impl PageArgs{
+    ///Paths a sandboxed `page` needs, everything is written under the output directory.
+    ///
+    /// The directory is created here, Landlock can only grant access to existing paths.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    pub fn sandbox_paths(&self)->anyhow::Result<SandboxPaths>{
+        std::fs::create_dir_all(&self.output)?;
+        let mut paths = SandboxPaths{ writable: vec![self.output.clone()], readable: vec![] };
+        paths.writable.extend(self.http_args.record.clone());
+        paths.readable.extend(self.http_args.replay.clone());
+        paths.readable.extend(self.http_args.proxy_cacert.clone());
+        Ok(paths)
+    }
+}
+
+///Expands `~` in the output directory.
+fn parse_output_dir(path:&str)->Result<PathBuf,String>{
+    Ok(PathBuf::from(shellexpand::tilde(path).as_ref()))
+}
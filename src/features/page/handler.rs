@@ -0,0 +1,358 @@
+//! Saving an HTML page with the assets it needs to render
+//!
+//! A lightweight take on wget's `--page-requisites` for archiving a single
+//! documentation page: the page's images, stylesheets and scripts (see
+//! [`html::requisites`]) are downloaded one by one through the download
+//! handler, then the page is saved with those references rewritten to the
+//! local copies. Links to other pages are never followed.
+//!
+//! # Layout
+//!
+//! The page is saved as `index.html` in the output directory, each asset under
+//! `<host>[_<port>]/<path of its url>` next to it (see [`local_path`]), so the
+//! rewritten references are plain relative paths.
+//!
+//! # Rewriting
+//!
+//! Only the attribute values that were downloaded are replaced, in place; the
+//! rest of the page, inline scripts included, is saved byte for byte. A page
+//! that isn't UTF-8 is handled as Latin-1, which maps every byte to a char and
+//! back, so its text is preserved whatever its real encoding.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::cli::PageArgs;
+use crate::features::save_to_local::{cli::LocalArgs, handler};
+use crate::shared::errors::CliantError;
+use crate::shared::html::{self, MAX_PAGE_BYTES};
+use crate::shared::network::{
+    DataTransport,
+    factory::{TransportType, handle_http},
+};
+use crate::shared::post_process::PostProcessArgs;
+use crate::shared::text_mode::TextModeArgs;
+use crate::shared::parts::MultipartArgs;
+use crate::shared::redact::{Redact, Redacted};
+use anyhow::Result;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use ring::digest::{SHA256, digest};
+use tokio_stream::StreamExt;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+/// File name of the saved page in the output directory.
+pub const PAGE_FILE_NAME: &str = "index.html";
+
+/// Characters escaped in the path segments of a rewritten reference.
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'\'').add(b'<').add(b'>').add(b'?').add(b'`');
+
+/// Saves the page at `args.url` and its assets into `args.output`.
+#[instrument(name = "handle_page", fields(args = %Redacted(&args.url)), skip(args))]
+pub async fn handle(args: PageArgs) -> Result<()> {
+    // Reject misconfigured request headers before any network I/O.
+    args.http_args.validate()?;
+    let transport = match args.transport {
+        TransportType::Http => handle_http(args.http_args.clone(), &TransportType::Http),
+    }?;
+    let page = fetch_page(&transport, &args.url).await?;
+
+    let requisites = html::requisites(&page);
+    let mut assets: HashMap<Url, Option<PathBuf>> = HashMap::new();
+    let mut replacements = Vec::new();
+    for requisite in &requisites {
+        let Ok(url) = args.url.join(&requisite.value) else { continue };
+        if !matches!(url.scheme(), "http" | "https") || (!args.span_hosts && url.origin() != args.url.origin()) {
+            continue;
+        }
+        if !assets.contains_key(&url) {
+            let saved = save_asset(&args, &url).await;
+            assets.insert(url.clone(), saved);
+        }
+        if let Some(Some(local)) = assets.get(&url) {
+            replacements.push((requisite.span.clone(), reference(local)));
+        }
+    }
+
+    tokio::fs::create_dir_all(&args.output).await?;
+    let saved_page = args.output.join(PAGE_FILE_NAME);
+    tokio::fs::write(&saved_page, page.encode(&rewrite(&page, replacements))).await?;
+    info!("Saved {} with {} assets in {}", Redacted(&args.url), assets.len(), args.output.display());
+
+    let failed = assets.values().filter(|saved| saved.is_none()).count();
+    if failed > 0 {
+        return Err(CliantError::PageIncomplete { url: args.url.redact(), failed, total: assets.len() }.into());
+    }
+    Ok(())
+}
+
+/// Downloads `url` to its place in the layout, `None` when it failed.
+async fn save_asset(args: &PageArgs, url: &Url) -> Option<PathBuf> {
+    let local = local_path(url);
+    let asset_args = LocalArgs {
+        url: url.clone(),
+        output: args.output.join(&local),
+        http_args: args.http_args.clone(),
+        transport: args.transport.clone(),
+        no_use_server_times: false,
+        save_metadata: false,
+        text_mode: TextModeArgs::default(),
+        verify_writes: None,
+        multipart: MultipartArgs::default(),
+        post_process: PostProcessArgs::default(),
+    };
+    match handler::handle(asset_args).await {
+        Ok(()) => Some(local),
+        Err(err) => {
+            warn!("Can't save asset {}, the page keeps referencing it remotely: {err}", Redacted(url));
+            None
+        }
+    }
+}
+
+/// The page's text along with how to turn it back into bytes.
+struct Page {
+    text: String,
+    latin1: bool,
+}
+
+impl Page {
+    fn encode(&self, text: &str) -> Vec<u8> {
+        if self.latin1 {
+            // Every char came from a single byte, rewritten references are ASCII.
+            text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+        } else {
+            text.as_bytes().to_vec()
+        }
+    }
+}
+
+impl std::ops::Deref for Page {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+async fn fetch_page(transport: &impl DataTransport, url: &Url) -> Result<Page, CliantError> {
+    let unexpected = |reason: String| CliantError::UnexpectedResponse { url: url.redact(), reason };
+    let (info, mut stream) = transport.receive_data(url.clone()).await?;
+    if !html::is_html(&info) {
+        return Err(unexpected(format!(
+            "it is not an HTML page (content type {}), use `cliant download` to save it",
+            info.content_type.as_deref().unwrap_or("unknown")
+        )));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() as u64 > MAX_PAGE_BYTES {
+            return Err(unexpected(format!("the page is over {} MiB", MAX_PAGE_BYTES / 1024 / 1024)));
+        }
+    }
+    Ok(match String::from_utf8(body) {
+        Ok(text) => Page { text, latin1: false },
+        Err(err) => Page { text: err.into_bytes().into_iter().map(char::from).collect(), latin1: true },
+    })
+}
+
+/// Where an asset is saved relative to the output directory:
+/// `<host>[_<port>]/<decoded path segments>`.
+///
+/// Segments that can't be file names are replaced, and a query string becomes
+/// a short hash before the extension (`app.js?v=3` → `app_<hash>.js`) so
+/// different versions of an asset don't overwrite each other.
+pub fn local_path(url: &Url) -> PathBuf {
+    let host = url.host_str().unwrap_or("localhost");
+    let mut host_dir: String =
+        host.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') { c } else { '_' }).collect();
+    if let Some(port) = url.port() {
+        host_dir.push_str(&format!("_{port}"));
+    }
+    let mut path = PathBuf::from(host_dir);
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| segments.map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    let (name, dirs) = segments.split_last().map_or(("", &[][..]), |(name, dirs)| (name.as_str(), dirs));
+    for dir in dirs.iter().filter(|dir| !dir.is_empty()) {
+        path.push(file_name(dir));
+    }
+    let mut name = if name.is_empty() { "index".to_string() } else { file_name(name) };
+    if let Some(query) = url.query() {
+        let hash: String = digest(&SHA256, query.as_bytes()).as_ref()[..4].iter().map(|byte| format!("{byte:02x}")).collect();
+        name = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => format!("{stem}_{hash}.{extension}"),
+            _ => format!("{name}_{hash}"),
+        };
+    }
+    path.push(name);
+    path
+}
+
+fn file_name(segment: &str) -> String {
+    match segment {
+        "." | ".." => "_".into(),
+        _ => segment.replace(['/', '\\', '\0'], "_"),
+    }
+}
+
+/// Reference to a saved asset from the page, a relative url.
+fn reference(local: &std::path::Path) -> String {
+    let segments: Vec<String> = local
+        .components()
+        .map(|component| utf8_percent_encode(&component.as_os_str().to_string_lossy(), SEGMENT).to_string())
+        .collect();
+    segments.join("/")
+}
+
+/// Replaces the `(span, value)` pairs of `text`, spans are in document order.
+fn rewrite(text: &str, replacements: Vec<(std::ops::Range<usize>, String)>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    for (span, value) in replacements {
+        out.push_str(&text[pos..span.start]);
+        out.push_str(&value);
+        pos = span.end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+    use async_tempfile::TempDir;
+    use clap::Parser;
+
+    const PAGE_FIXTURE: &str = r#"<!DOCTYPE html>
+<html><head>
+  <title>Guide</title>
+  <link rel="stylesheet" href="/static/site.css" media="screen">
+  <link rel="icon" href="/favicon.ico">
+  <script src="../static/app.js?v=3" defer></script>
+  <script>document.write('<img src="/inline.png">');</script>
+  <script src="{cross}/lib.js"></script>
+</head><body>
+  <!-- <img src="/commented.png"> -->
+  <img src=images/diagram.png alt="Flow: a > b" data-src="/lazy.png">
+  <img class="logo" src='/static/logo.svg'>
+  <a href="/docs/next.html">Next</a>
+</body></html>
+"#;
+
+    struct Fixture {
+        server: TestServer,
+        cross: TestServer,
+        page: String,
+    }
+
+    async fn fixture() -> Result<Fixture> {
+        let server = TestServer::start().await?;
+        let cross = TestServer::start().await?;
+        let page = PAGE_FIXTURE.replace("{cross}", cross.url("").as_str().trim_end_matches('/'));
+        let mut route = Route::ok(page.clone());
+        route.headers.push(("Content-Type".into(), "text/html; charset=utf-8".into()));
+        server.route("/docs/guide.html", route);
+        server.route("/static/site.css", Route::ok(test_body(100)));
+        server.route("/static/app.js?v=3", Route::ok(test_body(200)));
+        server.route("/docs/images/diagram.png", Route::ok(test_body(300)));
+        server.route("/static/logo.svg", Route::ok(test_body(400)));
+        cross.route("/lib.js", Route::ok(test_body(500)));
+        Ok(Fixture { server, cross, page })
+    }
+
+    fn page_args(url: &Url, output: &std::path::Path, flags: &[&str]) -> PageArgs {
+        let output = output.to_str().unwrap();
+        PageArgs::parse_from(["page", url.as_str(), "-o", output].iter().chain(flags))
+    }
+
+    #[tokio::test]
+    async fn test_saves_same_origin_assets_and_rewrites_references() -> Result<()> {
+        let Fixture { server, cross, page } = fixture().await?;
+        let dir = TempDir::new().await?;
+        handle(page_args(&server.url("/docs/guide.html"), dir.dir_path(), &[])).await?;
+
+        let host = format!("127.0.0.1_{}", server.url("/").port().unwrap());
+        assert_eq!(local_path(&server.url("/static/site.css")), PathBuf::from(&host).join("static/site.css"));
+        let saved = tokio::fs::read_to_string(dir.dir_path().join(PAGE_FILE_NAME)).await?;
+        for (asset, len) in
+            [("/static/site.css", 100), ("/static/app.js?v=3", 200), ("/docs/images/diagram.png", 300), ("/static/logo.svg", 400)]
+        {
+            let path = dir.dir_path().join(local_path(&server.url(asset)));
+            assert_eq!(tokio::fs::read(&path).await?, test_body(len), "{}", path.display());
+        }
+        // Every rewritten reference resolves to a saved file.
+        let references: Vec<String> = html::requisites(&saved).into_iter().map(|requisite| requisite.value).collect();
+        let cross_reference = format!("{}lib.js", cross.url(""));
+        assert_eq!(references.len(), 5);
+        for reference in references.iter().filter(|reference| **reference != cross_reference) {
+            assert!(reference.starts_with(&host), "{reference} was not rewritten");
+            let path = dir.dir_path().join(percent_decode_str(reference).decode_utf8()?.as_ref());
+            assert!(path.is_file(), "{reference} doesn't resolve to a saved file");
+        }
+        // Cross-origin assets are left alone by default.
+        assert!(references.contains(&cross_reference));
+        assert!(cross.requests().is_empty());
+        // Everything else is saved unchanged.
+        fn untouched(text: &str) -> Vec<&str> {
+            text.lines().filter(|line| !line.contains(" src") && !line.contains("stylesheet")).collect()
+        }
+        assert_eq!(untouched(&saved), untouched(&page));
+        for kept in [r#"<script>document.write('<img src="/inline.png">');</script>"#, r#"alt="Flow: a > b" data-src="/lazy.png">"#] {
+            assert!(saved.contains(kept), "{kept} is missing from the saved page");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_span_hosts_saves_cross_origin_assets() -> Result<()> {
+        let Fixture { server, cross, .. } = fixture().await?;
+        let dir = TempDir::new().await?;
+        handle(page_args(&server.url("/docs/guide.html"), dir.dir_path(), &["--span-hosts"])).await?;
+        let local = dir.dir_path().join(local_path(&cross.url("/lib.js")));
+        assert_eq!(tokio::fs::read(&local).await?, test_body(500));
+        let saved = tokio::fs::read_to_string(dir.dir_path().join(PAGE_FILE_NAME)).await?;
+        assert!(saved.contains(&format!("<script src=\"{}\"></script>", reference(&local_path(&cross.url("/lib.js"))))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_assets_keep_their_reference() -> Result<()> {
+        let Fixture { server, .. } = fixture().await?;
+        server.route("/static/logo.svg", Route::status(404));
+        let dir = TempDir::new().await?;
+        let err = handle(page_args(&server.url("/docs/guide.html"), dir.dir_path(), &[])).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(CliantError::PageIncomplete { failed: 1, total: 4, .. })), "{err}");
+        let saved = tokio::fs::read_to_string(dir.dir_path().join(PAGE_FILE_NAME)).await?;
+        assert!(saved.contains("src='/static/logo.svg'"));
+        assert!(!saved.contains("src=images/diagram.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_path() -> Result<()> {
+        let path = |url: &str| -> Result<String> { Ok(local_path(&Url::parse(url)?).to_string_lossy().into_owned()) };
+        assert_eq!(path("https://example.com/a/b/c.png")?, "example.com/a/b/c.png");
+        assert_eq!(path("http://example.com:8080/my%20logo.png")?, "example.com_8080/my logo.png");
+        assert_eq!(path("https://example.com/styles/")?, "example.com/styles/index");
+        assert_eq!(path("https://example.com/a%2F..%2Fb.css")?, "example.com/a_.._b.css");
+        let versioned = path("https://example.com/app.js?v=3")?;
+        assert!(versioned.starts_with("example.com/app_") && versioned.ends_with(".js"), "{versioned}");
+        assert_ne!(versioned, path("https://example.com/app.js?v=4")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latin1_pages_round_trip() {
+        let bytes = b"<p>caf\xe9</p><img src=\"a.png\">".to_vec();
+        let page = Page {
+            text: String::from_utf8(bytes.clone()).unwrap_err().into_bytes().into_iter().map(char::from).collect(),
+            latin1: true,
+        };
+        assert_eq!(page.encode(&page), bytes);
+        let span = html::requisites(&page)[0].span.clone();
+        assert_eq!(page.encode(&rewrite(&page, vec![(span, "h/a.png".into())])), b"<p>caf\xe9</p><img src=\"h/a.png\">");
+    }
+}
@@ -1,8 +1,15 @@
 use std::path::{Component, PathBuf};
 use url::Url;
 use path_clean::PathClean;
-use clap::{Parser,command,arg};
+use anyhow::Context;
+use clap::Parser;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+use crate::shared::sandbox::SandboxPaths;
 use crate::shared::network::{http::config::HttpArgs,factory::TransportType};
+use crate::shared::parts::MultipartArgs;
+use crate::shared::post_process::PostProcessArgs;
+use crate::shared::text_mode::TextModeArgs;
+use crate::shared::write_check::ReadBack;
 
 #[derive(Clone,Debug,Parser)]
 pub struct LocalArgs{
@@ -17,7 +24,59 @@ pub struct LocalArgs{
     ///Transport to use for send and receiving data. It can be http/https.
     #[arg(short='t',long,value_enum,default_value_t=TransportType::Http)]
     pub transport:TransportType,
+    ///Keep the local time as modification time instead of the server's Last-Modified.
+    #[arg(long)]
+    pub no_use_server_times:bool,
+    ///Save .metalink and .torrent files as they are, instead of downloading the file a metalink describes or refusing a torrent.
+    #[arg(long)]
+    pub save_metadata:bool,
+    #[command(flatten)]
+    pub text_mode:TextModeArgs,
+    ///Read the download back from disk once written and fail if it differs from what was written.
+    /// `direct` syncs it and drops it from the page cache first, so the storage itself is read (Linux).
+    #[arg(long,value_enum,value_name="MODE",num_args=0..=1,require_equals=true,default_missing_value="cached")]
+    pub verify_writes:Option<ReadBack>,
+    #[command(flatten)]
+    pub multipart:MultipartArgs,
+    #[command(flatten)]
+    pub post_process:PostProcessArgs,
+}
+impl LocalArgs{
+    ///Splits the output path into the file name and the directory it is written to.
+    ///
+    /// The file name is kept relative because opendal appends it to the root directory.
+    pub fn output_location(&self)->anyhow::Result<(PathBuf,PathBuf)>{
+        let file_name: PathBuf = self.output
+            .file_name()
+            .context(format!(
+                "Final component of {} is not a file",
+                self.output.display()
+            ))?
+            .into();
+        let file_parent_dir = self.output
+            .parent()
+            .context(format!(
+                "Can't determine parent directory of: {}",
+                self.output.display()
+            ))?
+            .to_path_buf();
+        Ok((file_name,file_parent_dir))
+    }
+
+    ///Paths a sandboxed download needs, derived from the same resolution the download uses.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    pub fn sandbox_paths(&self)->anyhow::Result<SandboxPaths>{
+        let (_,file_parent_dir)=self.output_location()?;
+        // An output path without directory is written to the working directory.
+        let output_dir = if file_parent_dir.as_os_str().is_empty() { PathBuf::from(".") } else { file_parent_dir };
+        let mut paths = SandboxPaths{ writable: vec![output_dir], readable: vec![] };
+        paths.writable.extend(self.http_args.record.clone());
+        paths.readable.extend(self.http_args.replay.clone());
+        paths.readable.extend(self.http_args.proxy_cacert.clone());
+        Ok(paths)
+    }
 }
+
 ///Perform path validation with this function,if path is a dir,
 /// this function will throw an Err,else it will return a string.
 /// 
@@ -40,12 +99,22 @@ fn parse_output_path(path:&str)->Result<PathBuf,String>{
 ///This method takes a url as a string literal,checks and validate http
 /// scheme in the url,parses it and return a Result Url or String
 /// type if any error occur.
-fn parse_url(url: &str) -> Result<Url, String> {
-    if url.starts_with("https://") || url.starts_with("http://") {
-        let parsed_url =
-            Url::parse(url).map_err(|e| format!("Invalid Url {url} {e}"));
-        return parsed_url;
+///
+/// A url without scheme defaults to https, other schemes must be served by a
+/// registered transport (see `TransportType::supported_schemes`).
+pub(crate) fn parse_url(url: &str) -> Result<Url, String> {
+    if !url.contains("://") {
+        let new_url = format!("https://{url}");
+        return Url::parse(&new_url).map_err(|e| format!("Invalid Url {url} {e}"));
     }
-    let new_url = format!("https://{url}");
-    Url::parse(&new_url).map_err(|e| format!("Invalid Url {url} {e}"))
-}
\ No newline at end of file
+    let parsed_url = Url::parse(url).map_err(|e| format!("Invalid Url {url} {e}"))?;
+    let supported = TransportType::supported_schemes();
+    if !supported.contains(&parsed_url.scheme()) {
+        return Err(format!(
+            "Unsupported url scheme {}, supported schemes are: {}",
+            parsed_url.scheme(),
+            supported.join(", ")
+        ));
+    }
+    Ok(parsed_url)
+}
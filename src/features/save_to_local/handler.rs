@@ -27,19 +27,29 @@
 //! - All resources are cleaned up even on error paths
 //! - Progress tracker finalization always occurs for proper UI state
 
-use std::path::PathBuf;
 
 use super::cli::LocalArgs;
+use crate::shared::checksum::{Checksum, ChecksumVerifier};
 use crate::shared::fs::FsOps;
+use crate::shared::html;
+use crate::shared::metalink::{self, MetadataKind};
+use crate::shared::output;
+use crate::shared::pacing::ChunkPacer;
+use crate::shared::parts::{Part, PlanInputs};
+use crate::shared::post_process::Download;
 use crate::shared::fs::local::LocalFsBuilder;
 use crate::shared::network::{
-    DataTransport,
+    ByteRange, DataTransport, DownloadInfo,
     factory::{TransportType, handle_http},
 };
-use crate::shared::progress_tracker::{CliProgressTracker, ProgressTracker};
+use crate::shared::progress_tracker::{CliProgressTracker, Phase, ProgressTracker};
+use crate::shared::write_check::WriteRecorder;
 use anyhow::{Context, Result};
-use tokio_stream::StreamExt;
-use tracing::{debug, error, info, instrument, trace};
+use bytes::Bytes;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, error, info, instrument, trace, warn};
+use crate::shared::errors::CliantError;
+use crate::shared::redact::{Redact, Redacted};
 use tokio::time;
 /// Downloads a file from an HTTP(S) URL and saves it to the local filesystem.
 ///
@@ -54,6 +64,7 @@ use tokio::time;
 ///   - `output`: The local filesystem path where the file will be saved
 ///   - `http_args`: HTTP-specific configuration (timeout, auth, headers, etc.)
 ///   - `transport`: The transport protocol to use (currently HTTP only)
+///   - `post_process`: Command to run once the download finished
 ///
 /// # Process
 ///
@@ -64,7 +75,8 @@ use tokio::time;
 /// 5. Streams data chunks from the remote source
 /// 6. Writes each chunk to disk and updates progress
 /// 7. Ensures proper cleanup of filesystem resources via RAII
-/// 8. Displays completion information
+/// 8. Runs the `--post-process` command, if any
+/// 9. Displays completion information
 ///
 /// # Errors
 ///
@@ -83,39 +95,125 @@ use tokio::time;
 /// - `builder.close_fs()` explicitly flushes and closes the file handle
 /// - Progress tracker is always finalized, even if errors occur
 /// - On error paths, `close_fs()` is still called to ensure cleanup
-#[instrument(name = "handle_http_download", fields(args = %args.url), skip(args))]
+#[instrument(name = "handle_http_download", fields(args = %Redacted(&args.url)), skip(args))]
 pub async fn handle(args: LocalArgs) -> Result<()> {
+    let post_process = args.post_process.clone();
+    let (path, url) = (args.output.clone(), args.url.clone());
+    let result = download(args).await;
+    let finished = Download { path: &path, url: &url, succeeded: result.is_ok() };
+    let post_processed = post_process.run(&finished).await;
+    // The download's own error is the one worth reporting.
+    result?;
+    Ok(post_processed?)
+}
+
+/// What fetching a url produced.
+enum Fetched {
+    Saved,
+    /// The url is a metalink, followed instead of saved.
+    Metalink(String),
+}
+
+/// What a metalink tells about the file downloaded from one of its mirrors.
+struct Described {
+    checksum: Option<Checksum>,
+    size: Option<u64>,
+}
+
+/// The download itself, steps 1 to 7 above.
+async fn download(args: LocalArgs) -> Result<()> {
+    let url = args.url.clone();
+    match fetch(args.clone(), None).await? {
+        Fetched::Saved => Ok(()),
+        Fetched::Metalink(document) => follow_metalink(args, &url, &document).await,
+    }
+}
+
+/// Downloads the file a metalink describes, trying its mirrors preferred first.
+async fn follow_metalink(args: LocalArgs, metalink_url: &url::Url, document: &str) -> Result<()> {
+    let error = |reason: String| CliantError::Metalink { url: metalink_url.redact(), reason };
+    let files = metalink::parse(document).map_err(error)?;
+    let file = metalink::choose(&files, &args.output).map_err(error)?;
+    if file.urls.is_empty() {
+        return Err(error(format!("it lists no http(s) mirror of {}", file.name)).into());
+    }
+    info!(
+        "{} is a metalink, downloading {} from its {} mirror(s)",
+        Redacted(metalink_url),
+        file.name,
+        file.urls.len()
+    );
+    let described = Described { checksum: file.checksum.clone(), size: file.size };
+    let mut last_error = None;
+    for mirror in &file.urls {
+        let mirror_args = LocalArgs { url: mirror.clone(), ..args.clone() };
+        match fetch(mirror_args, Some(&described)).await {
+            Ok(_) => return Ok(()),
+            Err(err) => {
+                warn!("Mirror {} failed: {err:#}", Redacted(mirror));
+                last_error = Some(err);
+            }
+        }
+    }
+    let last_error = last_error.expect("the metalink lists mirrors");
+    Err(last_error.context(format!("Every mirror of the metalink {} failed", Redacted(metalink_url))))
+}
+
+/// Downloads `args.url`, or only reads it when it is a metalink to follow.
+///
+/// `described` is set when `args.url` is a metalink's mirror, the metalink's
+/// hash and size then take precedence over what the server announces.
+async fn fetch(args: LocalArgs, described: Option<&Described>) -> Result<Fetched> {
+    // Using file_name (not full path) because opendal appends path to root directory
+    let (file_name, file_parent_dir) = args.output_location()?;
     let file_path = args.output;
     let url = args.url;
     let http_args = args.http_args;
-
-    // Extract file name from path
-    // Using file_name (not full path) because opendal appends path to root directory
-    let file_name: PathBuf = file_path
-        .file_name()
-        .context(format!(
-            "Final component of {} is not a file",
-            file_path.display()
-        ))?
-        .into();
-
-    // Get parent directory for file storage
-    let file_parent_dir = file_path
-        .parent()
-        .context(format!(
-            "Can't determine parent directory of: {}",
-            file_path.display()
-        ))?
-        .to_path_buf();
+    let requested_http2 = http_args.http_version.as_deref().is_some_and(|version| version.starts_with('2'));
+    let uses_proxy = http_args.proxy_url.is_some();
+    let no_head = http_args.no_head;
+    let max_size_discrepancy = http_args.max_size_discrepancy;
+    let allow_html = http_args.allow_html;
+    let save_metadata = args.save_metadata;
+    let text_mode = args.text_mode;
+    let verify_writes = args.verify_writes;
+    let multipart = args.multipart;
 
     debug!("File path: {:?}", file_path);
     debug!("File parent directory: {:?}", file_parent_dir);
 
+    // Reject misconfigured request headers before any network I/O.
+    http_args.validate()?;
+
     // Initialize transport layer
     let transport = match args.transport {
         TransportType::Http => handle_http(http_args, &TransportType::Http),
     }?;
 
+    let mut stream_result =
+        transport.receive_data(url.clone()).await.map(|(get_info, stream)| (get_info, stream.peekable()));
+    // Checked before the file is created, so a refused page leaves nothing behind.
+    if let Ok((get_info, stream)) = &mut stream_result
+        && !allow_html
+        && html::looks_like_page(get_info)
+    {
+        return Err(html_page_error(&url, stream).await?.into());
+    }
+    if let Ok((get_info, stream)) = &mut stream_result
+        && described.is_none()
+        && !save_metadata
+    {
+        let head = match stream.peek().await {
+            Some(Ok(bytes)) => bytes.clone(),
+            _ => Bytes::new(),
+        };
+        match metalink::metadata_kind(&url, get_info, &head) {
+            Some(MetadataKind::Torrent) => return Err(CliantError::Torrent { url: url.redact() }.into()),
+            Some(MetadataKind::Metalink) => return Ok(Fetched::Metalink(read_metalink(&url, stream).await?)),
+            None => {}
+        }
+    }
+
     // Create local filesystem writer with proper resource management
     let fs_writer = LocalFsBuilder::new()
         .file_name(file_name)
@@ -124,67 +222,385 @@ pub async fn handle(args: LocalArgs) -> Result<()> {
         .await?;
 
     // Retrieve remote file metadata and initialize tracking
-    let stream_result = transport.receive_data(url.clone()).await;
-    let total_bytes = transport.total_bytes(url.clone()).await?;
-    let tracker = CliProgressTracker::new(total_bytes, file_path.clone())?;
+    let mut download_info = match &stream_result {
+        // The GET response already describes the resource, save a round trip.
+        Ok((get_info, _)) if no_head => get_info.clone(),
+        // The HEAD only adds to a GET that succeeded: servers answering it with
+        // 405, or presigned urls signed for GET only, still serve the file.
+        Ok((get_info, _)) => match transport.download_info(url.clone()).await {
+            Ok(head_info) => head_info,
+            Err(err) => {
+                warn!("HEAD request to {} failed, using the GET response's headers: {err}", Redacted(&url));
+                get_info.clone()
+            }
+        },
+        // The GET's error is reported below.
+        Err(_) => DownloadInfo::default(),
+    };
+    if let Ok((get_info, _)) = &stream_result {
+        download_info.total_bytes = reconcile_total_bytes(
+            &url,
+            download_info.total_bytes,
+            get_info.total_bytes,
+            max_size_discrepancy,
+        )?;
+    }
+    if let Some(described) = described {
+        // Any difference to the metalink's size means this mirror has another file.
+        download_info.total_bytes = reconcile_total_bytes(&url, described.size, download_info.total_bytes, 1.0)?;
+    }
+    let tracker = CliProgressTracker::new(download_info.total_bytes, file_path.clone())?;
 
     // Stream and write data with proper error handling and cleanup
     // RAII ensures fs_writer is cleaned up even if errors occur
     match stream_result {
-        Ok(mut stream) => {
+        Ok((get_info, mut stream)) => {
             info!("Starting download stream...");
+            let mut converter = text_mode.converter(&get_info);
+            // The digest describes the body of this response, not the HEAD's.
+            let checksum = described.and_then(|described| described.checksum.clone()).or(get_info.checksum);
+            let mut verifier = checksum.map(ChecksumVerifier::new);
+            let mut recorder = verify_writes.map(WriteRecorder::new);
+            let (parts, explanations) = multipart.decide(&PlanInputs {
+                total: download_info.total_bytes,
+                transport_ranges: transport.supports_ranges(),
+                write_at: fs_writer.supports_write_at(),
+                accepts_ranges: get_info.accepts_ranges,
+                content_encoding: get_info.content_encoding.clone(),
+                converts_text: converter.is_some(),
+                verifies_writes: recorder.is_some(),
+            });
+            for explanation in &explanations {
+                debug!("Plan: {explanation}");
+            }
+            if multipart.explain_plan {
+                output::stderr(format_args!("Download plan for {}:\n", Redacted(&url)));
+                for explanation in &explanations {
+                    output::stderr(format_args!("  {explanation}\n"));
+                }
+            }
+            tracker.start().await;
             let instant = time::Instant::now();
-            while let Some(bytes) = stream.try_next().await? {
-                let bytes_size = bytes.len();
-                tracker.update(bytes_size).await; // call the update function before append_bytes to reflect actual network speed.
-                trace!(
-                    "Writing {} bytes to {:?}",
-                    bytes_size,
-                    file_path
-                );
-                fs_writer.append_bytes(bytes).await?; // If tracker.update was called here it will reflect file system write speed. 
-                
+            let in_parts = parts.is_some();
+            if let Some(parts) = parts {
+                info!("Downloading {} in {} parts of up to {} bytes", Redacted(&url), parts.len(), parts[0].size());
+                download_parts(&transport, &fs_writer, &tracker, &url, stream, &parts, multipart.max_concurrent_parts).await?;
+            } else {
+                let mut pacer = ChunkPacer::default();
+                while let Some(bytes) = stream.try_next().await? {
+                    // Let other downloads in this runtime make progress.
+                    pacer.chunk().await;
+                    let bytes_size = bytes.len();
+                    if let Some(verifier) = verifier.as_mut() {
+                        verifier.update(&bytes);
+                    }
+                    tracker.update(bytes_size).await; // call the update function before append_bytes to reflect actual network speed.
+                    trace!(
+                        "Writing {} bytes to {:?}",
+                        bytes_size,
+                        file_path
+                    );
+                    let bytes = match converter.as_mut() {
+                        Some(converter) => converter.push(&bytes),
+                        None => bytes,
+                    };
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record(&bytes);
+                    }
+                    fs_writer.append_bytes(bytes).await?; // If tracker.update was called here it will reflect file system write speed. 
+
+                }
+                if let Some(converter) = converter.as_mut() {
+                    let rest = converter.finish();
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record(&rest);
+                    }
+                    fs_writer.append_bytes(rest).await?;
+                    info!("{}", converter.report());
+                }
             }
             let elapsed =instant.elapsed();
             info!("Download streaming completed, file fully downloaded in {} secs or {}ms .",elapsed.as_secs(),elapsed.as_millis());
             // Explicit resource cleanup: flush buffers and close file handle
-            fs_writer.close_fs().await;
+            fs_writer.close_fs().await?;
+            if let Some(recorder) = recorder {
+                let progress = tracker.phase(Phase::ReadingBack, download_info.total_bytes).await;
+                let verified = recorder.verify(file_path.clone(), progress).await?;
+                info!(
+                    "Read {} bytes back from {:?} in {}ms, identical to what was written",
+                    verified.bytes,
+                    file_path,
+                    verified.elapsed.as_millis()
+                );
+                tracker.writes_verified(&verified);
+            }
+            if let Some(verifier) = verifier {
+                let checksum = if in_parts {
+                    let progress = tracker.phase(Phase::Verifying, download_info.total_bytes).await;
+                    verifier.finish_file(file_path.clone(), progress).await?
+                } else {
+                    verifier.finish()?
+                };
+                info!("Verified {} checksum from {}", checksum.algorithm.name(), checksum.origin);
+                tracker.checksum_verified(&checksum);
+            }
+            // Like wget, the file carries the origin's modification time.
+            match download_info.last_modified {
+                Some(last_modified) if !args.no_use_server_times => {
+                    fs_writer.set_mtime(last_modified).await?;
+                }
+                _ => debug!("Keeping local modification time of {:?}", file_path),
+            }
         }
 
         Err(err) => {
-            error!("Failed to stream data from {}: {}", url, err);
-            // Ensure cleanup even on error - critical for resource management
-            fs_writer.close_fs().await;
-            return Err(err).context(format!("Failed to download from {url}"));
+            error!("Failed to stream data from {}: {}", Redacted(&url), err);
+            // Ensure cleanup even on error - critical for resource management,
+            // the download's error is the one reported.
+            if let Err(close_err) = fs_writer.close_fs().await {
+                warn!("Can't close {:?} after the failed download: {close_err}", file_path);
+            }
+            return Err(err).context(format!("Failed to download from {}", Redacted(&url)));
         }
     }
 
     // Finalize progress tracker and display completion info
     tracker.finish().await;
 
+    let stats = transport.connection_stats();
+    info!(
+        http_version_counts = ?stats.http_version_counts,
+        estimated_connections = stats.estimated_connections,
+        "Connection usage"
+    );
+    let writer_stats = fs_writer.writer_stats();
+    debug!(
+        writes = writer_stats.writes,
+        bytes = writer_stats.bytes,
+        write_sizes = ?writer_stats.write_sizes,
+        "{writer_stats}"
+    );
+    if requested_http2 && stats.only_http1() {
+        warn!(
+            "--http-version 2 was requested but every response used HTTP/1.1, {}",
+            if uses_proxy {
+                "probably because the proxy in the path only speaks HTTP/1.1"
+            } else {
+                "probably because the server didn't offer HTTP/2 through TLS ALPN (or the url is plain http://)"
+            }
+        );
+    }
+
+    Ok(Fetched::Saved)
+}
+
+/// Downloads `parts` concurrently and writes each at its offset.
+///
+/// `whole` is the stream of the initial GET, which carries the first part: it is
+/// read up to the end of that part and dropped, closing its connection before
+/// its reader fetches more of what the other parts download.
+async fn download_parts(
+    transport: &impl DataTransport,
+    fs_writer: &impl FsOps,
+    tracker: &impl ProgressTracker,
+    url: &url::Url,
+    mut whole: impl Stream<Item = Result<Bytes, CliantError>> + Unpin,
+    parts: &[Part],
+    max_concurrent_parts: usize,
+) -> Result<(), CliantError> {
+    let Some((first, rest)) = parts.split_first() else {
+        return Ok(());
+    };
+    use futures::{StreamExt as _, TryStreamExt as _};
+
+    let first = async move {
+        write_part(fs_writer, tracker, url, *first, &mut whole).await?;
+        drop(whole);
+        Ok::<_, CliantError>(())
+    };
+    let fetch_rest = |limit| futures::stream::iter(rest.iter().map(|&part| async move {
+            let range = ByteRange::Span { first: part.first, last: part.last };
+            let (info, mut stream) = transport.receive_range(url.clone(), range).await?;
+            check_part_response(url, part, &info)?;
+            write_part(fs_writer, tracker, url, part, &mut stream).await
+        }))
+        .buffer_unordered(limit)
+        .try_collect::<Vec<()>>();
+    // The first part holds one of the connections, alone it holds the only one.
+    if max_concurrent_parts <= 1 {
+        first.await?;
+        fetch_rest(1).await?;
+    } else {
+        tokio::try_join!(first, fetch_rest(max_concurrent_parts - 1))?;
+    }
     Ok(())
 }
 
+/// Fails unless `info` describes exactly the bytes of `part`, which are
+/// written at its offset whatever the response holds.
+fn check_part_response(url: &url::Url, part: Part, info: &DownloadInfo) -> Result<(), CliantError> {
+    let answered = match info.content_range {
+        _ if !info.partial => "the whole file".to_string(),
+        None => "a 206 without a valid Content-Range".to_string(),
+        Some(range) if (range.first, range.last) != (part.first, part.last) => {
+            format!("bytes {}-{}", range.first, range.last)
+        }
+        // A chunked 206 has no Content-Length, its Content-Range is enough.
+        Some(_) => match info.total_bytes {
+            Some(length) if length != part.size() => format!("a body of {length} bytes"),
+            _ => return Ok(()),
+        },
+    };
+    Err(CliantError::UnexpectedResponse {
+        url: url.redact(),
+        reason: format!("asked for bytes {}-{}, the server answered with {answered}", part.first, part.last),
+    })
+}
+
+/// Writes the bytes of `part` read from `stream`, ignoring what follows them.
+async fn write_part(
+    fs_writer: &impl FsOps,
+    tracker: &impl ProgressTracker,
+    url: &url::Url,
+    part: Part,
+    stream: &mut (impl Stream<Item = Result<Bytes, CliantError>> + Unpin),
+) -> Result<(), CliantError> {
+    let end = part.last + 1;
+    let mut offset = part.first;
+    let mut pacer = ChunkPacer::default();
+    while offset < end {
+        let Some(mut bytes) = stream.try_next().await? else {
+            return Err(CliantError::UnexpectedResponse {
+                url: url.redact(),
+                reason: format!("the body of bytes {}-{} ended at byte {offset}", part.first, part.last),
+            });
+        };
+        pacer.chunk().await;
+        bytes.truncate((end - offset).try_into().unwrap_or(usize::MAX));
+        let len = bytes.len() as u64;
+        tracker.update(bytes.len()).await;
+        fs_writer.write_at(offset, bytes).await?;
+        offset += len;
+    }
+    trace!("Wrote bytes {}-{}", part.first, part.last);
+    Ok(())
+}
+
+/// Reads the metalink document `stream` carries.
+async fn read_metalink(
+    url: &url::Url,
+    stream: &mut (impl Stream<Item = Result<Bytes, CliantError>> + Unpin),
+) -> Result<String, CliantError> {
+    let error = |reason: String| CliantError::Metalink { url: url.redact(), reason };
+    let mut document = Vec::new();
+    while let Some(bytes) = stream.try_next().await? {
+        document.extend_from_slice(&bytes);
+        if document.len() > metalink::MAX_METALINK_BYTES {
+            return Err(error(format!("it is larger than {} MiB", metalink::MAX_METALINK_BYTES / 1024 / 1024)));
+        }
+    }
+    String::from_utf8(document).map_err(|_| error("it is not UTF-8 text".into()))
+}
+
+/// Describes the HTML page being downloaded from its first few KB: its title
+/// and the links in it that look like the file the user was after.
+async fn html_page_error(
+    url: &url::Url,
+    stream: &mut (impl Stream<Item = Result<Bytes, CliantError>> + Unpin),
+) -> Result<CliantError, CliantError> {
+    let mut page = Vec::new();
+    while page.len() < html::SNIFF_BYTES {
+        match stream.try_next().await? {
+            Some(bytes) => page.extend_from_slice(&bytes),
+            None => break,
+        }
+    }
+    let page = String::from_utf8_lossy(&page);
+    Ok(CliantError::HtmlPage {
+        url: url.redact(),
+        title: html::page_title(&page),
+        candidates: html::candidate_downloads(&page, url).iter().map(Redact::redact).collect(),
+    })
+}
+
+/// Checks the size announced before the download against the one of the response
+/// actually being written, e.g a cache tier answering HEAD with a stale length.
+///
+/// The response's size wins when the two are within `max_factor` of each other,
+/// otherwise the download is aborted.
+fn reconcile_total_bytes(
+    url: &url::Url,
+    planned: Option<u64>,
+    actual: Option<u64>,
+    max_factor: f64,
+) -> Result<Option<u64>, CliantError> {
+    let (Some(planned), Some(actual)) = (planned, actual) else {
+        return Ok(actual.or(planned));
+    };
+    if planned == actual {
+        return Ok(Some(actual));
+    }
+    let (low, high) = (planned.min(actual), planned.max(actual));
+    #[allow(clippy::cast_precision_loss)] // A rough ratio is enough.
+    let within_factor = low > 0 && high as f64 <= low as f64 * max_factor;
+    if !within_factor {
+        return Err(CliantError::InconsistentSize { url: url.redact(), planned, actual });
+    }
+    warn!(
+        planned,
+        actual,
+        "The server reported {planned} bytes before the download but the download response has {actual} bytes, using {actual}"
+    );
+    Ok(Some(actual))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shared::network::http::config::HttpArgs;
+    use std::path::PathBuf;
+    use crate::shared::network::http::config::{HttpArgs, RetryArgs};
+    use crate::shared::post_process::PostProcessArgs;
+    use crate::shared::text_mode::TextModeArgs;
+    use crate::shared::parts::MultipartArgs;
+    use crate::shared::network::ContentRange;
+    use crate::shared::test_server::{Route, TestServer, test_body};
     use tokio::fs;
     use async_tempfile::TempDir;
 
+    const TEST_FILE_SIZE: usize = 256 * 1024;
+
+    /// Starts a local server serving a `TEST_FILE_SIZE` file at `/1MB.zip`.
+    async fn serve_test_file() -> anyhow::Result<TestServer> {
+        let server = TestServer::start().await?;
+        server.route("/1MB.zip", Route::ok(test_body(TEST_FILE_SIZE)));
+        Ok(server)
+    }
+
+    /// Arguments of a plain download of `url` into `output`, tests override the fields they exercise.
+    fn local_args(url: url::Url, output: PathBuf) -> LocalArgs {
+        LocalArgs {
+            url,
+            http_args: HttpArgs::default(),
+            output,
+            transport: TransportType::Http,
+            no_use_server_times: false,
+            save_metadata: false,
+            text_mode: TextModeArgs::default(),
+            verify_writes: None,
+            multipart: MultipartArgs::default(),
+            post_process: PostProcessArgs::default(),
+        }
+    }
+
     /// Test downloading a file to a valid path
     #[tokio::test]
     async fn test_handle_valid_output_path() -> anyhow::Result<()> {
         let temp_dir = TempDir::new().await?;
         let output_path = temp_dir.dir_path().join("test_file.bin");
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: output_path.clone(),
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, output_path.clone());
 
         let result = handle(args).await;
         assert!(result.is_ok(), "Download should succeed");
@@ -196,6 +612,18 @@ mod tests {
         // Verify file has content
         let file_size = fs::metadata(&output_path).await?.len();
         assert!(file_size > 0, "Downloaded file should not be empty");
+        assert_eq!(
+            fs::read(&output_path).await?,
+            test_body(TEST_FILE_SIZE),
+            "Downloaded file should match the served content"
+        );
+        assert!(
+            server
+                .requests()
+                .iter()
+                .any(|req| req.method == "GET" && req.path == "/1MB.zip"),
+            "The file should have been fetched from the local server"
+        );
 
         Ok(())
     }
@@ -205,12 +633,7 @@ mod tests {
     async fn test_handle_invalid_path_no_filename() -> anyhow::Result<()> {
         let link = url::Url::parse("http://example.com/file.zip")?;
         // Root path has no file name
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: PathBuf::from("/"),
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, PathBuf::from("/"));
 
         let result = handle(args).await;
         assert!(
@@ -237,14 +660,10 @@ mod tests {
         // Create parent directory if needed
         fs::create_dir_all(output_path.parent().unwrap()).await?;
 
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: output_path.clone(),
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, output_path.clone());
 
         let result = handle(args).await;
         if result.is_ok() {
@@ -266,10 +685,11 @@ mod tests {
             url::Url::parse("http://invalid-nonexistent-domain-12345.local/file.zip")?;
 
         let args = LocalArgs {
-            url: invalid_link,
-            http_args: HttpArgs::default(),
-            output: output_path.clone(),
-            transport: TransportType::Http,
+            http_args: HttpArgs {
+                retry_args: RetryArgs::new(0, 0), // Name resolution won't recover, don't wait on backoff.
+                ..HttpArgs::default()
+            },
+            ..local_args(invalid_link, output_path.clone())
         };
 
         let result = handle(args).await;
@@ -287,21 +707,121 @@ mod tests {
         Ok(())
     }
 
+    /// Test that a user supplied Range header is rejected before any download starts
+    #[tokio::test]
+    async fn test_handle_rejects_user_range_header() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let output_path = temp_dir.dir_path().join("range_test.bin");
+        let link = url::Url::parse("http://example.com/file.zip")?;
+
+        let args = LocalArgs {
+            http_args: HttpArgs {
+                request_headers: Some("Range: bytes=0-100".into()),
+                ..HttpArgs::default()
+            },
+            ..local_args(link, output_path.clone())
+        };
+
+        let result = handle(args).await;
+        let err = result.expect_err("A user Range header must be rejected");
+        assert!(err.to_string().contains("'range'"), "Error should name the header: {err}");
+        assert!(!output_path.exists(), "No file should be created for a rejected request");
+
+        Ok(())
+    }
+
+    /// Test that a recorded session replays into a byte-identical file without the server
+    #[tokio::test]
+    async fn test_record_then_replay_offline() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let record_dir = temp_dir.dir_path().join("recording");
+        let recorded_path = temp_dir.dir_path().join("recorded.bin");
+        let replayed_path = temp_dir.dir_path().join("replayed.bin");
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
+
+        handle(LocalArgs {
+            http_args: HttpArgs {
+                username: Some("user".into()),
+                password: Some("hunter2-secret".into()),
+                record: Some(record_dir.clone()),
+                ..HttpArgs::default()
+            },
+            ..local_args(link.clone(), recorded_path.clone())
+        })
+        .await?;
+        drop(server); // Nothing is listening anymore, replay must not need the network.
+
+        handle(LocalArgs {
+            http_args: HttpArgs {
+                replay: Some(record_dir.clone()),
+                retry_args: RetryArgs::new(0, 0),
+                ..HttpArgs::default()
+            },
+            ..local_args(link, replayed_path.clone())
+        })
+        .await?;
+
+        assert_eq!(fs::read(&replayed_path).await?, fs::read(&recorded_path).await?);
+        assert_eq!(fs::read(&replayed_path).await?, test_body(TEST_FILE_SIZE));
+        let index = fs::read_to_string(record_dir.join("index.json")).await?;
+        assert!(!index.contains("dXNlcjpodW50ZXIyLXNlY3JldA"), "Basic auth credentials must be redacted");
+        assert!(!index.contains("hunter2-secret"));
+
+        Ok(())
+    }
+
+    /// Test that replaying a tampered recording fails with a descriptive error
+    #[tokio::test]
+    async fn test_replay_tampered_recording() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let record_dir = temp_dir.dir_path().join("recording");
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
+
+        handle(LocalArgs {
+            http_args: HttpArgs { record: Some(record_dir.clone()), ..HttpArgs::default() },
+            ..local_args(link.clone(), temp_dir.dir_path().join("recorded.bin"))
+        })
+        .await?;
+        let index_path = record_dir.join("index.json");
+        let index = fs::read_to_string(&index_path).await?;
+        fs::write(&index_path, index.replace("/1MB.zip", "/other.zip")).await?;
+
+        let result = handle(LocalArgs {
+            http_args: HttpArgs {
+                replay: Some(record_dir),
+                retry_args: RetryArgs::new(0, 0),
+                ..HttpArgs::default()
+            },
+            ..local_args(link, temp_dir.dir_path().join("replayed.bin"))
+        })
+        .await;
+        let err = result.expect_err("Replay of a tampered recording must fail");
+        assert!(
+            format!("{err:#}").contains("is not present in the recording"),
+            "Error should describe the mismatch: {err:#}"
+        );
+
+        Ok(())
+    }
+
     /// Test with custom HTTP timeout configuration
     #[tokio::test]
     async fn test_handle_with_timeout_config() -> anyhow::Result<()> {
         let temp_dir = TempDir::new().await?;
         let output_path = temp_dir.dir_path().join("timeout_test.bin");
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let mut http_args = HttpArgs::default();
-        http_args.timeout = 30; // Custom timeout
+        let http_args = HttpArgs {
+            timeout: 30, // Custom timeout
+            ..HttpArgs::default()
+        };
 
         let args = LocalArgs {
-            url: link,
             http_args,
-            output: output_path.clone(),
-            transport: TransportType::Http,
+            ..local_args(link, output_path.clone())
         };
 
         let result = handle(args).await;
@@ -320,12 +840,7 @@ mod tests {
         // Create a path that ends at root level
         let bad_path = std::path::PathBuf::from("/");
 
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: bad_path,
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, bad_path);
 
         let result = handle(args).await;
         assert!(
@@ -341,14 +856,10 @@ mod tests {
     async fn test_handle_progress_tracking() -> anyhow::Result<()> {
         let temp_dir = TempDir::new().await?;
         let output_path = temp_dir.dir_path().join("progress_test.bin");
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: output_path.clone(),
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, output_path.clone());
 
         // This tests that progress tracker is properly initialized and finalized
         let result = handle(args).await;
@@ -358,6 +869,278 @@ mod tests {
         Ok(())
     }
 
+    /// `--no-head` saves the HEAD round trip, the file is the same.
+    #[tokio::test]
+    async fn test_handle_no_head_sends_one_request() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        for no_head in [false, true] {
+            let server = serve_test_file().await?;
+            let output_path = temp_dir.dir_path().join(format!("no_head_{no_head}.bin"));
+            let args = LocalArgs {
+                http_args: HttpArgs { no_head, ..HttpArgs::default() },
+                ..local_args(server.url("/1MB.zip"), output_path.clone())
+            };
+            handle(args).await?;
+            assert_eq!(fs::read(&output_path).await?, test_body(TEST_FILE_SIZE));
+            let methods: Vec<String> = server.requests().into_iter().map(|request| request.method).collect();
+            if no_head {
+                assert_eq!(methods, ["GET"]);
+            } else {
+                assert_eq!(methods.len(), 2);
+                assert!(methods.contains(&"HEAD".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The HEAD carries the credentials the GET does.
+    #[tokio::test]
+    async fn test_handle_authenticates_head_and_get() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(4096);
+        server.route("/private.bin", Route::ok(body.clone()).with_basic_auth("alice", "hunter2"));
+        let output = temp_dir.dir_path().join("private.bin");
+        let mut args = local_args(server.url("/private.bin"), output.clone());
+        args.http_args = HttpArgs {
+            username: Some("alice".into()),
+            password: Some("hunter2".into()),
+            retry_args: RetryArgs::new(0, 0),
+            ..HttpArgs::default()
+        };
+        handle(args).await?;
+        assert_eq!(fs::read(&output).await?, body);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.headers.iter().any(|(name, _)| name == "authorization")));
+        Ok(())
+    }
+
+    /// Servers rejecting HEAD (405) and presigned GET-only urls (403) still serve the file.
+    #[tokio::test]
+    async fn test_handle_survives_rejected_head() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(4096);
+        for (path, status) in [("/no-head.bin", 405), ("/presigned.bin", 403)] {
+            server.route(path, Route::ok(body.clone()));
+            server.route(&format!("HEAD {path}"), Route::status(status));
+            let output = temp_dir.dir_path().join(path.trim_start_matches('/'));
+            let mut args = local_args(server.url(path), output.clone());
+            args.http_args.retry_args = RetryArgs::new(0, 0);
+            handle(args).await?;
+            assert_eq!(fs::read(&output).await?, body, "{path}");
+        }
+        Ok(())
+    }
+
+    /// A server answering `Want-Repr-Digest` gets its digest checked against the file.
+    #[tokio::test]
+    async fn test_handle_verifies_repr_digest() -> anyhow::Result<()> {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+
+        let temp_dir = TempDir::new().await?;
+        let body = test_body(TEST_FILE_SIZE);
+        let digest = STANDARD.encode(ring::digest::digest(&ring::digest::SHA256, &body));
+        let wrong_digest = STANDARD.encode([0u8; 32]);
+        for (digest, expect_ok) in [(digest, true), (wrong_digest, false)] {
+            let server = TestServer::start().await?;
+            let mut route = Route::ok(body.clone());
+            route.headers.push(("Repr-Digest".into(), format!("sha-256=:{digest}:")));
+            server.route("/digest.bin", route);
+            let output_path = temp_dir.dir_path().join(format!("digest_{expect_ok}.bin"));
+            let args = LocalArgs {
+                http_args: HttpArgs { retry_args: RetryArgs::new(0, 0), ..HttpArgs::default() },
+                ..local_args(server.url("/digest.bin"), output_path.clone())
+            };
+            let result = handle(args).await;
+            for request in server.requests() {
+                assert!(
+                    request.headers.contains(&("want-repr-digest".into(), "sha-256=10".into())),
+                    "{} request didn't ask for a digest: {:?}",
+                    request.method,
+                    request.headers
+                );
+            }
+            if expect_ok {
+                result?;
+                assert_eq!(fs::read(&output_path).await?, body);
+            } else {
+                let err = result.expect_err("A digest mismatch must fail the download");
+                assert!(
+                    matches!(err.downcast_ref::<CliantError>(), Some(CliantError::ChecksumMismatch { .. })),
+                    "Expected a checksum mismatch, got {err:?}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// HEAD and GET disagreeing on the size: the GET size is used when the two
+    /// are close, the download is aborted when they are wildly apart.
+    #[tokio::test]
+    async fn test_handle_reconciles_head_and_get_sizes() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let body = test_body(TEST_FILE_SIZE);
+        let len = body.len() as u64;
+        for (head_len, expect_ok) in [(len - len / 3, true), (len + len / 3, true), (len * 10, false), (len / 10, false)] {
+            let server = TestServer::start().await?;
+            let mut head = Route::ok("");
+            head.headers.push(("Content-Length".into(), head_len.to_string()));
+            // The GET is sent first, the HEAD while its body is pending.
+            server.script("/resized.bin", [Route::ok(body.clone()), head]);
+            let output_path = temp_dir.dir_path().join(format!("resized_{head_len}.bin"));
+            let args = LocalArgs {
+                http_args: HttpArgs { retry_args: RetryArgs::new(0, 0), ..HttpArgs::default() },
+                ..local_args(server.url("/resized.bin"), output_path.clone())
+            };
+            let result = handle(args).await;
+            let methods: Vec<String> = server.requests().into_iter().map(|request| request.method).collect();
+            assert_eq!(methods, ["GET", "HEAD"]);
+            if expect_ok {
+                result?;
+                let written = fs::read(&output_path).await?;
+                assert_eq!(written.len() as u64, len);
+                assert_eq!(
+                    ring::digest::digest(&ring::digest::SHA256, &written).as_ref(),
+                    ring::digest::digest(&ring::digest::SHA256, &body).as_ref()
+                );
+            } else {
+                let err = result.expect_err("Wildly inconsistent sizes must abort the download");
+                match err.downcast_ref::<CliantError>() {
+                    Some(CliantError::InconsistentSize { planned, actual, .. }) => {
+                        assert_eq!((*planned, *actual), (head_len, len));
+                    }
+                    _ => panic!("Expected an inconsistent size error, got {err:?}"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_total_bytes() {
+        let url = url::Url::parse("http://example.com/file.bin").unwrap();
+        assert_eq!(reconcile_total_bytes(&url, Some(10), Some(10), 2.0).unwrap(), Some(10));
+        assert_eq!(reconcile_total_bytes(&url, Some(10), Some(15), 2.0).unwrap(), Some(15));
+        assert_eq!(reconcile_total_bytes(&url, Some(20), Some(10), 2.0).unwrap(), Some(10));
+        assert_eq!(reconcile_total_bytes(&url, None, Some(10), 2.0).unwrap(), Some(10));
+        assert_eq!(reconcile_total_bytes(&url, Some(10), None, 2.0).unwrap(), Some(10));
+        assert!(reconcile_total_bytes(&url, Some(21), Some(10), 2.0).is_err());
+        assert!(reconcile_total_bytes(&url, Some(0), Some(10), 100.0).is_err());
+    }
+
+    /// The post-process command runs after a successful download and can fail it when required.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handle_runs_post_process() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = serve_test_file().await?;
+        let output_path = temp_dir.dir_path().join("processed.bin");
+        let marker = temp_dir.dir_path().join("processed.txt");
+        let mut args = LocalArgs {
+            post_process: PostProcessArgs {
+                post_process: Some(format!("sh -c 'echo \"$1 $2\" > {}' sh {{name}} {{size}}", marker.display())),
+                ..PostProcessArgs::default()
+            },
+            ..local_args(server.url("/1MB.zip"), output_path.clone())
+        };
+        handle(args.clone()).await?;
+        assert_eq!(fs::read_to_string(&marker).await?, format!("processed.bin {TEST_FILE_SIZE}\n"));
+
+        args.post_process.post_process = Some("false".into());
+        handle(args.clone()).await?;
+        args.post_process.post_process_required = true;
+        let err = handle(args).await.expect_err("A required post-process failure must fail the download");
+        assert!(matches!(err.downcast_ref::<CliantError>(), Some(CliantError::PostProcessFailed { .. })), "{err:?}");
+        Ok(())
+    }
+
+    /// A release page linked instead of its asset is refused, nothing is saved,
+    /// unless `--allow-html` is given or the server offers the page as a file.
+    #[tokio::test]
+    async fn test_handle_refuses_html_pages() -> anyhow::Result<()> {
+        use crate::shared::html::RELEASE_PAGE_FIXTURE;
+
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let mut page = Route::ok(RELEASE_PAGE_FIXTURE);
+        page.headers.push(("Content-Type".into(), "text/html; charset=utf-8".into()));
+        server.route("/example/tool/releases/tag/v2.4.1", page.clone());
+        let mut attachment = page;
+        attachment.headers.push(("Content-Disposition".into(), "attachment; filename=\"page.html\"".into()));
+        server.route("/page.html", attachment);
+
+        let args = |path: &str, output: &str, allow_html: bool| LocalArgs {
+            http_args: HttpArgs { allow_html, ..HttpArgs::default() },
+            ..local_args(server.url(path), temp_dir.dir_path().join(output))
+        };
+
+        let err = handle(args("/example/tool/releases/tag/v2.4.1", "v2.4.1", false))
+            .await
+            .expect_err("An HTML page must not be saved by default");
+        let Some(html_err @ CliantError::HtmlPage { title, candidates, .. }) = err.downcast_ref::<CliantError>() else {
+            panic!("Expected an HTML page error, got {err:?}");
+        };
+        assert_eq!(html_err.exit_code(), 3);
+        assert_eq!(title.as_deref(), Some("Release v2.4.1 & notes · example/tool"));
+        assert_eq!(candidates.len(), 4);
+        let message = html_err.to_string();
+        assert!(message.contains("tool-v2.4.1-x86_64-linux.tar.gz") && message.contains("--allow-html"), "{message}");
+        assert!(!temp_dir.dir_path().join("v2.4.1").exists(), "Nothing may be saved for a refused page");
+
+        handle(args("/example/tool/releases/tag/v2.4.1", "allowed.html", true)).await?;
+        assert_eq!(fs::read_to_string(temp_dir.dir_path().join("allowed.html")).await?, RELEASE_PAGE_FIXTURE);
+
+        handle(args("/page.html", "page.html", false)).await?;
+        assert_eq!(fs::read_to_string(temp_dir.dir_path().join("page.html")).await?, RELEASE_PAGE_FIXTURE);
+        Ok(())
+    }
+
+    /// A multipart/byteranges body fails the download instead of landing in the file.
+    #[tokio::test]
+    async fn test_handle_never_writes_multipart_boundaries() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let output_path = temp_dir.dir_path().join("multipart.bin");
+        let server = TestServer::start().await?;
+        let mut route = Route::ok("--3d6b6a416f9b5\r\nContent-Range: bytes 0-3/4\r\n\r\nabcd\r\n--3d6b6a416f9b5--\r\n");
+        route.status = 206;
+        route.headers.push(("Content-Type".into(), "multipart/byteranges; boundary=3d6b6a416f9b5".into()));
+        server.route("/multipart.bin", route);
+
+        let args = local_args(server.url("/multipart.bin"), output_path.clone());
+        assert!(handle(args).await.is_err());
+        let written = fs::read(&output_path).await.unwrap_or_default();
+        assert!(!String::from_utf8_lossy(&written).contains("3d6b6a416f9b5"));
+        Ok(())
+    }
+
+    /// The downloaded file carries the server's Last-Modified unless `--no-use-server-times` is set.
+    #[tokio::test]
+    async fn test_handle_applies_server_mtime() -> anyhow::Result<()> {
+        use std::time::{Duration, SystemTime};
+
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let mut route = Route::ok(test_body(1024));
+        route.headers.push(("Last-Modified".into(), "Sun, 06 Nov 1994 08:49:37 GMT".into()));
+        server.route("/dated.bin", route);
+        let server_time = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        for no_use_server_times in [false, true] {
+            let output = temp_dir.dir_path().join(format!("dated_{no_use_server_times}.bin"));
+            let args = LocalArgs {
+                no_use_server_times,
+                ..local_args(server.url("/dated.bin"), output.clone())
+            };
+            handle(args).await?;
+            let mtime = fs::metadata(&output).await?.modified()?;
+            assert_eq!(mtime == server_time, !no_use_server_times, "Unexpected mtime {mtime:?}");
+        }
+        Ok(())
+    }
+
     /// Test that instrument/tracing integration works
     #[tokio::test]
     async fn test_handle_with_tracing() -> anyhow::Result<()> {
@@ -368,14 +1151,10 @@ mod tests {
 
         let temp_dir = TempDir::new().await?;
         let output_path = temp_dir.dir_path().join("traced_download.bin");
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let args = LocalArgs {
-            url: link,
-            http_args: HttpArgs::default(),
-            output: output_path,
-            transport: TransportType::Http,
-        };
+        let args = local_args(link, output_path);
 
         // Execute with tracing enabled
         let result = handle(args).await;
@@ -390,14 +1169,10 @@ mod tests {
     async fn test_url_handling_efficiency() -> anyhow::Result<()> {
         let temp_dir = TempDir::new().await?;
         let output_path = temp_dir.dir_path().join("efficiency_test.bin");
-        let link = url::Url::parse("http://speedtest.tele2.net/1MB.zip")?;
+        let server = serve_test_file().await?;
+        let link = server.url("/1MB.zip");
 
-        let args = LocalArgs {
-            url: link.clone(),
-            http_args: HttpArgs::default(),
-            output: output_path,
-            transport: TransportType::Http,
-        };
+        let args = local_args(link.clone(), output_path);
 
         // URL is cloned twice in handle function - verify it works correctly
         let result = handle(args).await;
@@ -405,4 +1180,303 @@ mod tests {
 
         Ok(())
     }
+
+    fn metadata_args(url: url::Url, output: PathBuf) -> LocalArgs {
+        LocalArgs { save_metadata: true, ..local_args(url, output) }
+    }
+
+    /// A full disk fails the download instead of leaving a truncated file behind a success.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_full_disk_fails_the_download() -> anyhow::Result<()> {
+        let server = serve_test_file().await?;
+        let args = local_args(server.url("/1MB.zip"), PathBuf::from("/dev/full"));
+        let err = handle(args).await.unwrap_err();
+        assert!(format!("{err:#}").contains("No space left on device"), "{err:#}");
+        Ok(())
+    }
+
+    fn sha256_hex(content: &[u8]) -> String {
+        let digest = ring::digest::digest(&ring::digest::SHA256, content);
+        digest.as_ref().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// A metalink is followed through its mirrors, the broken preferred one first.
+    #[tokio::test]
+    async fn test_metalink_downloads_from_mirrors() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let (broken, mirror) = (TestServer::start().await?, TestServer::start().await?);
+        broken.route("/example.iso", Route::status(404));
+        mirror.route("/example.iso", Route::ok(test_body(4096)));
+        mirror.route("/corrupt.iso", Route::ok(test_body(4095)));
+        let metalink = |path: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="example.iso">
+    <hash type="sha-256">{}</hash>
+    <url priority="2">{}</url>
+    <url priority="1">{}</url>
+  </file>
+</metalink>"#,
+                sha256_hex(&test_body(4096)),
+                mirror.url(path),
+                broken.url("/example.iso"),
+            )
+        };
+        let server = TestServer::start().await?;
+        server.route("/example.meta4", Route::ok(metalink("/example.iso")));
+        server.route("/corrupt.meta4", Route::ok(metalink("/corrupt.iso")));
+
+        let output = temp_dir.dir_path().join("example.iso");
+        handle(local_args(server.url("/example.meta4"), output.clone())).await?;
+        assert_eq!(fs::read(&output).await?, test_body(4096));
+        assert!(broken.requests().iter().any(|req| req.method == "GET"), "The preferred mirror should be tried first");
+
+        let err = handle(local_args(server.url("/corrupt.meta4"), temp_dir.dir_path().join("corrupt.iso")))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("Every mirror of the metalink"), "{err:#}");
+        assert!(format!("{err:#}").contains("metalink announced sha-256"), "{err:#}");
+
+        // --save-metadata keeps the metalink itself.
+        let saved = temp_dir.dir_path().join("example.meta4");
+        handle(metadata_args(server.url("/example.meta4"), saved.clone())).await?;
+        assert_eq!(fs::read_to_string(&saved).await?, metalink("/example.iso"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_torrent_is_refused_unless_saving_metadata() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let torrent = "d8:announce31:http://tracker.example.com/ann4:infod4:name3:isoee";
+        let mut route = Route::ok(torrent);
+        route.headers.push(("Content-Type".into(), "application/x-bittorrent".into()));
+        server.route("/download?id=7", route);
+        // Recognized by its content alone too.
+        server.route("/get", Route::ok(torrent));
+
+        for path in ["/download?id=7", "/get"] {
+            let output = temp_dir.dir_path().join("file.iso");
+            let err = handle(local_args(server.url(path), output.clone())).await.unwrap_err();
+            assert!(err.to_string().contains("BitTorrent metadata file"), "{err:#}");
+            assert!(!output.exists(), "Nothing should be written for a refused torrent");
+        }
+
+        let output = temp_dir.dir_path().join("file.torrent");
+        handle(metadata_args(server.url("/get"), output.clone())).await?;
+        assert_eq!(fs::read_to_string(&output).await?, torrent);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_mode_converts_line_endings() -> anyhow::Result<()> {
+        use crate::shared::text_mode::LineEnding;
+
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let csv = "\u{feff}id,name\r\n1,caf\u{e9}\r\n2,bar\r\n";
+        let mut route = Route::ok(csv);
+        route.headers.push(("Content-Type".into(), "text/csv; charset=utf-8".into()));
+        server.route("/export.csv", route);
+        server.route("/export.bin", Route::ok(csv));
+
+        let download = |path: &str, assume_text: bool| {
+            let output = temp_dir.dir_path().join(path.trim_start_matches('/'));
+            let mut args = local_args(server.url(path), output.clone());
+            args.text_mode = TextModeArgs { text_mode: Some(LineEnding::Lf), assume_text };
+            async move { handle(args).await.map(|_| output) }
+        };
+        let output = download("/export.csv", false).await?;
+        assert_eq!(fs::read_to_string(&output).await?, "id,name\n1,caf\u{e9}\n2,bar\n");
+        // Untyped content is only converted when assumed to be text.
+        let output = download("/export.bin", false).await?;
+        assert_eq!(fs::read_to_string(&output).await?, csv);
+        let output = download("/export.bin", true).await?;
+        assert_eq!(fs::read_to_string(&output).await?, "id,name\n1,caf\u{e9}\n2,bar\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_writes_reads_back_what_was_written() -> anyhow::Result<()> {
+        use crate::shared::text_mode::LineEnding;
+        use crate::shared::write_check::ReadBack;
+
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(200 * 1024);
+        server.route("/file.bin", Route::ok(body.clone()));
+        let mut route = Route::ok("a\r\nb\r\n");
+        route.headers.push(("Content-Type".into(), "text/plain".into()));
+        server.route("/notes.txt", route);
+
+        let output = temp_dir.dir_path().join("file.bin");
+        let mut args = local_args(server.url("/file.bin"), output.clone());
+        args.verify_writes = Some(ReadBack::Direct);
+        handle(args).await?;
+        assert_eq!(fs::read(&output).await?, body);
+
+        // The converted text is what was written, not what the server sent.
+        let output = temp_dir.dir_path().join("notes.txt");
+        let mut args = local_args(server.url("/notes.txt"), output.clone());
+        args.text_mode = TextModeArgs { text_mode: Some(LineEnding::Lf), assume_text: false };
+        args.verify_writes = Some(ReadBack::Cached);
+        handle(args).await?;
+        assert_eq!(fs::read_to_string(&output).await?, "a\nb\n");
+        Ok(())
+    }
+
+    /// A download split into ranges is identical to the single stream one.
+    #[tokio::test]
+    async fn test_multipart_matches_single_stream() -> anyhow::Result<()> {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(1024 * 1024 + 123);
+        let mut route = Route::ok(body.clone());
+        route.headers.push(("Accept-Ranges".into(), "bytes".into()));
+        let digest = STANDARD.encode(ring::digest::digest(&ring::digest::SHA256, &body));
+        route.headers.push(("Repr-Digest".into(), format!("sha-256=:{digest}:")));
+        server.route("/large.bin", route);
+
+        let download = |name: &str, no_multipart: bool| {
+            let output = temp_dir.dir_path().join(name);
+            let mut args = local_args(server.url("/large.bin"), output.clone());
+            args.multipart = MultipartArgs { part_size: 128 * 1024, max_concurrent_parts: 3, no_multipart, ..MultipartArgs::default() };
+            async move { handle(args).await.map(|_| output) }
+        };
+        let in_parts = download("parts.bin", false).await?;
+        let ranged = server.requests().iter().filter(|request| request.headers.iter().any(|(name, _)| name == "range")).count();
+        // The first of the 9 parts comes from the initial GET.
+        assert_eq!(ranged, 8);
+        let single = download("single.bin", true).await?;
+        assert_eq!(sha256_hex(&fs::read(&in_parts).await?), sha256_hex(&body));
+        assert_eq!(fs::read(&in_parts).await?, fs::read(&single).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multipart_fails_when_ranges_are_ignored() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        // The GET and HEAD announce ranges, the range requests then get the whole file.
+        let whole = Route::ok(test_body(256 * 1024));
+        let mut announcing = whole.clone();
+        announcing.headers.push(("Accept-Ranges".into(), "bytes".into()));
+        server.script("/liar.bin", [announcing.clone(), announcing, whole]);
+        let mut args = local_args(server.url("/liar.bin"), temp_dir.dir_path().join("liar.bin"));
+        args.multipart = MultipartArgs { part_size: 64 * 1024, ..MultipartArgs::default() };
+        let err = handle(args).await.unwrap_err();
+        assert!(format!("{err:#}").contains("asked for bytes 65536-131071"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_part_responses_must_cover_the_part() {
+        let url = url::Url::parse("http://example.com/file.bin").unwrap();
+        let part = Part { first: 100, last: 199 };
+        let ranged = |first, last, total_bytes| DownloadInfo {
+            partial: true,
+            total_bytes,
+            content_range: Some(ContentRange { first, last, complete_length: Some(1000) }),
+            ..DownloadInfo::default()
+        };
+        assert!(check_part_response(&url, part, &ranged(100, 199, Some(100))).is_ok());
+        assert!(check_part_response(&url, part, &ranged(100, 199, None)).is_ok(), "A chunked 206 has no Content-Length");
+        for (info, answered) in [
+            (DownloadInfo { total_bytes: Some(1000), ..DownloadInfo::default() }, "the whole file"),
+            (DownloadInfo { partial: true, total_bytes: Some(100), ..DownloadInfo::default() }, "a 206 without a valid Content-Range"),
+            (ranged(0, 99, Some(100)), "bytes 0-99"),
+            (ranged(100, 199, Some(1000)), "a body of 1000 bytes"),
+        ] {
+            let err = check_part_response(&url, part, &info).unwrap_err().to_string();
+            assert!(err.contains(&format!("asked for bytes 100-199, the server answered with {answered}")), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multipart_fails_on_the_wrong_range() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        // Every range request gets the first 64 KiB, with a matching Content-Length.
+        let mut announcing = Route::ok(test_body(256 * 1024));
+        announcing.headers.push(("Accept-Ranges".into(), "bytes".into()));
+        let mut first_range = Route::ok(test_body(64 * 1024));
+        first_range.status = 206;
+        first_range.headers.push(("Content-Range".into(), "bytes 0-65535/262144".into()));
+        server.script("/stuck.bin", [announcing.clone(), announcing, first_range]);
+        let mut args = local_args(server.url("/stuck.bin"), temp_dir.dir_path().join("stuck.bin"));
+        args.multipart = MultipartArgs { part_size: 64 * 1024, ..MultipartArgs::default() };
+        let err = handle(args).await.unwrap_err();
+        assert!(format!("{err:#}").contains("the server answered with bytes 0-65535"), "{err:#}");
+        Ok(())
+    }
+
+    /// The initial GET is closed once the first part is written, not once the last part is.
+    #[tokio::test]
+    async fn test_initial_get_is_closed_after_the_first_part() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(8 * 1024);
+        let ranged = |route: Route| Route { headers: vec![("Accept-Ranges".into(), "bytes".into())], ..route };
+        let (initial, initial_gate) = Route::gated(body.clone(), 1024);
+        let (parts, parts_gate) = Route::gated(body.clone(), 1024);
+        server.route("HEAD /parts.bin", ranged(Route::ok(body.clone())));
+        server.script("/parts.bin", [ranged(initial), ranged(parts)]);
+        let output = temp_dir.dir_path().join("parts.bin");
+        let mut args = local_args(server.url("/parts.bin"), output.clone());
+        args.multipart = MultipartArgs { part_size: 2048, max_concurrent_parts: 2, ..MultipartArgs::default() };
+
+        // Only the first part is released on the initial GET, the other parts wait on theirs.
+        initial_gate.release(2);
+        let first_part_closed = async {
+            let closed = time::timeout(std::time::Duration::from_secs(5), async {
+                while initial_gate.hang_ups() == 0 {
+                    time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .is_ok();
+            parts_gate.release(1000);
+            closed
+        };
+        let (result, closed) = tokio::join!(handle(args), first_part_closed);
+        result?;
+        assert!(closed, "The initial GET stayed open while the other parts downloaded");
+        assert_eq!(fs::read(&output).await?, body);
+        Ok(())
+    }
+
+    /// `--max-concurrent-parts 1` fetches the ranges only once the first part is written.
+    #[tokio::test]
+    async fn test_single_concurrent_part_is_sequential() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new().await?;
+        let server = TestServer::start().await?;
+        let body = test_body(4 * 1024);
+        let (mut route, gate) = Route::gated(body.clone(), 256);
+        route.headers.push(("Accept-Ranges".into(), "bytes".into()));
+        server.route("/gated.bin", route);
+        let output = temp_dir.dir_path().join("gated.bin");
+        let mut args = local_args(server.url("/gated.bin"), output.clone());
+        args.multipart = MultipartArgs { part_size: 1024, max_concurrent_parts: 1, ..MultipartArgs::default() };
+
+        let ranged = || server.requests().iter().filter(|request| request.headers.iter().any(|(name, _)| name == "range")).count();
+        // The first chunk is peeked at before the download starts, the first part then waits on the second.
+        gate.release(1);
+        let while_first_part_blocked = async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            let ranged_early = ranged();
+            gate.release(1000);
+            ranged_early
+        };
+        let (result, ranged_early) = tokio::join!(handle(args), while_first_part_blocked);
+        result?;
+        assert_eq!(ranged_early, 0, "A range was requested while the first part was still downloading");
+        assert_eq!(ranged(), 3);
+        assert_eq!(fs::read(&output).await?, body);
+        Ok(())
+    }
 }
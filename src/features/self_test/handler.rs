@@ -0,0 +1,243 @@
+//! `cliant --self-test`: downloads known content from an in-process server
+//! through the real download path, to tell a Cliant bug from a middlebox or
+//! disk problem when a user reports corrupt downloads.
+//!
+//! Every scenario serves seed-derived content ([`test_body`]) and checks the
+//! written file against its sha-256. The report ends with the environment
+//! (version, features, OS, filesystem of the artifacts directory) so it can be
+//! pasted into an issue as is. The test suite runs the same battery, so what
+//! users run can't drift from what CI checks.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use ring::digest::{SHA256, digest};
+
+use crate::features::save_to_local::{cli::LocalArgs, handler::handle};
+use crate::shared::capabilities::enabled_features;
+use crate::shared::errors::CliantError;
+use crate::shared::network::factory::TransportType;
+use crate::shared::network::http::config::{HttpArgs, RetryArgs};
+use crate::shared::post_process::PostProcessArgs;
+use crate::shared::test_server::{Route, TestServer, test_body};
+use crate::shared::text_mode::TextModeArgs;
+use crate::shared::parts::MultipartArgs;
+
+/// A scenario and what it concluded: what was verified, or the invariant that broke.
+pub struct Outcome {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+}
+
+pub struct Report {
+    pub artifacts: PathBuf,
+    /// Filesystem of the artifacts directory, e.g `tmpfs`.
+    pub filesystem: String,
+    pub kept: bool,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Plain text report, one line per scenario and the environment.
+    pub fn render(&self) -> String {
+        let mut out = String::from("Cliant self-test\n\n");
+        let width = self.outcomes.iter().map(|outcome| outcome.name.len()).max().unwrap_or_default();
+        for Outcome { name, result } in &self.outcomes {
+            let (status, detail) = match result {
+                Ok(detail) => ("PASS", detail),
+                Err(detail) => ("FAIL", detail),
+            };
+            let _ = writeln!(out, "  {status}  {name:width$}  {detail}");
+        }
+        let features = enabled_features().join(", ");
+        let _ = writeln!(out, "\ncliant {} (features: {features})", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(out, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+        let artifacts = if self.kept { "kept" } else { "removed" };
+        let _ = writeln!(
+            out,
+            "artifacts: {} ({}, {artifacts})",
+            self.artifacts.display(),
+            self.filesystem
+        );
+        out
+    }
+}
+
+/// Runs every scenario in a fresh directory under the system temp directory,
+/// removed afterwards unless `keep_artifacts`.
+pub async fn run(keep_artifacts: bool) -> Result<Report> {
+    let artifacts = std::env::temp_dir().join(format!("cliant-self-test-{}", std::process::id()));
+    tokio::fs::create_dir_all(&artifacts).await?;
+    let filesystem = filesystem_type(&artifacts);
+    let server = TestServer::start().await?;
+    let mut outcomes = Vec::new();
+    outcomes.push(Outcome { name: "small file", result: small_file(&server, &artifacts).await });
+    outcomes.push(Outcome { name: "chunked file", result: chunked_file(&server, &artifacts).await });
+    outcomes.push(Outcome { name: "retry after failure", result: retry_after_failure(&server, &artifacts).await });
+    outcomes.push(Outcome { name: "checksum verification", result: checksum_verification(&server, &artifacts).await });
+    if !keep_artifacts {
+        tokio::fs::remove_dir_all(&artifacts).await?;
+    }
+    Ok(Report { artifacts, filesystem, kept: keep_artifacts, outcomes })
+}
+
+/// Turns a failed self-test into the process' error.
+pub fn check(report: &Report) -> Result<()> {
+    let failed = report.outcomes.iter().filter(|outcome| outcome.result.is_err()).count();
+    if failed > 0 {
+        bail!("{failed} of {} self-test scenarios failed", report.outcomes.len());
+    }
+    Ok(())
+}
+
+async fn small_file(server: &TestServer, artifacts: &Path) -> Result<String, String> {
+    let body = test_body(4 * 1024);
+    server.route("/small.bin", Route::ok(body.clone()));
+    download(server, "/small.bin", artifacts, RetryArgs::new(0, 0)).await?;
+    verify(&artifacts.join("small.bin"), &body).await
+}
+
+/// Large enough to arrive in many chunks, exercising the chunk loop and its yields.
+async fn chunked_file(server: &TestServer, artifacts: &Path) -> Result<String, String> {
+    let body = test_body(256 * 1024);
+    server.route("/chunked.bin", Route::ok(body.clone()));
+    download(server, "/chunked.bin", artifacts, RetryArgs::new(0, 0)).await?;
+    verify(&artifacts.join("chunked.bin"), &body).await
+}
+
+async fn retry_after_failure(server: &TestServer, artifacts: &Path) -> Result<String, String> {
+    let body = test_body(16 * 1024);
+    server.script("/flaky.bin", [Route::status(503), Route::ok(body.clone())]);
+    download(server, "/flaky.bin", artifacts, RetryArgs::new(2, 0)).await?;
+    let gets = server.requests().iter().filter(|request| request.method == "GET" && request.path == "/flaky.bin").count();
+    let verified = verify(&artifacts.join("flaky.bin"), &body).await?;
+    Ok(format!("{verified} after {gets} requests"))
+}
+
+/// A correct `Repr-Digest` must verify and a wrong one must fail the download.
+async fn checksum_verification(server: &TestServer, artifacts: &Path) -> Result<String, String> {
+    let body = test_body(8 * 1024);
+    let digest_header = |content: &[u8]| format!("sha-256=:{}:", STANDARD.encode(digest(&SHA256, content)));
+    for (path, digested) in [("/digest.bin", body.clone()), ("/corrupt.bin", Bytes::from_static(b"other content"))] {
+        let mut route = Route::ok(body.clone());
+        route.headers.push(("Repr-Digest".into(), digest_header(&digested)));
+        server.route(path, route);
+    }
+    download(server, "/digest.bin", artifacts, RetryArgs::new(0, 0)).await?;
+    verify(&artifacts.join("digest.bin"), &body).await?;
+    match download(server, "/corrupt.bin", artifacts, RetryArgs::new(0, 0)).await {
+        Err(err) if err.contains("Checksum mismatch") => Ok("a matching Repr-Digest verified, a wrong one was caught".into()),
+        Err(err) => Err(format!("a wrong Repr-Digest failed with an unexpected error: {err}")),
+        Ok(()) => Err("a wrong Repr-Digest was not detected".into()),
+    }
+}
+
+/// Downloads `path` of `server` into `artifacts` under its file name.
+async fn download(server: &TestServer, path: &str, artifacts: &Path, retry_args: RetryArgs) -> Result<(), String> {
+    let args = LocalArgs {
+        url: server.url(path),
+        output: artifacts.join(path.trim_start_matches('/')),
+        http_args: HttpArgs { retry_args, ..HttpArgs::default() },
+        transport: TransportType::Http,
+        no_use_server_times: false,
+        save_metadata: false,
+        text_mode: TextModeArgs::default(),
+        verify_writes: None,
+        multipart: MultipartArgs::default(),
+        post_process: PostProcessArgs::default(),
+    };
+    handle(args).await.map_err(|err| match err.downcast_ref::<CliantError>() {
+        Some(err) => err.to_string(),
+        None => format!("{err:#}"),
+    })
+}
+
+/// Checks the file at `path` holds exactly `expected`.
+async fn verify(path: &Path, expected: &[u8]) -> Result<String, String> {
+    let written = tokio::fs::read(path).await.map_err(|err| format!("can't read {}: {err}", path.display()))?;
+    let hex = |content: &[u8]| digest(&SHA256, content).as_ref().iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+    if written.len() != expected.len() {
+        return Err(format!("{} has {} bytes, {} were served", path.display(), written.len(), expected.len()));
+    }
+    let (actual, expected) = (hex(&written), hex(expected));
+    if actual != expected {
+        return Err(format!("{} hashes to sha-256 {actual}, the served content to {expected}", path.display()));
+    }
+    Ok(format!("{} bytes, sha-256 matches", written.len()))
+}
+
+/// Name of the filesystem `path` is on, from its `statfs` magic number.
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return "unknown filesystem".into();
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL terminated and `stat` is a valid statfs to fill.
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return "unknown filesystem".into();
+    }
+    #[allow(clippy::unnecessary_cast)] // The field's type differs between libc targets.
+    let magic = stat.f_type as u64;
+    let name = match magic {
+        0xEF53 => "ext2/3/4",
+        0x0102_1994 => "tmpfs",
+        0x5846_5342 => "xfs",
+        0x9123_683E => "btrfs",
+        0x794C_7630 => "overlayfs",
+        0x2FC1_2FC1 => "zfs",
+        0x6969 => "nfs",
+        0xFF53_4D42 => "cifs",
+        0x6573_5546 => "fuse",
+        _ => return format!("filesystem 0x{magic:x}"),
+    };
+    name.into()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_type(_path: &Path) -> String {
+    "unknown filesystem".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_self_test_passes() -> Result<()> {
+        let report = run(false).await?;
+        let rendered = report.render();
+        assert!(check(&report).is_ok(), "{rendered}");
+        assert!(!report.artifacts.exists(), "Artifacts should be removed");
+        for name in ["small file", "chunked file", "retry after failure", "checksum verification"] {
+            assert!(rendered.contains(&format!("PASS  {name}")), "{rendered}");
+        }
+        assert!(rendered.contains("after 2 requests"), "{rendered}");
+        assert!(rendered.contains(&format!("cliant {}", env!("CARGO_PKG_VERSION"))), "{rendered}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let report = Report {
+            artifacts: PathBuf::from("/tmp/cliant-self-test-1"),
+            filesystem: "tmpfs".into(),
+            kept: true,
+            outcomes: vec![
+                Outcome { name: "small file", result: Ok("4096 bytes, sha-256 matches".into()) },
+                Outcome { name: "retry after failure", result: Err("flaky.bin has 0 bytes, 16384 were served".into()) },
+            ],
+        };
+        let rendered = report.render();
+        assert!(rendered.contains("  FAIL  retry after failure  flaky.bin has 0 bytes"), "{rendered}");
+        assert!(rendered.contains("PASS  small file           4096 bytes"), "{rendered}");
+        assert!(rendered.contains("artifacts: /tmp/cliant-self-test-1 (tmpfs, kept)"), "{rendered}");
+        assert_eq!(check(&report).unwrap_err().to_string(), "1 of 2 self-test scenarios failed");
+    }
+}
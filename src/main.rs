@@ -6,27 +6,59 @@
 //! parses command-line arguments, configures the HTTP client, and starts the
 //! download process.
 
-use clap::{ArgAction, Parser, Subcommand};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, error::ErrorKind};
 use anyhow::Result;
 #[cfg(feature = "local")]
 use features::save_to_local::{cli::LocalArgs,handler::handle};
+#[cfg(feature = "local")]
+use features::cat::cli::CatArgs;
+#[cfg(feature = "local")]
+use features::page::cli::PageArgs;
 
 use tracing::Level;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use tracing_indicatif::IndicatifLayer;
 mod features;
 mod shared;
+use shared::errors::CliantError;
+use shared::capabilities::Capabilities;
+use shared::i18n::{self, validate_lang};
+use shared::output;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+use shared::sandbox::{self, SandboxMode};
 #[derive(Clone,Parser)]
-#[command(version="0.1.0",about="A state-of-the-art, high performance Data Mover for embarrassingly parallel tasks.",long_about=None)]
+#[command(version="0.1.0",about="A state-of-the-art, high performance Data Mover for embarrassingly parallel tasks.",long_about=None,arg_required_else_help=true)]
 struct Cliant{
     #[command(subcommand)]
-    command:Commands,
+    command:Option<Commands>,
+    /// Print a JSON report of the features, url schemes, flags and exit codes this binary supports, then exit.
+    #[arg(long,exclusive=true)]
+    pub capabilities: bool,
+    /// Download known content from an in-process server through the real download path and print a
+    /// pass/fail report with environment details, e.g to attach to a bug report about corrupt downloads.
+    /// It runs on its own: a subcommand or --sandbox is refused, only the logging flags apply.
+    #[cfg(feature = "local")]
+    #[arg(long)]
+    #[cfg_attr(all(feature = "sandbox", target_os = "linux"), arg(conflicts_with = "sandbox"))]
+    pub self_test: bool,
+    /// Keep the files written by --self-test instead of removing them.
+    #[cfg(feature = "local")]
+    #[arg(long,requires="self_test")]
+    pub keep_self_test_artifacts: bool,
     /// Set the Logging level to quiet. Less information about download events are emitted i.e only Errors.
     #[arg(short = 'q', long = "quiet",)]
     pub quiet: bool,
     /// Set the Logging level to verbose. More information about download events are emitted.
     #[arg(short='v',long="verbose",action=ArgAction::Count)]
     pub verbose: u8,
+    /// Language of user-facing messages (en, fr). Defaults to the LC_ALL/LC_MESSAGES/LANG locale, English if unsupported.
+    #[arg(long,value_parser=validate_lang)]
+    pub lang: Option<String>,
+    /// Confine file access to the output directory with Landlock before any network I/O (Linux only).
+    /// `--sandbox=best-effort` runs unsandboxed with a warning when the kernel lacks Landlock.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    #[arg(long,global=true,value_enum,num_args=0..=1,require_equals=true,default_missing_value="strict")]
+    pub sandbox: Option<SandboxMode>,
 }
 
 #[derive(Subcommand,Clone)]
@@ -34,6 +66,12 @@ enum Commands{
     #[cfg(feature = "local")]
     ///Fetch data from a remote http server and save it content to the local storage.
     Download(LocalArgs),
+    #[cfg(feature = "local")]
+    ///Print a remote file to stdout, e.g to look at a log. Text only and up to 100 MiB unless --force.
+    Cat(CatArgs),
+    #[cfg(feature = "local")]
+    ///Save an HTML page along with the images, stylesheets and scripts it needs, rewriting its references to the local copies.
+    Page(PageArgs),
 }
 
 fn setup_tracing(args: &Cliant) {
@@ -65,6 +103,8 @@ fn setup_tracing(args: &Cliant) {
                 .with_target(false) // cleaner output
                 .with_file(false)
                 .with_line_number(false)
+                // Its reports of failed writes go to stderr too, and panic once stderr is a closed pipe.
+                .log_internal_errors(false)
                 .compact()
                 .with_writer(indicatif_layer.get_stderr_writer())
                 
@@ -74,16 +114,140 @@ fn setup_tracing(args: &Cliant) {
         .init();
 }
 
-#[tokio::main]
-async fn main()->Result<()>{
+fn main()->Result<()>{
     human_panic::setup_panic!();
     let args= Cliant::parse();
+    if args.capabilities {
+        let capabilities = Capabilities::collect(&Cliant::command());
+        output::stdout(format_args!("{}\n", serde_json::to_string_pretty(&capabilities)?))?;
+        return Ok(());
+    }
     setup_tracing(&args);
-    match args.command{
+    i18n::init(args.lang.as_deref());
+    #[cfg(feature = "local")]
+    if let Err(err) = check_self_test(&args) {
+        err.exit();
+    }
+    #[cfg(feature = "local")]
+    if args.self_test {
+        let report = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(features::self_test::handler::run(args.keep_self_test_artifacts))?;
+        output::stdout(format_args!("{}", report.render()))?;
+        return features::self_test::handler::check(&report);
+    }
+    // Landlock only restricts threads spawned after it is applied, so this must run before the runtime starts.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    if let Some(mode) = args.sandbox {
+        let paths = match &args.command {
+            Some(Commands::Download(local_args)) => Some(local_args.sandbox_paths()?),
+            Some(Commands::Cat(cat_args)) => Some(cat_args.sandbox_paths()),
+            Some(Commands::Page(page_args)) => Some(page_args.sandbox_paths()?),
+            None => None,
+        };
+        if let Some(paths) = paths {
+            sandbox::restrict_current_thread(mode, &paths)?;
+        }
+    }
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args.command));
+    if let Err(err) = result {
+        // Same output as returning the error, with the exit code it maps to.
+        output::stderr(format_args!("Error: {err:?}\n"));
+        std::process::exit(err.downcast_ref::<CliantError>().map_or(1, CliantError::exit_code));
+    }
+    Ok(())
+}
+
+/// `--self-test` is exclusive of the subcommands, which clap can't express for a
+/// flag with a companion (`--keep-self-test-artifacts`): it would run and they be ignored.
+#[cfg(feature = "local")]
+fn check_self_test(args: &Cliant) -> Result<(), clap::Error> {
+    if args.self_test && args.command.is_some() {
+        return Err(Cliant::command().error(
+            ErrorKind::ArgumentConflict,
+            "--self-test runs on its own and can't be combined with a subcommand",
+        ));
+    }
+    Ok(())
+}
+
+async fn run(command:Option<Commands>)->Result<()>{
+    match command{
         #[cfg(feature = "local")]
-        Commands::Download(local_args)=>{
+        Some(Commands::Download(local_args))=>{
             handle(local_args).await?;
         }
+        #[cfg(feature = "local")]
+        Some(Commands::Cat(cat_args))=>{
+            features::cat::handler::handle(cat_args).await?;
+        }
+        #[cfg(feature = "local")]
+        Some(Commands::Page(page_args))=>{
+            features::page::handler::handle(page_args).await?;
+        }
+        None=>{
+            Cliant::command().error(ErrorKind::MissingSubcommand, "a subcommand is required").exit();
+        }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Wrappers depend on this document, changing it must be a conscious decision.
+    #[test]
+    fn test_capabilities_snapshot() {
+        let mut report = serde_json::to_value(Capabilities::collect(&Cliant::command())).unwrap();
+        // Flags grow with every release, only their shape is part of the snapshot.
+        let flags = report.as_object_mut().unwrap().remove("flags").unwrap();
+        assert_eq!(
+            report,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "features": shared::capabilities::enabled_features(),
+                "transports": ["http"],
+                "schemes": ["http", "https"],
+                "checksum_algorithms": ["sha-512", "sha-256", "md5"],
+                "write_strategies": ["ranged"],
+                "exit_codes": [
+                    {"code": 0, "meaning": "success"},
+                    {"code": 1, "meaning": "download failed"},
+                    {"code": 2, "meaning": "invalid command line arguments"},
+                    {"code": 3, "meaning": "the url is an HTML page, not a file"},
+                ],
+            })
+        );
+        let flags = flags.as_object().unwrap();
+        assert_eq!(flags.keys().collect::<Vec<_>>(), ["", "cat", "download", "page"]);
+        assert!(flags[""].as_array().unwrap().contains(&json!("--capabilities")));
+        assert!(flags["download"].as_array().unwrap().contains(&json!("--proxy-url")));
+    }
+
+    #[test]
+    fn test_capabilities_flag_is_exclusive() {
+        assert!(Cliant::try_parse_from(["cliant", "--capabilities"]).is_ok());
+        assert!(Cliant::try_parse_from(["cliant", "--capabilities", "-v"]).is_err());
+    }
+
+    #[cfg(feature = "local")]
+    #[test]
+    fn test_self_test_flag_is_exclusive() {
+        let parse = |args: &[&str]| {
+            Cliant::try_parse_from(args).and_then(|cliant| check_self_test(&cliant))
+        };
+        assert!(parse(&["cliant", "--self-test"]).is_ok());
+        assert!(parse(&["cliant", "--self-test", "--keep-self-test-artifacts", "-v"]).is_ok());
+        assert!(parse(&["cliant", "--keep-self-test-artifacts"]).is_err());
+        let err = parse(&["cliant", "--self-test", "download", "http://example.com/f.bin", "-o", "f.bin"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        #[cfg(all(feature = "sandbox", target_os = "linux"))]
+        assert!(parse(&["cliant", "--self-test", "--sandbox"]).is_err());
+    }
+}
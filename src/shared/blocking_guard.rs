@@ -0,0 +1,69 @@
+//! The one way blocking work (filesystem metadata, file times, and later
+//! statfs or archive extraction) runs from async code.
+//!
+//! A blocking call on a runtime worker stalls every task scheduled on it, and
+//! with a current-thread runtime the whole process. Async code must go through
+//! [`run`] rather than call `std::fs` directly or spawn its own blocking tasks,
+//! so the offenders stay easy to find. Sync code running once before any
+//! transfer starts, e.g reading `--proxy-cacert` while the client is built, is
+//! left as is.
+
+use crate::shared::errors::CliantError;
+
+/// Runs `work` on tokio's blocking thread pool and waits for it without
+/// holding up the calling worker. `what` names the work in the error of a panic.
+pub async fn run<T: Send + 'static>(
+    what: &'static str,
+    work: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> Result<T, CliantError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| CliantError::Fatal(format!("{what} panicked: {err}")))?
+        .map_err(CliantError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    /// Largest delay of a 10ms ticker sharing the current-thread runtime with `work`.
+    async fn ticker_lag<F: Future>(work: F) -> (F::Output, Duration) {
+        let lag = Arc::new(Mutex::new(Duration::ZERO));
+        let ticker_lag = lag.clone();
+        let ticker = tokio::spawn(async move {
+            loop {
+                let planned = Instant::now() + Duration::from_millis(10);
+                tokio::time::sleep_until(planned).await;
+                let mut lag = ticker_lag.lock().unwrap();
+                *lag = (*lag).max(Instant::now() - planned);
+            }
+        });
+        let output = work.await;
+        ticker.abort();
+        let lag = *lag.lock().unwrap();
+        (output, lag)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_blocking_work_leaves_the_runtime_responsive() -> anyhow::Result<()> {
+        let (slept, lag) = ticker_lag(run("sleeping", || {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok("slept")
+        }))
+        .await;
+        assert_eq!(slept?, "slept");
+        assert!(lag < Duration::from_millis(200), "The ticker was held up for {lag:?}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_panics_are_reported() {
+        let err = run("reading", || std::fs::read("/nonexistent/cliant")).await.unwrap_err();
+        assert!(matches!(err, CliantError::Io(_)), "{err}");
+        let err = run("exploding", || -> std::io::Result<()> { panic!("boom") }).await.unwrap_err();
+        assert!(err.to_string().contains("exploding panicked"), "{err}");
+    }
+}
@@ -0,0 +1,138 @@
+//! Machine-readable description of what this binary supports.
+//!
+//! `cliant --capabilities` prints a [`Capabilities`] document as JSON so
+//! wrapper tools can feature-detect before building command lines instead of
+//! parsing `--help`. Every list is read from the registry it describes (cargo
+//! features, the transport factory, the clap command tree, the exit code
+//! table), nothing here is maintained by hand.
+//!
+//! **NB:** The field names are part of the public interface, add fields
+//! instead of renaming them.
+
+use std::collections::BTreeMap;
+
+use clap::Command;
+use serde::Serialize;
+
+use crate::shared::checksum::ChecksumAlgorithm;
+use crate::shared::errors::EXIT_CODES;
+use crate::shared::network::factory::TransportType;
+
+/// Every feature of `[features]` in Cargo.toml paired with whether it is enabled.
+macro_rules! cargo_features {
+    ($($name:literal),* $(,)?) => {
+        [$(($name, cfg!(feature = $name))),*]
+    };
+}
+
+/// Kept in sync with Cargo.toml by `test_every_cargo_feature_is_reported`.
+const CARGO_FEATURES: [(&str, bool); 4] = cargo_features!["all", "default", "local", "sandbox"];
+
+/// Cargo features this binary was compiled with.
+pub fn enabled_features() -> Vec<&'static str> {
+    CARGO_FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+    pub transports: Vec<String>,
+    pub schemes: Vec<&'static str>,
+    /// Digests verified when the source announces one.
+    pub checksum_algorithms: Vec<&'static str>,
+    /// How downloads are written besides appending: `ranged` when parts are
+    /// written at their offset. Preallocation would be another one.
+    pub write_strategies: Vec<&'static str>,
+    /// Long flags accepted by each subcommand, global flags are listed under `""`.
+    pub flags: BTreeMap<String, Vec<String>>,
+    pub exit_codes: Vec<ExitCode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExitCode {
+    pub code: i32,
+    pub meaning: &'static str,
+}
+
+impl Capabilities {
+    /// Collects the report, `cli` is the root command of the binary.
+    pub fn collect(cli: &Command) -> Self {
+        let mut flags = BTreeMap::new();
+        flags.insert(String::new(), long_flags(cli));
+        for subcommand in cli.get_subcommands() {
+            flags.insert(subcommand.get_name().to_string(), long_flags(subcommand));
+        }
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            features: enabled_features(),
+            transports: TransportType::names(),
+            schemes: TransportType::supported_schemes(),
+            checksum_algorithms: ChecksumAlgorithm::ALL.iter().map(|algorithm| algorithm.name()).collect(),
+            write_strategies: write_strategies(),
+            flags,
+            exit_codes: EXIT_CODES
+                .iter()
+                .map(|&(code, meaning)| ExitCode { code, meaning })
+                .collect(),
+        }
+    }
+}
+
+/// Write strategies of the filesystem downloads are saved to.
+fn write_strategies() -> Vec<&'static str> {
+    let mut strategies = vec![];
+    #[cfg(feature = "local")]
+    if crate::shared::fs::local::LocalFs::WRITES_AT_OFFSETS {
+        strategies.push("ranged");
+    }
+    strategies
+}
+
+fn long_flags(command: &Command) -> Vec<String> {
+    let mut flags: Vec<String> = command
+        .get_arguments()
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{long}"))
+        .collect();
+    flags.sort();
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::save_to_local::cli::parse_url;
+
+    #[test]
+    fn test_every_cargo_feature_is_reported() {
+        let manifest = include_str!("../../Cargo.toml");
+        let mut declared: Vec<&str> = manifest
+            .lines()
+            .skip_while(|line| line.trim() != "[features]")
+            .skip(1)
+            .take_while(|line| !line.trim_start().starts_with('['))
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name.trim()))
+            .collect();
+        declared.sort_unstable();
+        let reported: Vec<&str> = CARGO_FEATURES.iter().map(|(name, _)| *name).collect();
+        assert_eq!(reported, declared);
+        assert_eq!(enabled_features().contains(&"default"), cfg!(feature = "default"));
+    }
+
+    #[test]
+    fn test_reported_schemes_match_url_validation() {
+        for scheme in TransportType::supported_schemes() {
+            assert!(
+                parse_url(&format!("{scheme}://example.com/file.zip")).is_ok(),
+                "{scheme} is reported but rejected by url validation"
+            );
+        }
+        for scheme in ["ftp", "s3", "file", "socks5"] {
+            assert!(
+                parse_url(&format!("{scheme}://example.com/file.zip")).is_err(),
+                "{scheme} is accepted by url validation but not reported"
+            );
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Verification of downloaded content against a digest announced by the source.
+//!
+//! Transports turn whatever digest the source offers into a [`Checksum`], the
+//! download loop feeds every written chunk to a [`ChecksumVerifier`] and fails
+//! the download when the digests differ.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use md5::{Digest, Md5};
+use ring::digest::{Context, SHA256, SHA512};
+
+use super::blocking_guard;
+use super::errors::CliantError;
+use super::progress_tracker::PhaseProgress;
+
+/// Digest algorithms Cliant can verify, strongest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha512,
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: &[ChecksumAlgorithm] = &[Self::Sha512, Self::Sha256, Self::Md5];
+
+    /// Name as registered in the HTTP digest algorithm registry.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha512 => "sha-512",
+            Self::Sha256 => "sha-256",
+            Self::Md5 => "md5",
+        }
+    }
+}
+
+/// The digest a source announced for the content it sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub expected: Vec<u8>,
+    /// Where the digest came from, e.g `Repr-Digest` for the HTTP header.
+    pub origin: &'static str,
+}
+
+enum Hasher {
+    Ring(Context),
+    Md5(Md5),
+}
+
+/// Hashes content as it is written and compares it to a [`Checksum`] at the end.
+pub struct ChecksumVerifier {
+    checksum: Checksum,
+    hasher: Hasher,
+}
+
+impl ChecksumVerifier {
+    pub fn new(checksum: Checksum) -> Self {
+        let hasher = match checksum.algorithm {
+            ChecksumAlgorithm::Sha512 => Hasher::Ring(Context::new(&SHA512)),
+            ChecksumAlgorithm::Sha256 => Hasher::Ring(Context::new(&SHA256)),
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+        };
+        Self { checksum, hasher }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Ring(context) => context.update(bytes),
+            Hasher::Md5(md5) => md5.update(bytes),
+        }
+    }
+
+    /// Hashes the file at `path` on the blocking pool and compares its digest,
+    /// for content written out of order such as the parts of a multipart download.
+    pub async fn finish_file(mut self, path: PathBuf, progress: PhaseProgress) -> Result<Checksum, CliantError> {
+        let verifier = blocking_guard::run("Hashing the download", move || {
+            let mut file = progress.reader(std::fs::File::open(path)?);
+            let mut buffer = vec![0; 256 * 1024];
+            loop {
+                match file.read(&mut buffer)? {
+                    0 => return Ok(self),
+                    read => self.update(&buffer[..read]),
+                }
+            }
+        })
+        .await?;
+        verifier.finish()
+    }
+
+    /// Compares the digest of everything passed to `update` with the expected one.
+    pub fn finish(self) -> Result<Checksum, CliantError> {
+        let actual = match self.hasher {
+            Hasher::Ring(context) => context.finish().as_ref().to_vec(),
+            Hasher::Md5(md5) => md5.finalize().to_vec(),
+        };
+        if actual != self.checksum.expected {
+            return Err(CliantError::ChecksumMismatch {
+                algorithm: self.checksum.algorithm.name(),
+                origin: self.checksum.origin,
+                expected: hex(&self.checksum.expected),
+                actual: hex(&actual),
+            });
+        }
+        Ok(self.checksum)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn verify(algorithm: ChecksumAlgorithm, expected_hex: &str, chunks: &[&[u8]]) -> Result<Checksum, CliantError> {
+        let mut verifier = ChecksumVerifier::new(Checksum {
+            algorithm,
+            expected: unhex(expected_hex),
+            origin: "test",
+        });
+        for chunk in chunks {
+            verifier.update(chunk);
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn test_known_digests_verify_across_chunks() {
+        verify(
+            ChecksumAlgorithm::Sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            &[b"a", b"bc"],
+        )
+        .unwrap();
+        verify(
+            ChecksumAlgorithm::Sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            &[b"ab", b"c"],
+        )
+        .unwrap();
+        verify(ChecksumAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72", &[b"abc"]).unwrap();
+    }
+
+    #[test]
+    fn test_mismatch_names_both_digests() {
+        let err = verify(ChecksumAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72", &[b"abd"]).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("md5") && message.contains("900150983cd24fb0d6963f7d28e17f72"), "{message}");
+    }
+}
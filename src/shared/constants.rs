@@ -0,0 +1,56 @@
+//! Tunables shared by the download loops.
+
+/// Chunk loops yield back to the scheduler after this many chunks, counted
+/// by [`ChunkPacer`](crate::shared::pacing::ChunkPacer).
+///
+/// On a fast connection every `stream.next()` and buffered write completes
+/// immediately, so without an explicit yield one large download can keep its
+/// worker busy for seconds while other downloads sharing the runtime stall.
+/// At 32 chunks (a few hundred KiB with typical chunk sizes) the cost of the
+/// extra scheduler round trip is not measurable on large files.
+pub const YIELD_EVERY_CHUNKS: u64 = 32;
+
+/// Received chunks larger than this are split before they enter the pipeline.
+///
+/// A fast connection can hand out multi-MiB `Bytes` at once, which makes
+/// progress updates lumpy and write batching unpredictable. Splitting is
+/// zero-copy, the pieces share the original allocation.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Capacity of the channel a transport streams a response body through.
+///
+/// It counts messages, each a chunk of at most [`MAX_CHUNK_SIZE`] bytes, not
+/// bytes: a stalled consumer leaves at most `STREAM_CHANNEL_MESSAGES *
+/// MAX_CHUNK_SIZE` bytes buffered, usually far less since most chunks are a
+/// few KiB.
+pub const STREAM_CHANNEL_MESSAGES: usize = 256;
+
+/// `--verify-writes` compares a download with what was written in windows of this many bytes.
+///
+/// A mismatch is reported with the window it falls in, and every window keeps a
+/// 32 byte sha-256 until the read-back, 4 KiB per GiB written.
+pub const VERIFY_WRITES_WINDOW: u64 = 8 * 1024 * 1024;
+
+/// Default size of the byte ranges a multipart download is split into.
+///
+/// Large enough that the request per part is negligible next to its transfer,
+/// small enough that a file of a few hundred MiB gets as many parts as there
+/// are concurrent connections.
+pub const PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Default of `--max-parts`, the most parts a download is split into before
+/// `--part-size` is raised.
+///
+/// Bounds the plan and the requests of a tiny `--part-size` on a large file:
+/// 2048 parts of the default size cover 32 GiB.
+pub const MAX_PARTS: u64 = 2048;
+
+/// Positional writes of one file running on the blocking pool at the same time.
+///
+/// Each part of a multipart download hands every chunk to the blocking pool,
+/// which all downloads of the process share. Writing one file from more
+/// threads doesn't make it faster, so a download holds at most this many.
+pub const BLOCKING_WRITES_PER_FILE: usize = 2;
+
+/// Default number of parts of a multipart download fetched at the same time.
+pub const MAX_CONCURRENT_PARTS: usize = 4;
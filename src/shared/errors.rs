@@ -1,6 +1,16 @@
 use thiserror::Error;
 use anyhow::Error as anyhowError;
 
+/// Process exit codes and their meaning, reported by `cliant --capabilities`.
+///
+/// 1 is what `main` returning an error exits with, 2 is clap's usage error code.
+pub const EXIT_CODES: &[(i32, &str)] = &[
+    (0, "success"),
+    (1, "download failed"),
+    (2, "invalid command line arguments"),
+    (3, "the url is an HTML page, not a file"),
+];
+
 #[derive(Error, Debug)]
 pub enum CliantError {
     #[error("Network connection error: {0}")]
@@ -13,6 +23,52 @@ pub enum CliantError {
     #[error("Filesystem error: {0}")]
     Io(#[from] std::io::Error),
     
+    #[error("Proxy {proxy} failed: {reason}")]
+    Proxy { proxy: String, reason: String },
+
+    #[error("Unexpected response from {url}: {reason}")]
+    UnexpectedResponse { url: String, reason: String },
+
+    #[error("The server is reporting inconsistent sizes for {url}: {planned} bytes before the download, {actual} bytes in the download response")]
+    InconsistentSize { url: String, planned: u64, actual: u64 },
+
+    #[error("Got an HTML page, not a file, from {url}{}{}\nUse --allow-html to save the page anyway.", title_line(title.as_deref()), candidates_lines(candidates))]
+    HtmlPage {
+        url: String,
+        title: Option<String>,
+        /// Links of the page that look like files, already redacted.
+        candidates: Vec<String>,
+    },
+
+    #[error("{url} is a BitTorrent metadata file, which cliant does not support\nUse --save-metadata to save the .torrent file itself.")]
+    Torrent { url: String },
+
+    #[error("Can't follow the metalink {url}: {reason}\nUse --save-metadata to save the metalink itself.")]
+    Metalink { url: String, reason: String },
+
+    #[error("Not printing {url}: {reason}\nUse --force to print it anyway.")]
+    Unprintable { url: String, reason: String },
+
+    #[error("Can't print the last bytes of {url}: {reason}")]
+    TailUnavailable { url: String, reason: String },
+
+    #[error("Saved the page {url} without {failed} of its {total} assets, the page references them remotely")]
+    PageIncomplete { url: String, failed: usize, total: usize },
+
+    #[error("Post-process command {command:?} failed: {reason}")]
+    PostProcessFailed { command: String, reason: String },
+
+    #[error("Checksum mismatch: {origin} announced {algorithm} {expected}, the downloaded content hashes to {actual}")]
+    ChecksumMismatch {
+        algorithm: &'static str,
+        origin: &'static str,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Storage returned different data than written to {path} on {device}: {reason}")]
+    StorageMismatch { path: String, device: String, reason: String },
+
     #[error("Critical system failure: {0}")]
     Fatal(String),
 
@@ -21,4 +77,25 @@ pub enum CliantError {
     ///This will convert `anyhow::Error` to my error variants.
     #[error("An error occurred: {0}")]
     Error(#[from] anyhowError)
-}
\ No newline at end of file
+}
+impl CliantError {
+    /// Process exit code for this error, one of [`EXIT_CODES`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliantError::HtmlPage { .. } => 3,
+            _ => 1,
+        }
+    }
+}
+
+fn title_line(title: Option<&str>) -> String {
+    title.map(|title| format!("\nPage title: {title}")).unwrap_or_default()
+}
+
+fn candidates_lines(candidates: &[String]) -> String {
+    if candidates.is_empty() {
+        return "\nNo links to files were found in the page.".into();
+    }
+    let lines: Vec<String> = candidates.iter().map(|candidate| format!("\n  {candidate}")).collect();
+    format!("\nLinks that look like files:{}", lines.concat())
+}
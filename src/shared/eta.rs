@@ -0,0 +1,231 @@
+//! Remaining time estimation with confidence bounds.
+//!
+//! A point ETA computed from the mean transfer rate jumps around whenever the
+//! rate does. Alongside it we compute an interval from the spread of the
+//! recent rate samples: the optimistic bound uses the 90th percentile rate and
+//! the pessimistic bound the 10th, so a steady transfer produces a tight
+//! interval and a stalling one a wide interval.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two rate samples.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of rate samples kept, i.e the estimate reflects the last ~30 secs.
+pub const SAMPLE_WINDOW: usize = 30;
+/// Fewer samples than this give no estimate, the spread would be meaningless.
+pub const MIN_SAMPLES: usize = 3;
+/// Upper clamp for every ETA value (7 days), a stalled transfer would otherwise
+/// produce an infinite pessimistic bound.
+pub const MAX_ETA_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Ring buffer of transfer rate samples in bytes/sec.
+#[derive(Debug)]
+pub struct RateSamples {
+    samples: VecDeque<f64>,
+    last_sample: Option<(Instant, u64)>,
+}
+
+impl Default for RateSamples {
+    fn default() -> Self {
+        Self { samples: VecDeque::with_capacity(SAMPLE_WINDOW), last_sample: None }
+    }
+}
+
+impl RateSamples {
+    /// Records the number of bytes transferred so far at `now`.
+    ///
+    /// A new rate sample is pushed at most once per `SAMPLE_INTERVAL`, returns
+    /// `true` when that happened.
+    pub fn observe(&mut self, now: Instant, bytes_done: u64) -> bool {
+        let Some((last_instant, last_bytes)) = self.last_sample else {
+            self.last_sample = Some((now, bytes_done));
+            return false;
+        };
+        let elapsed = now.saturating_duration_since(last_instant);
+        if elapsed < SAMPLE_INTERVAL {
+            return false;
+        }
+        let rate = bytes_done.saturating_sub(last_bytes) as f64 / elapsed.as_secs_f64();
+        if self.samples.len() == SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rate);
+        self.last_sample = Some((now, bytes_done));
+        true
+    }
+
+    /// Estimate for `remaining_bytes` based on the current sample window.
+    pub fn estimate(&self, remaining_bytes: u64) -> Option<EtaEstimate> {
+        let samples: Vec<f64> = self.samples.iter().copied().collect();
+        estimate(remaining_bytes, &samples)
+    }
+}
+
+/// Remaining time in seconds with its confidence interval,
+/// `eta_secs_low <= eta_secs <= eta_secs_high` always holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EtaEstimate {
+    pub eta_secs: u64,
+    pub eta_secs_low: u64,
+    pub eta_secs_high: u64,
+}
+
+impl EtaEstimate {
+    /// Compact form for the progress bar e.g `ETA 7–12m`.
+    pub fn compact(&self) -> String {
+        let (low, high) = (self.eta_secs_low, self.eta_secs_high);
+        let (divisor, unit) = match high {
+            0..60 => (1, "s"),
+            60..3600 => (60, "m"),
+            _ => (3600, "h"),
+        };
+        let (low, high) = (low / divisor, high.div_ceil(divisor));
+        if low == high {
+            format!("ETA {high}{unit}")
+        } else {
+            format!("ETA {low}–{high}{unit}")
+        }
+    }
+}
+
+/// Computes the ETA and its bounds from rate samples in bytes/sec.
+///
+/// Returns `None` with fewer than `MIN_SAMPLES` samples or when every sample
+/// is zero, there is nothing to extrapolate from in both cases.
+pub fn estimate(remaining_bytes: u64, rate_samples: &[f64]) -> Option<EtaEstimate> {
+    if rate_samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut sorted: Vec<f64> = rate_samples
+        .iter()
+        .map(|rate| if rate.is_finite() { rate.max(0.0) } else { 0.0 })
+        .collect();
+    sorted.sort_by(f64::total_cmp);
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+    let eta_secs = secs_at(remaining_bytes, mean);
+    let eta_secs_low = secs_at(remaining_bytes, percentile(&sorted, 90)).min(eta_secs);
+    let eta_secs_high = secs_at(remaining_bytes, percentile(&sorted, 10)).max(eta_secs);
+    Some(EtaEstimate { eta_secs, eta_secs_low, eta_secs_high })
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[f64], pct: usize) -> f64 {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn secs_at(remaining_bytes: u64, rate: f64) -> u64 {
+    if rate <= 0.0 {
+        return MAX_ETA_SECS;
+    }
+    ((remaining_bytes as f64 / rate).ceil() as u64).min(MAX_ETA_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    fn assert_ordered(eta: &EtaEstimate) {
+        assert!(
+            eta.eta_secs_low <= eta.eta_secs && eta.eta_secs <= eta.eta_secs_high,
+            "Bounds must be ordered low <= point <= high: {eta:?}"
+        );
+    }
+
+    #[test]
+    fn test_too_few_samples_give_no_estimate() {
+        assert_eq!(estimate(1024, &[]), None);
+        assert_eq!(estimate(1024, &[MIB, MIB]), None);
+    }
+
+    #[test]
+    fn test_zero_rates_give_no_estimate() {
+        assert_eq!(estimate(1024, &[0.0, 0.0, 0.0, 0.0]), None);
+        assert_eq!(estimate(1024, &[f64::NAN, 0.0, -1.0]), None);
+    }
+
+    #[test]
+    fn test_steady_transfer_has_tight_interval() {
+        let samples = vec![MIB; SAMPLE_WINDOW];
+        let eta = estimate(60 * 1024 * 1024, &samples).unwrap();
+        assert_ordered(&eta);
+        assert_eq!(eta.eta_secs, 60);
+        assert_eq!(eta.eta_secs_low, eta.eta_secs_high);
+    }
+
+    #[test]
+    fn test_stall_widens_interval() {
+        let remaining = 60 * 1024 * 1024;
+        let mut samples = vec![MIB; 20];
+        let steady = estimate(remaining, &samples).unwrap();
+        samples.extend([0.0; 5]);
+        let stalled = estimate(remaining, &samples).unwrap();
+        assert_ordered(&stalled);
+        assert!(
+            stalled.eta_secs_high - stalled.eta_secs_low
+                > steady.eta_secs_high - steady.eta_secs_low,
+            "A stall should widen the interval: {steady:?} vs {stalled:?}"
+        );
+        // A rate of zero at the 10th percentile is clamped, not infinite.
+        assert_eq!(stalled.eta_secs_high, MAX_ETA_SECS);
+    }
+
+    #[test]
+    fn test_bounds_follow_percentiles() {
+        let samples: Vec<f64> = (1..=10).map(|i| i as f64 * MIB).collect();
+        let eta = estimate(90 * 1024 * 1024, &samples).unwrap();
+        assert_ordered(&eta);
+        assert_eq!(eta.eta_secs_low, 10); // 9 MiB/s
+        assert_eq!(eta.eta_secs_high, 90); // 1 MiB/s
+        assert_eq!(eta.eta_secs, 17); // mean 5.5 MiB/s
+    }
+
+    #[test]
+    fn test_samples_are_rate_limited_and_bounded() {
+        let start = Instant::now();
+        let mut rates = RateSamples::default();
+        assert!(!rates.observe(start, 0));
+        assert!(!rates.observe(start + Duration::from_millis(500), 512));
+        assert!(rates.observe(start + Duration::from_secs(1), 1024));
+        assert_eq!(rates.samples, [1024.0]);
+        for i in 2..=(SAMPLE_WINDOW as u64 + 5) {
+            rates.observe(start + Duration::from_secs(i), i * 1024);
+        }
+        assert_eq!(rates.samples.len(), SAMPLE_WINDOW);
+        let eta = rates.estimate(10 * 1024).unwrap();
+        assert_eq!(eta.eta_secs, 10);
+    }
+
+    proptest::proptest! {
+        /// Sizes far beyond 4 GiB keep the bounds ordered and clamped.
+        #[test]
+        fn test_bounds_hold_for_huge_sizes(
+            remaining in 0u64..(1 << 50),
+            rates in proptest::collection::vec(0.0f64..1e10, MIN_SAMPLES..=SAMPLE_WINDOW),
+        ) {
+            if let Some(eta) = estimate(remaining, &rates) {
+                assert_ordered(&eta);
+                proptest::prop_assert!(eta.eta_secs_high <= MAX_ETA_SECS);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_format() {
+        let eta = |low, point, high| EtaEstimate {
+            eta_secs: point,
+            eta_secs_low: low,
+            eta_secs_high: high,
+        };
+        assert_eq!(eta(420, 500, 720).compact(), "ETA 7–12m");
+        assert_eq!(eta(30, 40, 45).compact(), "ETA 30–45s");
+        assert_eq!(eta(60, 60, 60).compact(), "ETA 1m");
+        assert_eq!(eta(3600, 5000, 9000).compact(), "ETA 1–3h");
+    }
+}
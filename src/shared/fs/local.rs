@@ -1,11 +1,11 @@
 #![allow(unused)]
 use bytes::Bytes;
 use opendal::{Operator, Writer, services};
-use std::{path::PathBuf, sync::Arc};
+use std::{fs::FileTimes, path::PathBuf, sync::Arc, time::{Instant, SystemTime}};
 use tokio::sync::Mutex;
 use tracing::{debug, error, instrument::{self, WithSubscriber}, trace,};
 
-use crate::shared::{errors::CliantError, fs::FsOps};
+use crate::shared::{blocking_guard, constants::BLOCKING_WRITES_PER_FILE, errors::CliantError, fs::{FsOps, stats::WriterStats}};
 
 pub struct LocalFsBuilder {
     root_path: Option<PathBuf>,
@@ -48,12 +48,25 @@ impl LocalFsBuilder {
             .await
             .map_err(|err| CliantError::Io(err.into()))?;
         op.with_current_subscriber();
-        Ok(LocalFs { writer: Arc::new(Mutex::new(writer)) })
+        Ok(LocalFs {
+            writer: Arc::new(Mutex::new(writer)),
+            path: root_path.join(path),
+            positional: std::sync::OnceLock::new(),
+            blocking_writes: tokio::sync::Semaphore::new(BLOCKING_WRITES_PER_FILE),
+            stats: std::sync::Mutex::default(),
+        })
     }
 }
 
 pub struct LocalFs {
     writer: Arc<Mutex<Writer>>,
+    /// Absolute path of the written file.
+    path: PathBuf,
+    /// Handle of the file for `write_at`, opened on first use next to the opendal writer.
+    positional: std::sync::OnceLock<Arc<std::fs::File>>,
+    /// Caps the `write_at` calls on the blocking pool, see [`BLOCKING_WRITES_PER_FILE`].
+    blocking_writes: tokio::sync::Semaphore,
+    stats: std::sync::Mutex<WriterStats>,
 }
 
 impl FsOps for LocalFs {
@@ -61,40 +74,122 @@ impl FsOps for LocalFs {
     ///
     /// **NB:** If  this method is called in different threads only one thread can write to the
     /// in-memory buffer at a time while other threads block.
-    /// Rememeber to call `close_fs` after appending every chunk of bytes.
+    /// Rememeber to call `close_fs` after appending every chunk of bytes, the
+    /// buffered bytes are only written there and its error must be checked too.
     #[tracing::instrument(name="append_bytes_to_handle",skip(self,bytes))]
     async fn append_bytes(&self, bytes: Bytes) -> Result<(), CliantError> {
         let byte_length=bytes.len();
         trace!("Writing bytes of length {} to file handle ...",byte_length);
-        let _ = self.writer.lock().await.write(bytes).await;
+        let waiting = Instant::now();
+        let mut writer = self.writer.lock().await;
+        self.stats.lock().unwrap().observe_write(byte_length, waiting.elapsed());
+        writer.write(bytes).await.map_err(|err| CliantError::Io(err.into()))?;
         trace!("Wrote {} successfully to handle.",byte_length);
         Ok(())
     }
+
+    /// Writes through a std handle on the blocking pool: opendal's writer only
+    /// appends. The file was created (and truncated) when the writer was built,
+    /// so `close_fs` afterwards only syncs it. At most [`BLOCKING_WRITES_PER_FILE`]
+    /// writes run at once, concurrent parts wait for their turn.
+    #[tracing::instrument(name="write_bytes_at",skip(self,bytes))]
+    async fn write_at(&self, offset: u64, bytes: Bytes) -> Result<(), CliantError> {
+        let file = match self.positional.get() {
+            Some(file) => file.clone(),
+            None => {
+                let path = self.path.clone();
+                let file = blocking_guard::run("Opening the file for positional writes", move || {
+                    std::fs::File::options().write(true).open(path)
+                })
+                .await?;
+                self.positional.get_or_init(|| Arc::new(file)).clone()
+            }
+        };
+        let byte_length = bytes.len();
+        trace!("Writing bytes of length {} at offset {} ...", byte_length, offset);
+        let waiting = Instant::now();
+        let _slot = self.blocking_writes.acquire().await.map_err(|err| CliantError::Fatal(format!("Waiting to write at an offset: {err}")))?;
+        self.stats.lock().unwrap().observe_write(byte_length, waiting.elapsed());
+        blocking_guard::run("Writing at an offset", move || write_all_at(&file, &bytes, offset)).await
+    }
+
+    fn supports_write_at(&self) -> bool {
+        Self::WRITES_AT_OFFSETS
+    }
+
+    #[tracing::instrument(name="set_file_mtime",skip(self))]
+    async fn set_mtime(&self, mtime: SystemTime) -> Result<(), CliantError> {
+        let path = self.path.clone();
+        blocking_guard::run("Setting file times", move || {
+            let times = FileTimes::new().set_modified(mtime).set_accessed(mtime);
+            std::fs::File::options().write(true).open(&path)?.set_times(times)
+        })
+        .await?;
+        debug!("Set modification time of {} to {:?}", self.path.display(), mtime);
+        Ok(())
+    }
 }
 
 impl LocalFs {
+    /// Whether `write_at` is supported, known before any file is opened for `--capabilities`.
+    pub const WRITES_AT_OFFSETS: bool = true;
+
     ///Call this method after appending every chunk of bytes.
-    ///this method will flush the in-memory buffer to the File system.
+    ///this method will flush the in-memory buffer to the File system,
+    /// a failure (e.g a full disk) means the file is incomplete.
     #[forbid(dead_code)]
     #[tracing::instrument(name="close_file_handle",skip(self))]
-    pub async fn close_fs(&self) {
+    pub async fn close_fs(&self) -> Result<(), CliantError> {
         let mut writer = self.writer.lock().await;
         debug!("Closing File handle,flushing in-memory buffers...");
-        if let Err(err) = writer.close().await {
-            error!(
-                "Can't close opendal writer while dropping LocalFs an error occurred, writer has been closed already. :{err}"
-            );
+        self.stats.lock().unwrap().observe_flush();
+        let closed = writer.close().await;
+        let Some(file) = self.positional.get().cloned() else {
+            return closed.map_err(|err| {
+                error!("Can't flush and close {}: {err}", self.path.display());
+                CliantError::Io(err.into())
+            }).map(|_| ());
+        };
+        // The parts bypassed the opendal writer, whose check that it wrote the
+        // whole file fails. Their errors show when the file is synced.
+        if let Err(err) = closed {
+            debug!("Ignoring the close of the opendal writer after positional writes: {err}");
         }
+        blocking_guard::run("Syncing the file", move || file.sync_all()).await
+    }
+
+    /// What the writer did so far.
+    pub fn writer_stats(&self) -> WriterStats {
+        self.stats.lock().unwrap().clone()
     }
 }
 
+#[cfg(unix)]
+fn write_all_at(file: &std::fs::File, bytes: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(bytes, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &std::fs::File, mut bytes: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !bytes.is_empty() {
+        let written = file.seek_write(bytes, offset)?;
+        bytes = &bytes[written..];
+        offset += written as u64;
+    }
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_local_fs() -> anyhow::Result<()> {
+    use async_tempfile::TempDir;
     use tokio::sync::Semaphore;
 
+    let temp_dir = TempDir::new().await?;
     let localfs = LocalFsBuilder::new()
         .file_name(PathBuf::from("non_existent.txt"))
-        .root_path(PathBuf::from("/home/val/Documents/"))
+        .root_path(temp_dir.dir_path().clone())
         .build()
         .await?;
     let localfs_arc = Arc::new(localfs);
@@ -117,7 +212,65 @@ async fn test_local_fs() -> anyhow::Result<()> {
         task.await?;
     }
 
-    localfs_arc.close_fs().await; // close writer and flush buffer after all write tasks have been completed
+    localfs_arc.close_fs().await?; // close writer and flush buffer after all write tasks have been completed
+    let stats = localfs_arc.writer_stats();
+    assert_eq!((stats.writes, stats.flushes), (1000, 1));
+    assert_eq!(stats.write_sizes[0], 1000);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_write_at_fills_the_file_out_of_order() -> anyhow::Result<()> {
+    use async_tempfile::TempDir;
+
+    let temp_dir = TempDir::new().await?;
+    let localfs = LocalFsBuilder::new()
+        .file_name(PathBuf::from("parts.bin"))
+        .root_path(temp_dir.dir_path().clone())
+        .build()
+        .await?;
+    assert!(localfs.supports_write_at());
+    localfs.write_at(6, Bytes::from_static(b"world")).await?;
+    localfs.write_at(0, Bytes::from_static(b"hello ")).await?;
+    localfs.close_fs().await?;
+    assert_eq!(tokio::fs::read(temp_dir.dir_path().join("parts.bin")).await?, b"hello world");
+    assert_eq!(localfs.writer_stats().writes, 2);
+    Ok(())
+}
+#[tokio::test]
+async fn test_write_at_waits_for_a_blocking_slot() -> anyhow::Result<()> {
+    use async_tempfile::TempDir;
+    use std::time::Duration;
+
+    let temp_dir = TempDir::new().await?;
+    let localfs = LocalFsBuilder::new()
+        .file_name(PathBuf::from("slots.bin"))
+        .root_path(temp_dir.dir_path().clone())
+        .build()
+        .await?;
+    // Other parts of the download hold every slot.
+    let held = localfs.blocking_writes.acquire_many(BLOCKING_WRITES_PER_FILE as u32).await?;
+    let write = localfs.write_at(0, Bytes::from_static(b"hello"));
+    tokio::pin!(write);
+    assert!(tokio::time::timeout(Duration::from_millis(100), &mut write).await.is_err(), "Wrote without a free slot");
+    drop(held);
+    write.await?;
+    localfs.close_fs().await?;
+    assert_eq!(tokio::fs::read(temp_dir.dir_path().join("slots.bin")).await?, b"hello");
+    Ok(())
+}
+/// A full disk fails the write that flushes the buffer, or at the latest the close that flushes the rest.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_write_failures_are_returned() -> anyhow::Result<()> {
+    for len in [8, 9 * 1024 * 1024] {
+        let localfs = LocalFsBuilder::new().file_name(PathBuf::from("full")).root_path(PathBuf::from("/dev")).build().await?;
+        let written = async {
+            localfs.append_bytes(Bytes::from(vec![0u8; len])).await?;
+            localfs.close_fs().await
+        };
+        let err = written.await.unwrap_err();
+        assert!(err.to_string().contains("No space left on device"), "{len} bytes: {err}");
+    }
+    Ok(())
+}
@@ -1,5 +1,8 @@
 #[cfg(feature="local")]
 pub mod local;
+pub mod stats;
+
+use std::time::SystemTime;
 
 use bytes::Bytes;
 
@@ -7,4 +10,10 @@ use crate::shared::errors::CliantError;
 
 pub trait FsOps{
     async fn append_bytes(&self,bytes:Bytes)->Result<(),CliantError>;
+    ///Writes `bytes` at `offset` of the file, for parts of a download written out of order.
+    async fn write_at(&self,offset:u64,bytes:Bytes)->Result<(),CliantError>;
+    ///Whether `write_at` is supported, multipart downloads need it.
+    fn supports_write_at(&self)->bool;
+    ///Sets the modification and access time of the written file, call it after the file is closed.
+    async fn set_mtime(&self,mtime:SystemTime)->Result<(),CliantError>;
 } 
\ No newline at end of file
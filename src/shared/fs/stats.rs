@@ -0,0 +1,74 @@
+//! What the file writer did during a download, for judging the cost of the
+//! write path before and after changing it.
+//!
+//! A write is one call into the writer: opendal batches them into syscalls of
+//! up to 4 MiB, so the count is an upper bound on write syscalls. Lock wait is
+//! the time spent acquiring the writer's mutex, which stays near zero while a
+//! single task writes and grows once several tasks share a writer.
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Upper bounds of the `write_sizes` buckets, the last bucket has none.
+const WRITE_SIZE_BOUNDS: [u64; 3] = [4 * 1024, 64 * 1024, 1024 * 1024];
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct WriterStats {
+    pub writes: u64,
+    pub bytes: u64,
+    /// Writes under 4 KiB, under 64 KiB, under 1 MiB and of 1 MiB or more.
+    pub write_sizes: [u64; 4],
+    pub flushes: u64,
+    pub lock_wait: Duration,
+}
+
+impl WriterStats {
+    /// Records a write of `len` bytes that waited `lock_wait` for the writer.
+    pub fn observe_write(&mut self, len: usize, lock_wait: Duration) {
+        let len = len as u64;
+        self.writes += 1;
+        self.bytes += len;
+        let bucket = WRITE_SIZE_BOUNDS.iter().position(|&bound| len < bound).unwrap_or(WRITE_SIZE_BOUNDS.len());
+        self.write_sizes[bucket] += 1;
+        self.lock_wait += lock_wait;
+    }
+
+    pub fn observe_flush(&mut self) {
+        self.flushes += 1;
+    }
+}
+
+/// `writer: 812 writes, avg 161 KiB, 1 flushes, 2.3ms total lock wait`.
+impl fmt::Display for WriterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let average = self.bytes.checked_div(self.writes).unwrap_or_default();
+        let average = if average < 1024 { format!("{average} B") } else { format!("{} KiB", average / 1024) };
+        write!(
+            f,
+            "writer: {} writes, avg {average}, {} flushes, {:?} total lock wait",
+            self.writes, self.flushes, self.lock_wait
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_scripted_writes() {
+        let mut stats = WriterStats::default();
+        for (len, wait_ms) in [(100, 0), (4 * 1024, 1), (64 * 1024, 0), (2 * 1024 * 1024, 2)] {
+            stats.observe_write(len, Duration::from_millis(wait_ms));
+        }
+        stats.observe_flush();
+        assert_eq!(stats.writes, 4);
+        assert_eq!(stats.bytes, 100 + 4 * 1024 + 64 * 1024 + 2 * 1024 * 1024);
+        assert_eq!(stats.write_sizes, [1, 1, 1, 1]);
+        assert_eq!(stats.lock_wait, Duration::from_millis(3));
+        assert_eq!(stats.to_string(), "writer: 4 writes, avg 529 KiB, 1 flushes, 3ms total lock wait");
+        assert_eq!(WriterStats::default().to_string(), "writer: 0 writes, avg 0 B, 0 flushes, 0ns total lock wait");
+    }
+}
@@ -0,0 +1,152 @@
+//! Streaming gzip decompression for content that arrives in chunks.
+//!
+//! Only the first gzip member is decoded, which is what `.gz` files and
+//! `Content-Encoding: gzip` bodies consist of. The trailer (CRC32 and size) is
+//! not checked: truncated input is exactly what `cat --head-bytes` produces,
+//! and integrity is the job of the checksum verification.
+
+use miniz_oxide::inflate::stream::{InflateState, inflate};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use super::errors::CliantError;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Output produced per inflate call, grown content is split into several calls.
+const OUTPUT_CHUNK: usize = 64 * 1024;
+
+enum State {
+    /// Accumulating the member header, its length isn't known up front.
+    Header(Vec<u8>),
+    Body(Box<InflateState>),
+    Done,
+}
+
+pub struct GzipDecoder {
+    state: State,
+}
+
+impl Default for GzipDecoder {
+    fn default() -> Self {
+        Self { state: State::Header(Vec::new()) }
+    }
+}
+
+impl GzipDecoder {
+    /// Decompresses the next chunk of input, returning the output it completes.
+    pub fn push(&mut self, input: &[u8]) -> Result<Vec<u8>, CliantError> {
+        let mut input = input;
+        if let State::Header(buffered) = &mut self.state {
+            buffered.extend_from_slice(input);
+            let Some(len) = header_len(buffered)? else {
+                return Ok(Vec::new());
+            };
+            let body = buffered.split_off(len);
+            self.state = State::Body(InflateState::new_boxed(DataFormat::Raw));
+            return self.push(&body);
+        }
+        let State::Body(state) = &mut self.state else {
+            // Anything after the first member (trailer, padding) is ignored.
+            return Ok(Vec::new());
+        };
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; OUTPUT_CHUNK];
+        loop {
+            let result = inflate(state, input, &mut buf, MZFlush::None);
+            input = &input[result.bytes_consumed..];
+            output.extend_from_slice(&buf[..result.bytes_written]);
+            match result.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.state = State::Done;
+                    return Ok(output);
+                }
+                // No progress possible until more input arrives.
+                Err(MZError::Buf) => return Ok(output),
+                Ok(_) if input.is_empty() && result.bytes_written < buf.len() => return Ok(output),
+                Ok(_) => {}
+                Err(err) => {
+                    return Err(CliantError::ParseError(format!("Invalid gzip data: {err:?}")));
+                }
+            }
+        }
+    }
+}
+
+/// Length of the gzip member header at the start of `buf`, `None` while incomplete.
+fn header_len(buf: &[u8]) -> Result<Option<usize>, CliantError> {
+    if buf.len() < 10 {
+        return Ok(None);
+    }
+    if buf[..3] != [0x1f, 0x8b, 8] {
+        return Err(CliantError::ParseError("Not gzip data (bad magic bytes)".into()));
+    }
+    let flags = buf[3];
+    let mut len = 10;
+    if flags & FEXTRA != 0 {
+        let Some(extra) = buf.get(len..len + 2) else { return Ok(None) };
+        len += 2 + usize::from(u16::from_le_bytes([extra[0], extra[1]]));
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let Some(end) = buf.get(len..).and_then(|rest| rest.iter().position(|&byte| byte == 0)) else {
+                return Ok(None);
+            };
+            len += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+    Ok((buf.len() >= len).then_some(len))
+}
+
+/// Gzip member of `data` with a file name in its header, for tests.
+#[cfg(test)]
+pub(crate) fn gzip_fixture(data: &[u8]) -> Vec<u8> {
+    let mut gz = vec![0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 0xff];
+    gz.extend_from_slice(b"fixture.log\0");
+    gz.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+    // The CRC32 is left zeroed, the decoder doesn't check it.
+    gz.extend_from_slice(&[0, 0, 0, 0]);
+    gz.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+    gz
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_lines() -> Vec<u8> {
+        (0..20_000).map(|i| format!("line {i}: something happened\n")).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn test_decodes_across_any_chunking() {
+        let data = log_lines();
+        let gz = gzip_fixture(&data);
+        for chunk_size in [1, 7, 4096, gz.len()] {
+            let mut decoder = GzipDecoder::default();
+            let mut output = Vec::new();
+            for chunk in gz.chunks(chunk_size) {
+                output.extend(decoder.push(chunk).unwrap());
+            }
+            assert_eq!(output, data, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_truncated_input_yields_a_prefix() {
+        let data = log_lines();
+        let gz = gzip_fixture(&data);
+        let output = GzipDecoder::default().push(&gz[..gz.len() / 2]).unwrap();
+        assert!(!output.is_empty() && data.starts_with(&output));
+    }
+
+    #[test]
+    fn test_rejects_non_gzip_data() {
+        assert!(GzipDecoder::default().push(b"plain text, not gzip").is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! Recognizing HTML pages downloaded by mistake, e.g a release page instead of
+//! one of its assets, and pointing at the files they link to.
+//!
+//! The scanning is deliberately shallow (no DOM, no scripts): tags and their
+//! attributes are found by a small tokenizer that skips comments and the
+//! content of `<script>`/`<style>` elements, which is enough for the title,
+//! the links and the assets of a page.
+
+use std::ops::Range;
+
+use url::Url;
+
+use super::network::DownloadInfo;
+
+/// Bytes of a page read to find its title and links.
+pub const SNIFF_BYTES: usize = 64 * 1024;
+
+/// Larger HTML responses are assumed to be wanted, e.g an archived page.
+pub const MAX_PAGE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Candidate links shown at most.
+pub const MAX_CANDIDATES: usize = 10;
+
+/// Extensions of links that look like downloadable files rather than pages.
+const FILE_EXTENSIONS: &[&str] = &[
+    "zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "exe", "msi", "dmg", "pkg",
+    "deb", "rpm", "apk", "appimage", "iso", "img", "jar", "whl", "bin", "sig", "sha256",
+];
+
+/// Whether a response looks like a page the user didn't mean to save: HTML,
+/// not offered as a file through `Content-Disposition`, and not huge.
+pub fn looks_like_page(info: &DownloadInfo) -> bool {
+    is_html(info)
+        && info.content_disposition.is_none()
+        && info.total_bytes.is_none_or(|size| size <= MAX_PAGE_BYTES)
+}
+
+/// Whether the content is an HTML document.
+pub fn is_html(info: &DownloadInfo) -> bool {
+    info.content_type.as_deref().is_some_and(|content_type| {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        mime == "text/html" || mime == "application/xhtml+xml"
+    })
+}
+
+/// Text of the first `<title>` element, whitespace collapsed.
+pub fn page_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(&html[start..end].split_whitespace().collect::<Vec<_>>().join(" "));
+    (!title.is_empty()).then_some(title)
+}
+
+/// Values of every `href` attribute, in document order.
+pub fn hrefs(html: &str) -> Vec<String> {
+    tags(html)
+        .iter()
+        .filter_map(|tag| tag.attribute(html, "href"))
+        .filter(|value| !value.is_empty())
+        .map(decode_entities)
+        .collect()
+}
+
+/// A `src`/`href` attribute value referencing an asset of the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requisite {
+    /// The attribute value, entities decoded.
+    pub value: String,
+    /// Byte range of the raw value in the page, quotes excluded.
+    pub span: Range<usize>,
+}
+
+/// References to what the page needs to render: `<img src>`,
+/// `<link rel=stylesheet href>` and `<script src>`, in document order.
+pub fn requisites(html: &str) -> Vec<Requisite> {
+    let mut requisites = Vec::new();
+    for tag in tags(html) {
+        let wanted = match tag.name.as_str() {
+            "img" | "script" => "src",
+            "link" if tag.attribute(html, "rel").is_some_and(|rel| {
+                rel.split_ascii_whitespace().any(|token| token.eq_ignore_ascii_case("stylesheet"))
+            }) =>
+            {
+                "href"
+            }
+            _ => continue,
+        };
+        if let Some(span) = tag.value_span(wanted)
+            && !html[span.clone()].trim().is_empty()
+        {
+            requisites.push(Requisite { value: decode_entities(html[span.clone()].trim()), span });
+        }
+    }
+    requisites
+}
+
+/// Links of the page that look like files, resolved against `base`, deduplicated.
+pub fn candidate_downloads(html: &str, base: &Url) -> Vec<Url> {
+    let mut candidates: Vec<Url> = Vec::new();
+    for href in hrefs(html) {
+        let Ok(url) = base.join(&href) else { continue };
+        let extension = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        let is_file = extension.is_some_and(|extension| FILE_EXTENSIONS.contains(&extension.as_str()));
+        if is_file && matches!(url.scheme(), "http" | "https") && !candidates.contains(&url) {
+            candidates.push(url);
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+    }
+    candidates
+}
+
+struct Tag {
+    /// Lowercased tag name.
+    name: String,
+    /// Lowercased attribute names and the byte range of their value, if any.
+    attributes: Vec<(String, Option<Range<usize>>)>,
+}
+
+impl Tag {
+    fn value_span(&self, name: &str) -> Option<Range<usize>> {
+        self.attributes.iter().find(|(attribute, _)| attribute == name).and_then(|(_, span)| span.clone())
+    }
+
+    fn attribute<'a>(&self, html: &'a str, name: &str) -> Option<&'a str> {
+        self.value_span(name).map(|span| &html[span])
+    }
+}
+
+/// Opening tags of the page in document order.
+///
+/// Comments and the raw text of `<script>` and `<style>` elements are
+/// skipped, markup in them isn't part of the document.
+fn tags(html: &str) -> Vec<Tag> {
+    let lower = html.to_ascii_lowercase();
+    let mut tags = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = html[pos..].find('<') {
+        let start = pos + found;
+        if html[start..].starts_with("<!--") {
+            pos = html[start..].find("-->").map_or(html.len(), |end| start + end + "-->".len());
+            continue;
+        }
+        let Some((tag, end)) = parse_tag(html, start) else {
+            pos = start + 1;
+            continue;
+        };
+        pos = end;
+        if matches!(tag.name.as_str(), "script" | "style") {
+            let close = format!("</{}", tag.name);
+            pos = lower[pos..].find(&close).map_or(html.len(), |found| pos + found);
+        }
+        tags.push(tag);
+    }
+    tags
+}
+
+/// Parses the opening tag at `start`, returns it with the position after its `>`.
+fn parse_tag(html: &str, start: usize) -> Option<(Tag, usize)> {
+    let bytes = html.as_bytes();
+    let is_space = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_whitespace);
+    let mut i = start + 1;
+    if !bytes.get(i)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while bytes.get(i).is_some_and(u8::is_ascii_alphanumeric) {
+        i += 1;
+    }
+    let name = html[start + 1..i].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    loop {
+        while is_space(i) || bytes.get(i) == Some(&b'/') {
+            i += 1;
+        }
+        if *bytes.get(i)? == b'>' {
+            return Some((Tag { name, attributes }, i + 1));
+        }
+        let name_start = i;
+        while bytes.get(i).is_some_and(|&byte| !byte.is_ascii_whitespace() && !matches!(byte, b'=' | b'>' | b'/')) {
+            i += 1;
+        }
+        let attribute = html[name_start..i].to_ascii_lowercase();
+        while is_space(i) {
+            i += 1;
+        }
+        if bytes.get(i) != Some(&b'=') {
+            // A stray `=` can't start a name, skip it.
+            i += usize::from(attribute.is_empty());
+            attributes.push((attribute, None));
+            continue;
+        }
+        i += 1;
+        while is_space(i) {
+            i += 1;
+        }
+        let span = match *bytes.get(i)? {
+            quote @ (b'"' | b'\'') => {
+                let end = i + 1 + html[i + 1..].find(char::from(quote))?;
+                let span = i + 1..end;
+                i = end + 1;
+                span
+            }
+            _ => {
+                let value_start = i;
+                while bytes.get(i).is_some_and(|&byte| !byte.is_ascii_whitespace() && byte != b'>') {
+                    i += 1;
+                }
+                value_start..i
+            }
+        };
+        attributes.push((attribute, Some(span)));
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+pub(crate) const RELEASE_PAGE_FIXTURE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>
+    Release v2.4.1 &amp; notes · example/tool
+  </title>
+  <link href="/assets/site.css" rel="stylesheet">
+</head>
+<body>
+  <a href="/example/tool/releases">All releases</a>
+  <a class="asset" href="/example/tool/releases/download/v2.4.1/tool-v2.4.1-x86_64-linux.tar.gz">Linux</a>
+  <a class="asset" href='/example/tool/releases/download/v2.4.1/tool-v2.4.1-aarch64-macos.zip'>macOS</a>
+  <a class="asset" href=tool-v2.4.1-windows.exe>Windows</a>
+  <a href="https://mirror.example.org/tool/tool-v2.4.1.tar.gz.sha256">Checksum</a>
+  <a href="/example/tool/releases/download/v2.4.1/tool-v2.4.1-x86_64-linux.tar.gz">Linux (again)</a>
+  <a href="mailto:maintainer@example.com?subject=bin.zip">Contact</a>
+  <p data-href="/not/a/link.zip">Decoy</p>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_title() {
+        assert_eq!(page_title(RELEASE_PAGE_FIXTURE).as_deref(), Some("Release v2.4.1 & notes · example/tool"));
+        assert_eq!(page_title("<html><body>no title</body></html>"), None);
+        assert_eq!(page_title("<TITLE></TITLE>"), None);
+    }
+
+    #[test]
+    fn test_candidate_downloads() -> anyhow::Result<()> {
+        let base = Url::parse("https://example.com/example/tool/releases/tag/v2.4.1")?;
+        let candidates: Vec<String> =
+            candidate_downloads(RELEASE_PAGE_FIXTURE, &base).into_iter().map(String::from).collect();
+        assert_eq!(
+            candidates,
+            [
+                "https://example.com/example/tool/releases/download/v2.4.1/tool-v2.4.1-x86_64-linux.tar.gz",
+                "https://example.com/example/tool/releases/download/v2.4.1/tool-v2.4.1-aarch64-macos.zip",
+                "https://example.com/example/tool/releases/tag/tool-v2.4.1-windows.exe",
+                "https://mirror.example.org/tool/tool-v2.4.1.tar.gz.sha256",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidates_are_capped() -> anyhow::Result<()> {
+        let html: String = (0..50).map(|i| format!("<a href=\"/f{i}.zip\">{i}</a>")).collect();
+        let base = Url::parse("https://example.com/")?;
+        assert_eq!(candidate_downloads(&html, &base).len(), MAX_CANDIDATES);
+        Ok(())
+    }
+
+    #[test]
+    fn test_requisites() {
+        let html = r#"<head>
+  <link rel="stylesheet" href="/site.css"><link rel=icon href=/favicon.ico>
+  <LINK REL="Alternate StyleSheet" HREF='/dark.css'>
+  <script src="/app.js?a=1&amp;b=2" defer></script>
+  <script>var html = '<img src="/inline.png">';</script>
+  <style>body { background: url("<img src=/style.png>") }</style>
+</head>
+<!-- <img src="/commented.png"> -->
+<img alt="a > b" data-src="/lazy.png" src=diagram.png><img src="">
+<a href="/next.html">next</a>"#;
+        let found = requisites(html);
+        let values: Vec<&str> = found.iter().map(|requisite| requisite.value.as_str()).collect();
+        assert_eq!(values, ["/site.css", "/dark.css", "/app.js?a=1&b=2", "diagram.png"]);
+        // Spans cover the raw value only.
+        assert_eq!(&html[found[2].span.clone()], "/app.js?a=1&amp;b=2");
+        assert_eq!(&html[found[3].span.clone()], "diagram.png");
+    }
+
+    #[test]
+    fn test_looks_like_page() {
+        let info = |content_type: &str, content_disposition: Option<&str>, total_bytes| DownloadInfo {
+            content_type: Some(content_type.into()),
+            content_disposition: content_disposition.map(Into::into),
+            total_bytes,
+            ..DownloadInfo::default()
+        };
+        assert!(looks_like_page(&info("text/html; charset=utf-8", None, Some(2048))));
+        assert!(looks_like_page(&info("TEXT/HTML", None, None)));
+        assert!(!looks_like_page(&info("text/html", Some("attachment; filename=page.html"), Some(2048))));
+        assert!(!looks_like_page(&info("text/html", None, Some(MAX_PAGE_BYTES + 1))));
+        assert!(!looks_like_page(&info("application/octet-stream", None, Some(2048))));
+        assert!(!looks_like_page(&DownloadInfo::default()));
+    }
+}
@@ -0,0 +1,241 @@
+//! Catalog of user-facing messages.
+//!
+//! Everything printed for the user (summaries, hints) goes through [`tr`] or
+//! [`tr_plural`] instead of `format!`, tracing logs stay in English. Templates
+//! are compiled in, placeholders are written `{name}` and plural forms are
+//! picked by each language's plural rule, never by string concatenation.
+//!
+//! The language is resolved once: `--lang` first, then `LC_ALL`,
+//! `LC_MESSAGES` and `LANG`, English otherwise. A key missing from a catalog
+//! falls back to English.
+
+use std::sync::OnceLock;
+
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Fr,
+}
+
+impl Lang {
+    pub const ALL: &[Lang] = &[Lang::En, Lang::Fr];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Fr => "fr",
+        }
+    }
+
+    /// Parses a locale like `fr`, `fr_FR.UTF-8` or `fr-CA`, `None` if unsupported.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let language = locale.split(['_', '-', '.', '@']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "fr" => Some(Lang::Fr),
+            _ => None,
+        }
+    }
+
+    fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or(Lang::En)
+    }
+
+    /// Whether `count` takes the singular form.
+    fn is_singular(self, count: u64) -> bool {
+        match self {
+            Lang::En => count == 1,
+            // French uses the singular for zero too.
+            Lang::Fr => count <= 1,
+        }
+    }
+}
+
+macro_rules! message_keys {
+    ($($key:ident),+ $(,)?) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum MessageKey { $($key),+ }
+
+        impl MessageKey {
+            #[cfg(test)]
+            pub const ALL: &[MessageKey] = &[$(MessageKey::$key),+];
+        }
+    };
+}
+
+message_keys! {
+    DownloadCompleted,
+    DownloadPath,
+    BytesWritten,
+    ChecksumVerified,
+    WritesVerified,
+    PhaseVerifying,
+    PhaseReadingBack,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Template {
+    Simple(&'static str),
+    Plural { one: &'static str, other: &'static str },
+}
+
+fn catalog(lang: Lang, key: MessageKey) -> Option<Template> {
+    use MessageKey::*;
+    use Template::*;
+    let template = match (lang, key) {
+        (Lang::En, DownloadCompleted) => Simple("Download '{name}' completed."),
+        (Lang::En, DownloadPath) => Simple("File path: {path}"),
+        (Lang::En, BytesWritten) => Plural { one: "{count} byte written", other: "{count} bytes written" },
+        (Lang::En, ChecksumVerified) => Simple("{algorithm} checksum verified against the server's {origin}."),
+        (Lang::En, WritesVerified) => Simple("Read {bytes} bytes back from disk in {millis} ms ({mode}), identical to what was written."),
+        (Lang::En, PhaseVerifying) => Simple("verifying"),
+        (Lang::En, PhaseReadingBack) => Simple("reading back"),
+
+        (Lang::Fr, DownloadCompleted) => Simple("Téléchargement de « {name} » terminé."),
+        (Lang::Fr, DownloadPath) => Simple("Chemin du fichier : {path}"),
+        (Lang::Fr, BytesWritten) => Plural { one: "{count} octet écrit", other: "{count} octets écrits" },
+        (Lang::Fr, ChecksumVerified) => Simple("Somme de contrôle {algorithm} vérifiée d'après l'en-tête {origin} du serveur."),
+        (Lang::Fr, WritesVerified) => Simple("{bytes} octets relus sur le disque en {millis} ms ({mode}), identiques à ceux écrits."),
+        (Lang::Fr, PhaseVerifying) => Simple("vérification"),
+        (Lang::Fr, PhaseReadingBack) => Simple("relecture"),
+    };
+    Some(template)
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Resolves the message language, `lang` is the `--lang` override.
+pub fn init(lang: Option<&str>) {
+    let resolved = lang.and_then(Lang::from_locale).unwrap_or_else(Lang::from_env);
+    debug!(?resolved, "Resolved message language");
+    let _ = LANG.set(resolved);
+}
+
+fn current() -> Lang {
+    *LANG.get_or_init(Lang::from_env)
+}
+
+/// Clap value parser for `--lang`.
+pub fn validate_lang(lang: &str) -> Result<String, String> {
+    match Lang::from_locale(lang) {
+        Some(_) => Ok(lang.to_string()),
+        None => {
+            let supported: Vec<&str> = Lang::ALL.iter().map(|lang| lang.code()).collect();
+            Err(format!("Unsupported language {lang}, supported languages are: {}", supported.join(", ")))
+        }
+    }
+}
+
+fn lookup(lang: Lang, key: MessageKey) -> Template {
+    catalog(lang, key)
+        .or_else(|| catalog(Lang::En, key))
+        .unwrap_or_else(|| panic!("{key:?} is missing from the English catalog"))
+}
+
+fn render(template: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(template.to_string(), |message, (name, value)| {
+        message.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+fn translate(lang: Lang, key: MessageKey, args: &[(&str, &str)]) -> String {
+    match lookup(lang, key) {
+        Template::Simple(template) | Template::Plural { other: template, .. } => render(template, args),
+    }
+}
+
+fn translate_plural(lang: Lang, key: MessageKey, count: u64, args: &[(&str, &str)]) -> String {
+    let count_str = count.to_string();
+    let mut args = args.to_vec();
+    args.push(("count", &count_str));
+    match lookup(lang, key) {
+        Template::Plural { one, .. } if lang.is_singular(count) => render(one, &args),
+        Template::Plural { other: template, .. } | Template::Simple(template) => render(template, &args),
+    }
+}
+
+/// Renders `key` in the current language.
+pub fn tr(key: MessageKey, args: &[(&str, &str)]) -> String {
+    translate(current(), key, args)
+}
+
+/// Renders `key` in the current language, choosing the plural form for `count`
+/// which is also available to the template as `{count}`.
+pub fn tr_plural(key: MessageKey, count: u64, args: &[(&str, &str)]) -> String {
+    translate_plural(current(), key, count, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(template: &str) -> BTreeSet<&str> {
+        template
+            .split('{')
+            .skip(1)
+            .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    fn forms(template: Template) -> Vec<&'static str> {
+        match template {
+            Template::Simple(template) => vec![template],
+            Template::Plural { one, other } => vec![one, other],
+        }
+    }
+
+    #[test]
+    fn test_every_key_is_in_english_catalog() {
+        for &key in MessageKey::ALL {
+            assert!(catalog(Lang::En, key).is_some(), "{key:?} is missing from the English catalog");
+        }
+    }
+
+    #[test]
+    fn test_placeholders_match_english() {
+        for &lang in Lang::ALL {
+            for &key in MessageKey::ALL {
+                let Some(template) = catalog(lang, key) else { continue };
+                let english = placeholders(forms(catalog(Lang::En, key).unwrap())[0]);
+                for form in forms(template) {
+                    assert_eq!(placeholders(form), english, "{lang:?} {key:?}: {form}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_plural_rules() {
+        let written = |lang, count| translate_plural(lang, MessageKey::BytesWritten, count, &[]);
+        assert_eq!(written(Lang::En, 0), "0 bytes written");
+        assert_eq!(written(Lang::En, 1), "1 byte written");
+        assert_eq!(written(Lang::En, 3), "3 bytes written");
+        assert_eq!(written(Lang::Fr, 0), "0 octet écrit");
+        assert_eq!(written(Lang::Fr, 1), "1 octet écrit");
+        assert_eq!(written(Lang::Fr, 3), "3 octets écrits");
+    }
+
+    #[test]
+    fn test_render_arguments() {
+        let message = translate(Lang::Fr, MessageKey::DownloadCompleted, &[("name", "a.zip")]);
+        assert_eq!(message, "Téléchargement de « a.zip » terminé.");
+    }
+
+    #[test]
+    fn test_locale_parsing() {
+        assert_eq!(Lang::from_locale("fr_FR.UTF-8"), Some(Lang::Fr));
+        assert_eq!(Lang::from_locale("fr-CA"), Some(Lang::Fr));
+        assert_eq!(Lang::from_locale("en_US"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("C"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("de_DE"), None);
+        assert!(validate_lang("de").is_err());
+    }
+}
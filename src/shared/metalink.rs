@@ -0,0 +1,352 @@
+//! Recognizing download metadata files, metalinks and BitTorrent `.torrent`
+//! files, which users fetch by mistake when they want the file they describe.
+//!
+//! Metalinks (RFC 5854 and the older 3.0 format) are followed: the file they
+//! describe is downloaded from its mirrors, preferred first, and verified
+//! against the strongest hash they list. Torrents are refused, Cliant doesn't
+//! speak BitTorrent.
+
+use std::path::Path;
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use url::Url;
+
+use super::checksum::{Checksum, ChecksumAlgorithm};
+use super::network::DownloadInfo;
+use super::network::factory::TransportType;
+
+/// Larger documents aren't metalinks worth following.
+pub const MAX_METALINK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Where the digests of a followed metalink come from.
+const ORIGIN: &str = "metalink";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataKind {
+    Metalink,
+    Torrent,
+}
+
+/// Recognizes a metadata file by the url's extension, the media type of the
+/// response or `head`, the first bytes of its content.
+pub fn metadata_kind(url: &Url, info: &DownloadInfo, head: &[u8]) -> Option<MetadataKind> {
+    let extension = url.path().rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    let mime = info
+        .content_type
+        .as_deref()
+        .map(|content_type| content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+    match (extension.as_deref(), mime.as_deref()) {
+        (Some("metalink" | "meta4"), _) | (_, Some("application/metalink4+xml" | "application/metalink+xml")) => {
+            Some(MetadataKind::Metalink)
+        }
+        (Some("torrent"), _) | (_, Some("application/x-bittorrent")) => Some(MetadataKind::Torrent),
+        // A bencoded dictionary starting with the keys every torrent has.
+        _ if head.starts_with(b"d8:announce") || head.starts_with(b"d13:announce-list") || head.starts_with(b"d4:info") => {
+            Some(MetadataKind::Torrent)
+        }
+        _ if looks_like_metalink(head) => Some(MetadataKind::Metalink),
+        _ => None,
+    }
+}
+
+/// An XML document whose root element is `metalink`.
+fn looks_like_metalink(head: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&head[..head.len().min(1024)]);
+    let trimmed = head.trim_start_matches('\u{feff}').trim_start();
+    trimmed.starts_with("<?xml") && head.contains("<metalink")
+}
+
+/// A file described by a metalink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetalinkFile {
+    /// Name of the file, possibly with directories, e.g `debian/netinst.iso`.
+    pub name: String,
+    pub size: Option<u64>,
+    /// The strongest of the listed hashes Cliant can verify.
+    pub checksum: Option<Checksum>,
+    /// Mirrors Cliant can fetch from, preferred first.
+    pub urls: Vec<Url>,
+}
+
+/// A mirror url and how much it is preferred, lower first.
+struct Mirror {
+    url: Url,
+    rank: i64,
+}
+
+/// The file being read and what is known of it so far.
+#[derive(Default)]
+struct Entry {
+    name: String,
+    size: Option<u64>,
+    hashes: Vec<Checksum>,
+    mirrors: Vec<Mirror>,
+}
+
+/// Element whose text is being collected.
+enum Field {
+    Size,
+    Hash(Option<ChecksumAlgorithm>),
+    Url(i64),
+}
+
+/// The files a metalink describes, in document order.
+pub fn parse(document: &str) -> Result<Vec<MetalinkFile>, String> {
+    let mut reader = Reader::from_str(document);
+    let mut files = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut field: Option<Field> = None;
+    let mut text = String::new();
+    // Piece hashes describe chunks of the file, not the file.
+    let mut in_pieces = false;
+    let mut is_metalink = false;
+    loop {
+        let event = reader.read_event().map_err(|err| format!("invalid XML: {err}"))?;
+        match event {
+            // Empty elements carry nothing worth reading, e.g an `<url/>` without address.
+            Event::Start(element) => {
+                let name = element.local_name();
+                match name.as_ref() {
+                    b"metalink" => is_metalink = true,
+                    b"file" => {
+                        let name = attribute(&element, "name")?.ok_or("a file has no name")?;
+                        entry = Some(Entry { name, ..Entry::default() });
+                    }
+                    b"pieces" => in_pieces = true,
+                    b"size" if entry.is_some() => field = Some(Field::Size),
+                    b"hash" if entry.is_some() && !in_pieces => {
+                        let algorithm = attribute(&element, "type")?.and_then(|name| algorithm(&name));
+                        field = Some(Field::Hash(algorithm));
+                    }
+                    b"url" if entry.is_some() => field = Some(Field::Url(rank(&element)?)),
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Text(content) => text.push_str(&content.decode().map_err(|err| format!("invalid XML: {err}"))?),
+            Event::CData(content) => text.push_str(&content.decode().map_err(|err| format!("invalid XML: {err}"))?),
+            Event::GeneralRef(reference) => {
+                let resolved = match reference.resolve_char_ref().map_err(|err| format!("invalid XML: {err}"))? {
+                    Some(character) => character.to_string(),
+                    None => {
+                        let name = reference.decode().map_err(|err| format!("invalid XML: {err}"))?;
+                        resolve_predefined_entity(&name).ok_or(format!("unknown entity &{name};"))?.to_string()
+                    }
+                };
+                text.push_str(&resolved);
+            }
+            Event::End(element) => {
+                match element.local_name().as_ref() {
+                    b"file" => files.extend(entry.take().map(finish)),
+                    b"pieces" => in_pieces = false,
+                    _ => {}
+                }
+                if let (Some(entry), Some(field)) = (entry.as_mut(), field.take()) {
+                    read_field(entry, field, text.trim())?;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !is_metalink {
+        return Err("the document is not a metalink".into());
+    }
+    if files.is_empty() {
+        return Err("it describes no file".into());
+    }
+    Ok(files)
+}
+
+fn read_field(entry: &mut Entry, field: Field, text: &str) -> Result<(), String> {
+    match field {
+        Field::Size => entry.size = Some(text.parse().map_err(|_| format!("invalid size {text:?} of {}", entry.name))?),
+        Field::Hash(Some(algorithm)) => {
+            let expected = unhex(text).ok_or(format!("invalid {} hash of {}", algorithm.name(), entry.name))?;
+            entry.hashes.push(Checksum { algorithm, expected, origin: ORIGIN });
+        }
+        // Algorithms Cliant can't verify, e.g sha-1.
+        Field::Hash(None) => {}
+        Field::Url(rank) => match Url::parse(text) {
+            Ok(url) if TransportType::supported_schemes().contains(&url.scheme()) => {
+                entry.mirrors.push(Mirror { url, rank });
+            }
+            // e.g ftp:// mirrors, or relative urls the spec doesn't allow.
+            _ => tracing::debug!("Skipping unsupported mirror {text:?} of {}", entry.name),
+        },
+    }
+    Ok(())
+}
+
+fn finish(mut entry: Entry) -> MetalinkFile {
+    // Stable, mirrors of equal rank keep their document order.
+    entry.mirrors.sort_by_key(|mirror| mirror.rank);
+    let checksum = ChecksumAlgorithm::ALL
+        .iter()
+        .find_map(|algorithm| entry.hashes.iter().find(|hash| hash.algorithm == *algorithm))
+        .cloned();
+    MetalinkFile {
+        name: entry.name,
+        size: entry.size,
+        checksum,
+        urls: entry.mirrors.into_iter().map(|mirror| mirror.url).collect(),
+    }
+}
+
+/// Metalink 4 ranks urls by `priority`, 1 first; metalink 3 by `preference`, 100 first.
+/// Unranked urls come last.
+fn rank(element: &BytesStart) -> Result<i64, String> {
+    let number = |value: String| value.trim().parse::<i64>().map_err(|_| format!("invalid url rank {value:?}"));
+    if let Some(priority) = attribute(element, "priority")? {
+        return number(priority);
+    }
+    if let Some(preference) = attribute(element, "preference")? {
+        return Ok(-number(preference)?);
+    }
+    Ok(i64::MAX)
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, String> {
+    let attribute = element.try_get_attribute(name).map_err(|err| format!("invalid XML: {err}"))?;
+    attribute
+        .map(|attribute| attribute.unescape_value().map(|value| value.into_owned()))
+        .transpose()
+        .map_err(|err| format!("invalid XML: {err}"))
+}
+
+/// Hash types of both metalink versions, `sha-256` and `sha256`.
+fn algorithm(name: &str) -> Option<ChecksumAlgorithm> {
+    let name = name.to_ascii_lowercase().replace('-', "");
+    ChecksumAlgorithm::ALL.iter().copied().find(|algorithm| algorithm.name().replace('-', "") == name)
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// The file to download: the only one, or the one named like the output file.
+pub fn choose<'a>(files: &'a [MetalinkFile], output: &Path) -> Result<&'a MetalinkFile, String> {
+    if let [file] = files {
+        return Ok(file);
+    }
+    let file_name = |name: &str| Path::new(name).file_name().map(ToOwned::to_owned);
+    files
+        .iter()
+        .find(|file| file_name(&file.name).as_deref() == output.file_name())
+        .ok_or_else(|| {
+            let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+            format!(
+                "it describes {} files, name the output after the one to download: {}",
+                files.len(),
+                names.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METALINK_4: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="example.iso">
+    <size>14471447</size>
+    <hash type="sha-1">a97fcf6ba9358f8a6f62beee4421863d3e52b080</hash>
+    <hash type="sha-256">ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad</hash>
+    <pieces length="262144" type="sha-512">
+      <hash>ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f</hash>
+    </pieces>
+    <url location="de" priority="2">https://mirror.example.de/example.iso?a=1&amp;b=2</url>
+    <url priority="1">https://mirror.example.com/example.iso</url>
+    <url priority="1">ftp://ftp.example.com/example.iso</url>
+    <metaurl mediatype="torrent">https://example.com/example.iso.torrent</metaurl>
+  </file>
+  <file name="docs/example.txt">
+    <url>https://example.com/example.txt</url>
+  </file>
+</metalink>"#;
+
+    const METALINK_3: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files>
+    <file name="example.iso">
+      <size>1024</size>
+      <verification>
+        <hash type="md5">900150983cd24fb0d6963f7d28e17f72</hash>
+      </verification>
+      <resources>
+        <url type="http" preference="10">http://slow.example.com/example.iso</url>
+        <url type="http" preference="100">http://fast.example.com/example.iso</url>
+      </resources>
+    </file>
+  </files>
+</metalink>"#;
+
+    #[test]
+    fn test_parse_metalink_4() {
+        let files = parse(METALINK_4).unwrap();
+        assert_eq!(files.len(), 2);
+        let iso = &files[0];
+        assert_eq!(iso.name, "example.iso");
+        assert_eq!(iso.size, Some(14_471_447));
+        // sha-1 can't be verified and piece hashes describe chunks.
+        let checksum = iso.checksum.as_ref().unwrap();
+        assert_eq!((checksum.algorithm, checksum.origin), (ChecksumAlgorithm::Sha256, "metalink"));
+        let urls: Vec<&str> = iso.urls.iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            ["https://mirror.example.com/example.iso", "https://mirror.example.de/example.iso?a=1&b=2"]
+        );
+        assert_eq!(files[1].checksum, None);
+    }
+
+    #[test]
+    fn test_parse_metalink_3_ranks_by_preference() {
+        let files = parse(METALINK_3).unwrap();
+        let checksum = files[0].checksum.as_ref().unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Md5);
+        assert_eq!(files[0].urls[0].as_str(), "http://fast.example.com/example.iso");
+    }
+
+    #[test]
+    fn test_parse_rejects_other_documents() {
+        assert!(parse("<?xml version=\"1.0\"?><rss></rss>").unwrap_err().contains("not a metalink"));
+        assert!(parse("<metalink><file name=\"a\"><size>big</size></file></metalink>").unwrap_err().contains("size"));
+    }
+
+    #[test]
+    fn test_choose_by_output_name() {
+        let files = parse(METALINK_4).unwrap();
+        assert_eq!(choose(&files, Path::new("/tmp/example.txt")).unwrap().name, "docs/example.txt");
+        let err = choose(&files, Path::new("/tmp/other.bin")).unwrap_err();
+        assert!(err.contains("2 files") && err.contains("example.iso, docs/example.txt"), "{err}");
+        // A single file is downloaded whatever the output is called.
+        let single = &files[..1];
+        assert_eq!(choose(single, Path::new("renamed.iso")).unwrap().name, "example.iso");
+    }
+
+    #[test]
+    fn test_metadata_kind() {
+        let url = |path: &str| Url::parse(&format!("https://example.com{path}")).unwrap();
+        let typed = |content_type: &str| DownloadInfo { content_type: Some(content_type.into()), ..DownloadInfo::default() };
+        let untyped = DownloadInfo::default();
+        assert_eq!(metadata_kind(&url("/a.meta4"), &untyped, b""), Some(MetadataKind::Metalink));
+        assert_eq!(metadata_kind(&url("/a.TORRENT"), &untyped, b""), Some(MetadataKind::Torrent));
+        assert_eq!(
+            metadata_kind(&url("/download?id=1"), &typed("application/x-bittorrent"), b""),
+            Some(MetadataKind::Torrent)
+        );
+        assert_eq!(
+            metadata_kind(&url("/download?id=1"), &typed("application/metalink4+xml"), b""),
+            Some(MetadataKind::Metalink)
+        );
+        assert_eq!(metadata_kind(&url("/get"), &untyped, b"d8:announce35:udp://"), Some(MetadataKind::Torrent));
+        assert_eq!(metadata_kind(&url("/get"), &untyped, METALINK_3.as_bytes()), Some(MetadataKind::Metalink));
+        assert_eq!(metadata_kind(&url("/a.iso"), &untyped, b"\x7fELF"), None);
+    }
+}
@@ -1,4 +1,24 @@
 pub mod errors;
 pub mod network;
 pub mod fs;
-pub mod progress_tracker;
\ No newline at end of file
+pub mod gzip;
+pub mod html;
+pub mod post_process;
+pub mod progress_tracker;
+pub mod redact;
+pub mod text_mode;
+pub mod write_check;
+pub mod eta;
+pub mod capabilities;
+pub mod blocking_guard;
+pub mod checksum;
+pub mod constants;
+pub mod i18n;
+pub mod metalink;
+pub mod output;
+pub mod pacing;
+pub mod parts;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub mod sandbox;
+#[cfg(any(test, feature = "local"))]
+pub mod test_server;
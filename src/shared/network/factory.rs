@@ -9,6 +9,33 @@ pub enum TransportType{
     Http,
 }
 
+impl TransportType {
+    /// Url schemes this transport can fetch.
+    pub fn schemes(&self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature="local")]
+            TransportType::Http => &["http", "https"],
+        }
+    }
+
+    /// Names of every registered transport, as accepted by `--transport`.
+    pub fn names() -> Vec<String> {
+        Self::value_variants()
+            .iter()
+            .filter_map(|transport| transport.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect()
+    }
+
+    /// Url schemes of every registered transport, url validation accepts exactly these.
+    pub fn supported_schemes() -> Vec<&'static str> {
+        Self::value_variants()
+            .iter()
+            .flat_map(|transport| transport.schemes().iter().copied())
+            .collect()
+    }
+}
+
 pub fn handle_http(http_args:HttpArgs,transport_type:&TransportType)-> Result<impl DataTransport>{
     match transport_type{
         #[cfg(feature="local")]
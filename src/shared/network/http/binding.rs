@@ -0,0 +1,241 @@
+//! `--source-ip` / `--interface`: the local address or network interface
+//! requests leave from, for hosts on several networks (e.g a metered uplink
+//! and an internal mirror network).
+//!
+//! `--interface` binds the sockets to the device (`SO_BINDTODEVICE`) when the
+//! process may, which takes `CAP_NET_RAW`, and falls back to binding the
+//! interface's primary address with a warning otherwise. Both flags are checked
+//! against the host's interfaces when the client is built, so a typo fails at
+//! startup with the real candidates instead of on the first connection.
+
+use std::net::IpAddr;
+
+use reqwest::ClientBuilder;
+use tracing::{info, warn};
+
+use super::config::HttpArgs;
+use crate::shared::errors::CliantError;
+
+/// A network interface of this host and its addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// How `--interface` is applied.
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    Device,
+    Address(IpAddr),
+}
+
+/// Applies `--source-ip` and `--interface` to every request of the client.
+pub(super) fn bind(mut builder: ClientBuilder, args: &HttpArgs) -> Result<ClientBuilder, CliantError> {
+    #[cfg(target_os = "linux")]
+    let interface = args.interface.as_deref();
+    #[cfg(not(target_os = "linux"))]
+    let interface: Option<&str> = None;
+    if args.source_ip.is_none() && interface.is_none() {
+        return Ok(builder);
+    }
+    if args.proxy_url.is_some() {
+        warn!("--source-ip and --interface apply to the connection to the proxy, the proxy decides how it reaches the server.");
+    }
+    #[cfg(unix)]
+    let interfaces = interfaces()?;
+    if let Some(source_ip) = args.source_ip {
+        #[cfg(unix)]
+        check_source_ip(source_ip, &interfaces)?;
+        info!("Sending requests from {source_ip}");
+        builder = builder.local_address(source_ip);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(name) = interface {
+        builder = match resolve_interface(name, &interfaces, can_bind_device)? {
+            Binding::Device => builder.interface(name),
+            Binding::Address(address) => builder.local_address(address),
+        };
+    }
+    Ok(builder)
+}
+
+#[cfg(unix)]
+fn check_source_ip(source_ip: IpAddr, interfaces: &[Interface]) -> Result<(), CliantError> {
+    if interfaces.iter().any(|interface| interface.addresses.contains(&source_ip)) {
+        return Ok(());
+    }
+    Err(CliantError::ParseError(format!(
+        "--source-ip {source_ip} is not an address of this host, its interfaces are: {}",
+        describe(interfaces)
+    )))
+}
+
+/// Binds to the device when `probe` says the process may, to the interface's address otherwise.
+#[cfg(target_os = "linux")]
+fn resolve_interface(
+    name: &str,
+    interfaces: &[Interface],
+    probe: impl Fn(&str) -> std::io::Result<()>,
+) -> Result<Binding, CliantError> {
+    let Some(interface) = interfaces.iter().find(|interface| interface.name == name) else {
+        return Err(CliantError::ParseError(format!(
+            "Unknown network interface {name:?}, this host's interfaces are: {}",
+            describe(interfaces)
+        )));
+    };
+    match probe(name) {
+        Ok(()) => {
+            info!("Sending requests through interface {name}");
+            Ok(Binding::Device)
+        }
+        Err(err) => {
+            // The first IPv4 address, the one most servers are reached over.
+            let address = interface
+                .addresses
+                .iter()
+                .find(|address| address.is_ipv4())
+                .or(interface.addresses.first())
+                .copied()
+                .ok_or_else(|| {
+                    CliantError::ParseError(format!("Network interface {name} has no address to send requests from"))
+                })?;
+            warn!(
+                "Can't bind to interface {name} ({err}), it needs CAP_NET_RAW. Sending requests from its address {address} instead."
+            );
+            Ok(Binding::Address(address))
+        }
+    }
+}
+
+/// Whether this process may bind sockets to the device `name`.
+#[cfg(target_os = "linux")]
+fn can_bind_device(name: &str) -> std::io::Result<()> {
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None)?;
+    socket.bind_device(Some(name.as_bytes()))
+}
+
+/// `lo (127.0.0.1, ::1), eth0 (10.0.0.2), tun0`.
+#[cfg(unix)]
+fn describe(interfaces: &[Interface]) -> String {
+    interfaces
+        .iter()
+        .map(|interface| {
+            let addresses: Vec<String> = interface.addresses.iter().map(ToString::to_string).collect();
+            if addresses.is_empty() {
+                interface.name.clone()
+            } else {
+                format!("{} ({})", interface.name, addresses.join(", "))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Network interfaces of this host with their IPv4 and IPv6 addresses.
+#[cfg(unix)]
+pub fn interfaces() -> Result<Vec<Interface>, CliantError> {
+    use std::ffi::CStr;
+
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: on success `head` is a list owned by us until the freeifaddrs below.
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut cursor = head;
+    while !cursor.is_null() {
+        // SAFETY: every non-null entry of the list is a valid ifaddrs with a NUL terminated name.
+        let entry = unsafe { &*cursor };
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) }.to_string_lossy().into_owned();
+        // SAFETY: `ifa_addr` is null or points to a sockaddr of the family it announces.
+        let address = unsafe { ip_address(entry.ifa_addr) };
+        let position = interfaces.iter().position(|interface| interface.name == name).unwrap_or_else(|| {
+            interfaces.push(Interface { name, addresses: vec![] });
+            interfaces.len() - 1
+        });
+        interfaces[position].addresses.extend(address);
+        cursor = entry.ifa_next;
+    }
+    // SAFETY: `head` came from getifaddrs and no reference into it outlives this call.
+    unsafe { libc::freeifaddrs(head) };
+    Ok(interfaces)
+}
+
+/// # Safety
+///
+/// `addr` must be null or point to a valid sockaddr of the family it announces.
+#[cfg(unix)]
+unsafe fn ip_address(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    // SAFETY: guaranteed by the caller.
+    unsafe {
+        match i32::from((*addr).sa_family) {
+            libc::AF_INET => {
+                // `s_addr` holds the octets in network order.
+                Some(IpAddr::from((*addr.cast::<libc::sockaddr_in>()).sin_addr.s_addr.to_ne_bytes()))
+            }
+            libc::AF_INET6 => Some(IpAddr::from((*addr.cast::<libc::sockaddr_in6>()).sin6_addr.s6_addr)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::shared::network::DataTransport;
+    use crate::shared::network::http::HttpAdapter;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+    use std::net::Ipv4Addr;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    async fn fetch(args: HttpArgs) -> anyhow::Result<()> {
+        let server = TestServer::start().await?;
+        server.route("/file.bin", Route::ok(test_body(1024)));
+        let adapter = HttpAdapter::new(args)?;
+        let info = adapter.download_info(server.url("/file.bin")).await?;
+        assert_eq!(info.total_bytes, Some(1024));
+        let (info, _) = adapter.receive_data(server.url("/file.bin")).await?;
+        assert_eq!(info.total_bytes, Some(1024));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_source_ip_reaches_local_server() -> anyhow::Result<()> {
+        fetch(HttpArgs { source_ip: Some(LOCALHOST), ..HttpArgs::default() }).await
+    }
+
+    #[tokio::test]
+    async fn test_interface_reaches_local_server() -> anyhow::Result<()> {
+        // Bound to the device or, without CAP_NET_RAW, to 127.0.0.1.
+        fetch(HttpArgs { interface: Some("lo".into()), ..HttpArgs::default() }).await
+    }
+
+    #[test]
+    fn test_unknown_names_and_addresses_list_candidates() {
+        let err = HttpAdapter::new(HttpArgs { interface: Some("cliant-bogus0".into()), ..HttpArgs::default() })
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("Unknown network interface \"cliant-bogus0\""), "{err:#}");
+        assert!(format!("{err:#}").contains("lo (127.0.0.1"), "{err:#}");
+
+        let documentation_address = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 77));
+        let err = HttpAdapter::new(HttpArgs { source_ip: Some(documentation_address), ..HttpArgs::default() })
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("lo (127.0.0.1"), "{err:#}");
+    }
+
+    #[test]
+    fn test_interface_falls_back_to_its_address() -> anyhow::Result<()> {
+        let interfaces = interfaces()?;
+        let denied = |_: &str| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(resolve_interface("lo", &interfaces, denied)?, Binding::Address(LOCALHOST));
+        assert_eq!(resolve_interface("lo", &interfaces, |_| Ok(()))?, Binding::Device);
+        Ok(())
+    }
+}
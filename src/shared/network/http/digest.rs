@@ -0,0 +1,353 @@
+//! Server announced digests (RFC 9530 `Repr-Digest`, legacy `Content-MD5`).
+//!
+//! Every request asks for a digest with `Want-Repr-Digest`, servers that
+//! implement RFC 9530 answer with a `Repr-Digest` structured-field dictionary
+//! such as `sha-256=:<base64>:`. The digest covers the whole representation,
+//! so it is checked against the complete file, never against a single range.
+//! A missing or unusable header simply means nothing is verified.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::header::{HeaderMap, HeaderName};
+use tracing::debug;
+
+use crate::shared::checksum::{Checksum, ChecksumAlgorithm};
+
+pub const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+pub const WANT_REPR_DIGEST: HeaderName = HeaderName::from_static("want-repr-digest");
+const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+/// Sent with every request, sha-256 being the algorithm every RFC 9530 server supports.
+pub const WANT_REPR_DIGEST_VALUE: &str = "sha-256=10";
+
+/// The digest to verify the body of a response against, `Repr-Digest` taking
+/// precedence over `Content-MD5` and stronger algorithms over weaker ones.
+pub fn server_checksum(headers: &HeaderMap) -> Option<Checksum> {
+    repr_digest(headers).or_else(|| content_md5(headers))
+}
+
+fn repr_digest(headers: &HeaderMap) -> Option<Checksum> {
+    // Multiple field lines combine into one dictionary.
+    let lines: Vec<String> = headers
+        .get_all(REPR_DIGEST)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    let dictionary = match parse_dictionary(&lines.join(", ")) {
+        Ok(dictionary) => dictionary,
+        Err(reason) => {
+            debug!("Ignoring malformed Repr-Digest header {:?}: {}", lines, reason);
+            return None;
+        }
+    };
+    ChecksumAlgorithm::ALL.iter().find_map(|&algorithm| {
+        // The last occurrence of a key wins in a structured-field dictionary.
+        let (_, item) = dictionary.iter().rev().find(|(key, _)| key == algorithm.name())?;
+        match item {
+            Item::Bytes(expected) if expected.len() == digest_len(algorithm) => Some(Checksum {
+                algorithm,
+                expected: expected.clone(),
+                origin: "Repr-Digest",
+            }),
+            _ => {
+                debug!("Ignoring Repr-Digest member {} with an invalid value", algorithm.name());
+                None
+            }
+        }
+    })
+}
+
+fn content_md5(headers: &HeaderMap) -> Option<Checksum> {
+    let value = headers.get(CONTENT_MD5)?;
+    match STANDARD.decode(value.as_bytes()) {
+        Ok(expected) if expected.len() == digest_len(ChecksumAlgorithm::Md5) => Some(Checksum {
+            algorithm: ChecksumAlgorithm::Md5,
+            expected,
+            origin: "Content-MD5",
+        }),
+        _ => {
+            debug!("Ignoring malformed Content-MD5 header {:?}", value);
+            None
+        }
+    }
+}
+
+fn digest_len(algorithm: ChecksumAlgorithm) -> usize {
+    match algorithm {
+        ChecksumAlgorithm::Sha512 => 64,
+        ChecksumAlgorithm::Sha256 => 32,
+        ChecksumAlgorithm::Md5 => 16,
+    }
+}
+
+/// Value of a dictionary member, only byte sequences and integers are needed.
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    Other,
+}
+
+/// Parses an RFC 8941 structured-field dictionary, parameters are discarded.
+fn parse_dictionary(input: &str) -> Result<Vec<(String, Item)>, String> {
+    let mut parser = Parser { input: input.as_bytes(), pos: 0 };
+    let mut members = Vec::new();
+    parser.skip_spaces();
+    while !parser.at_end() {
+        let key = parser.parse_key()?;
+        let item = if parser.eat(b'=') {
+            parser.parse_item_or_inner_list()?
+        } else {
+            Item::Other // A bare key is the boolean true.
+        };
+        parser.skip_parameters()?;
+        members.push((key, item));
+        parser.skip_whitespace();
+        if parser.at_end() {
+            break;
+        }
+        if !parser.eat(b',') {
+            return Err(format!("expected ',' at offset {}", parser.pos));
+        }
+        parser.skip_whitespace();
+        if parser.at_end() {
+            return Err("trailing comma".into());
+        }
+    }
+    Ok(members)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.eat(b' ') {}
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(u8) -> bool) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(&accept) {
+            self.pos += 1;
+        }
+        // Every accepted byte is ASCII.
+        std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default()
+    }
+
+    fn parse_key(&mut self) -> Result<String, String> {
+        if !self.peek().is_some_and(|byte| byte.is_ascii_lowercase() || byte == b'*') {
+            return Err(format!("expected a key at offset {}", self.pos));
+        }
+        Ok(self
+            .take_while(|byte| {
+                byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"_-.*".contains(&byte)
+            })
+            .to_string())
+    }
+
+    fn skip_parameters(&mut self) -> Result<(), String> {
+        while self.eat(b';') {
+            self.skip_spaces();
+            self.parse_key()?;
+            if self.eat(b'=') {
+                self.parse_bare_item()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_item_or_inner_list(&mut self) -> Result<Item, String> {
+        if !self.eat(b'(') {
+            return self.parse_bare_item();
+        }
+        loop {
+            self.skip_spaces();
+            if self.eat(b')') {
+                self.skip_parameters()?;
+                return Ok(Item::Other);
+            }
+            self.parse_bare_item()?;
+            self.skip_parameters()?;
+            if !matches!(self.peek(), Some(b' ' | b')')) {
+                return Err(format!("unterminated inner list at offset {}", self.pos));
+            }
+        }
+    }
+
+    fn parse_bare_item(&mut self) -> Result<Item, String> {
+        match self.peek() {
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b'"') => self.parse_string(),
+            Some(b':') => self.parse_byte_sequence(),
+            Some(b'?') => {
+                self.pos += 1;
+                if self.eat(b'0') || self.eat(b'1') {
+                    Ok(Item::Other)
+                } else {
+                    Err(format!("invalid boolean at offset {}", self.pos))
+                }
+            }
+            Some(byte) if byte.is_ascii_alphabetic() || byte == b'*' => {
+                self.take_while(|byte| {
+                    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&byte)
+                });
+                Ok(Item::Other)
+            }
+            _ => Err(format!("expected an item at offset {}", self.pos)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Item, String> {
+        let negative = self.eat(b'-');
+        let integer = self.take_while(|byte| byte.is_ascii_digit()).to_string();
+        if integer.is_empty() || integer.len() > 15 {
+            return Err(format!("invalid number at offset {}", self.pos));
+        }
+        if self.eat(b'.') {
+            let fraction = self.take_while(|byte| byte.is_ascii_digit());
+            if fraction.is_empty() || fraction.len() > 3 || integer.len() > 12 {
+                return Err(format!("invalid decimal at offset {}", self.pos));
+            }
+            return Ok(Item::Other);
+        }
+        let value: i64 = integer.parse().map_err(|err| format!("invalid integer: {err}"))?;
+        Ok(Item::Integer(if negative { -value } else { value }))
+    }
+
+    fn parse_string(&mut self) -> Result<Item, String> {
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(Item::Other);
+                }
+                Some(b'\\') if matches!(self.input.get(self.pos + 1), Some(b'"' | b'\\')) => self.pos += 2,
+                Some(0x20..=0x7e) => self.pos += 1,
+                _ => return Err(format!("invalid string at offset {}", self.pos)),
+            }
+        }
+    }
+
+    fn parse_byte_sequence(&mut self) -> Result<Item, String> {
+        self.pos += 1;
+        let encoded = self
+            .take_while(|byte| byte.is_ascii_alphanumeric() || b"+/=".contains(&byte))
+            .to_string();
+        if !self.eat(b':') {
+            return Err(format!("unterminated byte sequence at offset {}", self.pos));
+        }
+        STANDARD
+            .decode(encoded)
+            .map(Item::Bytes)
+            .map_err(|err| format!("invalid base64 in byte sequence: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    // sha-256 and sha-512 of "hello world", md5 of "hello world".
+    const SHA256: &str = "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=";
+    const SHA512: &str =
+        "MJ7MSJwS1utMxA9QyQLytNDtd+5RGnx6m808qG1M2G+YndNbxf9JlnDaNCVbRbDP2DDoH2Bdz33FVC6TrpzXbw==";
+    const MD5: &str = "XrY7u+Ae7tCTyyK7j1rNww==";
+
+    fn headers(pairs: &[(&HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append((*name).clone(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_dictionary_members() {
+        let members = parse_dictionary(&format!("sha-256=:{SHA256}:, unixsum=30637;note=\"x\", flag")).unwrap();
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].0, "sha-256");
+        assert!(matches!(&members[0].1, Item::Bytes(bytes) if bytes.len() == 32));
+        assert_eq!(members[1], ("unixsum".to_string(), Item::Integer(30637)));
+        assert_eq!(members[2], ("flag".to_string(), Item::Other));
+        assert_eq!(parse_dictionary("sha-256=10").unwrap(), [("sha-256".to_string(), Item::Integer(10))]);
+    }
+
+    #[test]
+    fn test_parse_dictionary_rejects_malformed_values() {
+        for malformed in [
+            "sha-256=:not base64!:",
+            "sha-256=:abc",
+            "Sha-256=:AAAA:",
+            "sha-256=:AAAA:,",
+            "sha-256=:AAAA: sha-512=:AAAA:",
+            "sha-256=\"unterminated",
+            "=:AAAA:",
+        ] {
+            assert!(parse_dictionary(malformed).is_err(), "{malformed:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_strongest_repr_digest_algorithm_wins() {
+        let headers = headers(&[(&REPR_DIGEST, &format!("sha-256=:{SHA256}:, sha-512=:{SHA512}:"))]);
+        let checksum = server_checksum(&headers).unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha512);
+        assert_eq!(checksum.expected.len(), 64);
+    }
+
+    #[test]
+    fn test_repr_digest_field_lines_are_combined() {
+        let headers = headers(&[
+            (&REPR_DIGEST, "unixsum=30637"),
+            (&REPR_DIGEST, &format!("sha-256=:{SHA256}:")),
+        ]);
+        assert_eq!(server_checksum(&headers).unwrap().algorithm, ChecksumAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn test_repr_digest_is_preferred_over_content_md5() {
+        let headers = headers(&[(&REPR_DIGEST, &format!("sha-256=:{SHA256}:")), (&CONTENT_MD5, MD5)]);
+        let checksum = server_checksum(&headers).unwrap();
+        assert_eq!((checksum.algorithm, checksum.origin), (ChecksumAlgorithm::Sha256, "Repr-Digest"));
+    }
+
+    #[test]
+    fn test_unusable_repr_digest_falls_back_to_content_md5() {
+        for repr_digest in ["sha-256=:AAAA:", "unixsum=30637", "sha-256=:broken"] {
+            let headers = headers(&[(&REPR_DIGEST, repr_digest), (&CONTENT_MD5, MD5)]);
+            let checksum = server_checksum(&headers).unwrap();
+            assert_eq!(checksum.algorithm, ChecksumAlgorithm::Md5, "{repr_digest}");
+        }
+        assert!(server_checksum(&headers(&[(&CONTENT_MD5, "garbage")])).is_none());
+        assert!(server_checksum(&HeaderMap::new()).is_none());
+    }
+}
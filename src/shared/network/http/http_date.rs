@@ -0,0 +1,58 @@
+//! Parsing of HTTP-date header values (`Last-Modified`, `Date`, ...).
+//!
+//! RFC 9110 requires recipients to accept all three historical formats:
+//! IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`), the obsolete RFC 850 form
+//! (`Sunday, 06-Nov-94 08:49:37 GMT`) and ANSI C's asctime
+//! (`Sun Nov  6 08:49:37 1994`). All of them are in UTC.
+
+use std::time::SystemTime;
+
+use chrono::NaiveDateTime;
+
+const HTTP_DATE_FORMATS: &[&str] = &[
+    "%a, %d %b %Y %H:%M:%S GMT",
+    "%A, %d-%b-%y %H:%M:%S GMT",
+    "%a %b %e %H:%M:%S %Y",
+];
+
+/// Parses an HTTP-date, `None` for anything that is not one of the three formats.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    HTTP_DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|date| date.and_utc().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 1994-11-06T08:49:37Z, the example date of RFC 9110.
+    fn rfc_example() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    #[test]
+    fn test_imf_fixdate() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(rfc_example()));
+    }
+
+    #[test]
+    fn test_rfc850_date() {
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(rfc_example()));
+    }
+
+    #[test]
+    fn test_asctime_date() {
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(rfc_example()));
+    }
+
+    #[test]
+    fn test_garbage_is_rejected() {
+        for value in ["", "yesterday", "06 Nov 1994", "Sun, 06 Nov 1994 08:49:37 PST", "Mon, 06 Nov 1994 08:49:37 GMT"] {
+            assert_eq!(parse_http_date(value), None, "{value:?} should not parse");
+        }
+    }
+}
@@ -6,26 +6,49 @@ use reqwest_tracing::TracingMiddleware;
 use tokio::sync::mpsc::channel;
 use tracing::{error, instrument};
 
-use super::http::config::HttpArgs;
-use crate::shared::{errors::CliantError, network::DataTransport};
+use super::http::config::{HttpArgs, parse_proxy_url};
+use super::http::digest::{WANT_REPR_DIGEST, WANT_REPR_DIGEST_VALUE, server_checksum};
+use super::http::http_date::parse_http_date;
+use super::http::retry::RetryOnStatus;
+use super::http::recording::{RecordingMiddleware, ReplayMiddleware};
+use crate::shared::{
+    constants::{MAX_CHUNK_SIZE, STREAM_CHANNEL_MESSAGES},
+    pacing::ChunkPacer,
+    errors::CliantError,
+    redact::{Redact, Redacted},
+    network::{ByteRange, ContentRange, DataTransport, DownloadInfo, split_chunk, stats::ConnectionStats},
+};
 use bytes::Bytes;
-use reqwest::{Client, header::CONTENT_LENGTH};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
+use hyper_util::client::legacy::connect::HttpInfo;
+use reqwest::{
+    Client, StatusCode,
+    header::{ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HeaderMap, LAST_MODIFIED, RANGE},
+};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Error as MiddlewareError};
+use reqwest_retry::{RetryError, RetryTransientMiddleware, policies::ExponentialBackoff};
 use tokio_stream::{Stream, wrappers::ReceiverStream};
+pub mod binding;
 pub mod config;
+pub mod digest;
+pub mod http_date;
+pub mod recording;
+pub mod resolve;
+pub mod retry;
 
 pub struct HttpAdapter {
     client: ClientWithMiddleware,
     username:Option<String>,
     password:Option<SecretString>,
+    /// `host:port` of the configured proxy, used to name it in errors.
+    proxy:Option<String>,
+    stats:std::sync::Mutex<ConnectionStats>,
 }
 
 impl HttpAdapter {
     #[allow(clippy::cast_possible_truncation)]
-    #[instrument(name="new_http_adapter",fields(config=format!("{:?}\n", http_args,)))]
+    #[instrument(name="new_http_adapter",skip(http_args),fields(config=%Redacted(&http_args)))]
     pub fn new(http_args: HttpArgs) -> Result<Self> {
-        let retry_args = http_args.retry_args;
+        let retry_args = &http_args.retry_args;
         let delay_secs = *retry_args.retry_delay_secs();
         let max_retry_bound = delay_secs.max(10); // clamp delay sec to 10 secs.
         let retry_policy = ExponentialBackoff::builder()
@@ -35,122 +58,863 @@ impl HttpAdapter {
             )
             
             .build_with_max_retries(*retry_args.max_no_retries() as u32);
-        let retry_middleware =
-            RetryTransientMiddleware::new_with_policy(retry_policy); // Enable retry with exponential backoff.
+        let retry_status_codes = retry_args.retry_status_codes();
+        debug!("Retrying HTTP status codes {:?}", retry_status_codes);
+        // Enable retry with exponential backoff.
+        let retry_middleware = RetryTransientMiddleware::new_with_policy_and_strategy(
+            retry_policy,
+            RetryOnStatus::new(retry_status_codes),
+        );
         let try_client = Client::try_from(http_args.clone())
             .context("Can't create http client due to misconfiguration.")?;
-        let client: ClientWithMiddleware = ClientBuilder::new(try_client)
+        let mut client_builder = ClientBuilder::new(try_client)
             .with(TracingMiddleware::default()) // Enable built-in http client tracing and logging.
-            .with(retry_middleware)
-            .build();
+            .with(retry_middleware);
+        // Installed below the retry middleware so every single attempt is recorded or replayed.
+        if let Some(replay_dir) = &http_args.replay {
+            client_builder = client_builder.with(ReplayMiddleware::open(replay_dir.clone())?);
+        } else if let Some(record_dir) = &http_args.record {
+            client_builder = client_builder.with(RecordingMiddleware::new(
+                record_dir.clone(),
+                http_args.record_body_limit,
+            )?);
+        }
+        let client: ClientWithMiddleware = client_builder.build();
+
+        let proxy = match &http_args.proxy_url {
+            Some(proxy_url) => {
+                Some(parse_proxy_url(proxy_url)?.authority())
+            }
+            None => None,
+        };
 
-        Ok(Self { client,username:http_args.username,password:http_args.password })
+        Ok(Self {
+            client,
+            username:http_args.username,
+            password:http_args.password,
+            proxy,
+            stats:std::sync::Mutex::default(),
+        })
     }
-}
 
-impl DataTransport for HttpAdapter {
-    #[instrument(name="recieve_data",skip(self),fields(source))]
-    async fn receive_data(
+    /// Sends the GET for `source`, or for a window of it, and streams its body.
+    ///
+    /// Returns once the response headers arrived, the body is read by a task
+    /// feeding the returned stream, which stops when the stream is dropped.
+    async fn get(
         &self,
         source: url::Url,
-    ) -> Result<impl Stream<Item = Result<Bytes, CliantError>>, CliantError>
-    {
-        debug!("Initializing channels for streaming data from source {}...",source.clone());
-        let (tx, rx) = channel(256);
+        range: Option<ByteRange>,
+    ) -> Result<(DownloadInfo, ReceiverStream<Result<Bytes, CliantError>>), CliantError> {
+        let mut client=self.client.get(source.clone()).header(WANT_REPR_DIGEST, WANT_REPR_DIGEST_VALUE);
+        if let Some(range) = range {
+            client = client.header(RANGE, range_header(range));
+        }
+        let mut resp = match self.authenticate(client).send().await {
+            Ok(resp) => resp,
+            Err(err) => {
+                let err = self.map_send_error(err, &source);
+                error!(error = %err,"could'nt download {}.", Redacted(&source));
+                return Err(err);
+            }
+        };
+        self.observe_response(&resp);
+        self.check_proxy_status(&resp)?;
+        check_status(&resp)?;
+        check_body_framing(&resp)?;
+        let info = response_info(&resp, &source)?;
+        debug!("Initializing channels for streaming data from source {}...",Redacted(&source));
+        let (tx, rx) = channel(STREAM_CHANNEL_MESSAGES);
         debug!("Initialization completed.");
-        let mut client=self.client.get(source.clone());
-        if self.username.is_some(){
-            let password=self.password.clone().map(|p|p.expose_secret().to_string());
-            let username=self.username.clone().unwrap();
-            client=client.basic_auth(username,password);
-        }
-        match client.send().await {
-            Ok(mut resp) => {
-                loop {
-                    match resp.chunk().await {
-                        Ok(Some(bytes)) => {
-                            trace!("Recieved chunk of len {} from source {}",bytes.len(),source.clone());
-                            if let Err(err) = tx.send(Ok(bytes)).await {
-                                error!(error = %err, "Error sending bytes to channel");
-                                return Err(CliantError::Fatal(
-                                    "Error sending bytes to channel".into(),
-                                ));
-                            }
-                        }
-                        Ok(None) => {
-                            info!("Streaming of chunks from {} completed.",source.clone());
-                            break;
+        tokio::spawn(async move {
+            let mut pacer = ChunkPacer::default();
+            loop {
+                let chunk = tokio::select! {
+                    chunk = resp.chunk() => chunk,
+                    // The consumer is gone while the server is slow, don't wait for its next chunk.
+                    () = tx.closed() => {
+                        debug!("Stopped streaming {}, its stream was dropped",Redacted(&source));
+                        return;
+                    }
+                };
+                match chunk {
+                    Ok(Some(bytes)) => {
+                        pacer.chunk().await;
+                        trace!("Recieved chunk of len {} from source {}",bytes.len(),Redacted(&source));
+                        if bytes.len() > MAX_CHUNK_SIZE {
+                            trace!("Splitting oversized chunk of len {} into pieces of {} bytes",bytes.len(),MAX_CHUNK_SIZE);
                         }
-                        Err(err) => {
-                            //Propagate error to sender to handle it.
-                            error!(error = %err, "Error sending error to channel");
-                            return Err(CliantError::ReqwestClient(err));
+                        for piece in split_chunk(bytes, MAX_CHUNK_SIZE) {
+                            if tx.send(Ok(piece)).await.is_err() {
+                                // The consumer is gone, dropping the response closes the connection.
+                                debug!("Stopped streaming {}, its stream was dropped",Redacted(&source));
+                                return;
+                            }
                         }
                     }
+                    Ok(None) => {
+                        info!("Streaming of chunks from {} completed.",Redacted(&source));
+                        return;
+                    }
+                    Err(err) => {
+                        //Propagate error to the consumer to handle it.
+                        let err = redact_reqwest_error(err);
+                        error!(error = %err, "Error streaming {}", Redacted(&source));
+                        let _ = tx.send(Err(CliantError::ReqwestClient(err))).await;
+                        return;
+                    }
                 }
             }
-            Err(err) => {
-                error!(error = %err,"could'nt download {source}.");
-                return Err(CliantError::ReqwestMiddleware(err));
+        });
+        Ok((info, ReceiverStream::new(rx)))
+    }
+
+    /// Adds the http basic authentication credentials, if any, to `request`.
+    fn authenticate(&self, request: reqwest_middleware::RequestBuilder) -> reqwest_middleware::RequestBuilder {
+        match &self.username {
+            Some(username) => {
+                let password = self.password.as_ref().map(|password| password.expose_secret().to_string());
+                request.basic_auth(username, password)
             }
+            None => request,
         }
-        Ok(ReceiverStream::new(rx))
     }
-    #[instrument(name="total_bytes",skip(self),fields(source))]
-    async fn total_bytes(&self,source:url::Url)->Result<Option<usize>,CliantError> {
-        debug!("getting total size of {}",source.clone());
-        
-        let resp=self.client.head(source.clone()).send().await?;
-        debug!("Sent HTTP head request to {}",source.clone());
-        let size_result = resp
-            .headers()
-            .get(CONTENT_LENGTH)
-            .map(|header| -> Result<&str> {
-                let header_str = header.to_str().context(
-                    "Error !, Can't convert response header CONTENT-LENGTH header to string",
-                )?;
-                Ok(header_str)
-            });
-        
 
-        let size_info=if let Some(size) = size_result {
-            
-            
-            Some(size?.trim().parse::<usize>().map_err(
-                |err| CliantError::ParseError(format!("Error !, Can't convert  file size from http header to usize object header,caused by:{err}")),
-            )?)
-            
-        } else {
-            warn!(
-                "Can't get download size for url {} ,in http header Content-Length", &source
-            );
-            None
+    /// Records the protocol version and connection of a response.
+    fn observe_response(&self, resp: &reqwest::Response) {
+        let version = format!("{:?}", resp.version());
+        // Set by hyper's connector, absent for replayed responses.
+        let local_addr = resp.extensions().get::<HttpInfo>().map(HttpInfo::local_addr);
+        debug!(http_version = %version, ?local_addr, "Received response from {}", Redacted(resp.url()));
+        self.stats.lock().unwrap().observe(&version, local_addr);
+    }
+
+    /// Reports failures of the proxy itself (unreachable proxy, TLS to the proxy,
+    /// a refused CONNECT tunnel) as proxy errors naming the proxy host instead of
+    /// blaming the download url.
+    fn map_send_error(&self, err: MiddlewareError, source: &url::Url) -> CliantError {
+        let err = redact_middleware_error(err);
+        let Some(proxy) = &self.proxy else {
+            return CliantError::ReqwestMiddleware(err);
         };
+        let mut reasons = vec![err.to_string()];
+        let mut cause = std::error::Error::source(&err);
+        while let Some(inner) = cause {
+            reasons.push(inner.to_string());
+            cause = inner.source();
+        }
+        let connect_failed = inner_reqwest_error(&err).is_some_and(reqwest::Error::is_connect);
+        // https:// urls are tunneled with CONNECT, a failed tunnel shows up in the error chain.
+        let tunnel_failed = reasons.iter().any(|reason| reason.starts_with("tunnel error"));
+        // http:// urls are forwarded by the proxy, any connection failure is a failure to reach it.
+        let proxy_unreachable = source.scheme() == "http" && connect_failed;
+        if tunnel_failed || proxy_unreachable {
+            reasons.dedup();
+            CliantError::Proxy { proxy: proxy.clone(), reason: reasons.join(": ") }
+        } else {
+            CliantError::ReqwestMiddleware(err)
+        }
+    }
+
+    /// A forwarding proxy answers 407 itself when it wants credentials.
+    fn check_proxy_status(&self, resp: &reqwest::Response) -> Result<(), CliantError> {
+        match &self.proxy {
+            Some(proxy) if resp.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+                Err(CliantError::Proxy {
+                    proxy: proxy.clone(),
+                    reason: "proxy authentication required (HTTP 407)".into(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Size and modification time of the resource a HEAD or GET response describes.
+fn response_info(resp: &reqwest::Response, source: &url::Url) -> Result<DownloadInfo, CliantError> {
+    let size_info = parse_content_length(resp.headers())?;
+    if size_info.is_none() {
+        warn!(
+            "Can't get download size for url {} ,in http header Content-Length", Redacted(source)
+        );
+    }
+
+    debug!("Got download size {:?} bytes.",size_info);
+
+    let last_modified = resp.headers().get(LAST_MODIFIED).and_then(|header| {
+        let parsed = header.to_str().ok().and_then(parse_http_date);
+        if parsed.is_none() {
+            debug!("Ignoring unparseable Last-Modified header {:?} of {}", header, Redacted(source));
+        }
+        parsed
+    });
+    let checksum = server_checksum(resp.headers());
+    debug!("Server announced checksum {:?}", checksum);
+    let header_text = |name| {
+        resp.headers().get(name).map(|value: &reqwest::header::HeaderValue| {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        })
+    };
+    Ok(DownloadInfo {
+        total_bytes: size_info,
+        last_modified,
+        checksum,
+        content_type: header_text(CONTENT_TYPE),
+        content_disposition: header_text(CONTENT_DISPOSITION),
+        content_encoding: header_text(CONTENT_ENCODING),
+        partial: resp.status() == StatusCode::PARTIAL_CONTENT,
+        content_range: header_text(CONTENT_RANGE).as_deref().and_then(parse_content_range),
+        accepts_ranges: resp
+            .headers()
+            .get_all(ACCEPT_RANGES)
+            .iter()
+            .any(|value| value.as_bytes().split(|&byte| byte == b',').any(|unit| unit.trim_ascii().eq_ignore_ascii_case(b"bytes"))),
+    })
+}
+
+/// The `Content-Length` of a response, `None` when it has none.
+///
+/// Parsed strictly: digits only (no sign, no spaces within), repeated headers or
+/// comma separated lists must all carry the same value, and anything else is an
+/// error rather than a size of 0, which would silently produce an empty file.
+pub(crate) fn parse_content_length(headers: &HeaderMap) -> Result<Option<u64>, CliantError> {
+    let invalid = |value: &str| CliantError::ParseError(format!("Invalid Content-Length header {value:?}"));
+    let mut length = None;
+    for header in headers.get_all(CONTENT_LENGTH) {
+        let text = String::from_utf8_lossy(header.as_bytes());
+        for value in text.split(',').map(str::trim) {
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid(&text));
+            }
+            let value: u64 = value.parse().map_err(|_| invalid(&text))?;
+            if let Some(previous) = length
+                && previous != value
+            {
+                return Err(CliantError::ParseError(format!("Conflicting Content-Length headers {previous} and {value}")));
+            }
+            length = Some(value);
+        }
+    }
+    Ok(length)
+}
+
+/// A `Content-Range` of the form `bytes first-last/length` or `bytes first-last/*`,
+/// `None` for an unsatisfied range (`bytes */length`) or a malformed one.
+pub(crate) fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (unit, range) = value.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let (span, complete_length) = range.trim().split_once('/')?;
+    let (first, last) = span.split_once('-')?;
+    // Digits only, `parse` would also take a sign.
+    let digits = |text: &str| match text.bytes().all(|byte| byte.is_ascii_digit()) {
+        true => text.parse::<u64>().ok(),
+        false => None,
+    };
+    let (first, last) = (digits(first)?, digits(last)?);
+    let complete_length = match complete_length {
+        "*" => None,
+        length => Some(digits(length)?),
+    };
+    let valid = first <= last && complete_length.is_none_or(|length| last < length);
+    valid.then_some(ContentRange { first, last, complete_length })
+}
+
+/// `Range` header value asking for `range`.
+fn range_header(range: ByteRange) -> String {
+    match range {
+        ByteRange::Head(len) => format!("bytes=0-{}", len.saturating_sub(1)),
+        ByteRange::Tail(len) => format!("bytes=-{len}"),
+        ByteRange::Span { first, last } => format!("bytes={first}-{last}"),
+    }
+}
+
+/// Fails on an error status that is left after retrying, instead of saving the
+/// error page as the file.
+fn check_status(resp: &reqwest::Response) -> Result<(), CliantError> {
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(CliantError::UnexpectedResponse {
+            url: resp.url().redact(),
+            reason: format!(
+                "HTTP {} {}",
+                status.as_u16(),
+                status.canonical_reason().unwrap_or("(non-standard status)")
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Rejects bodies that would not be the plain bytes of the resource.
+///
+/// A `multipart/byteranges` body carries MIME boundaries and part headers,
+/// and a Content-Range in another unit than bytes can't be mapped onto the
+/// file; writing either to disk silently corrupts the download.
+fn check_body_framing(resp: &reqwest::Response) -> Result<(), CliantError> {
+    let unexpected = |reason: String| CliantError::UnexpectedResponse {
+        url: resp.url().redact(),
+        reason,
+    };
+    if let Some(content_type) = resp.headers().get(CONTENT_TYPE) {
+        let content_type = String::from_utf8_lossy(content_type.as_bytes());
+        if content_type.trim().to_ascii_lowercase().starts_with("multipart/byteranges") {
+            return Err(unexpected(format!(
+                "the server sent a multipart/byteranges body ({content_type}) that Cliant did not ask for"
+            )));
+        }
+    }
+    if let Some(content_range) = resp.headers().get(CONTENT_RANGE) {
+        let content_range = String::from_utf8_lossy(content_range.as_bytes());
+        let unit = content_range.split_whitespace().next().unwrap_or_default();
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return Err(unexpected(format!(
+                "unsupported Content-Range unit {unit:?} in {content_range:?}, only bytes are supported"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Redacts the url reqwest embeds in its error messages.
+fn redact_reqwest_error(mut err: reqwest::Error) -> reqwest::Error {
+    if let Some(url) = err.url_mut()
+        && let Ok(redacted) = url::Url::parse(&url.redact())
+    {
+        *url = redacted;
+    }
+    err
+}
+
+/// Redacts the url of the reqwest error behind `err`, looking through the retry
+/// middleware's wrapper.
+fn redact_middleware_error(err: MiddlewareError) -> MiddlewareError {
+    match err {
+        MiddlewareError::Reqwest(err) => MiddlewareError::Reqwest(redact_reqwest_error(err)),
+        MiddlewareError::Middleware(mut err) => {
+            if let Some(retry_err) = err.downcast_mut::<RetryError>() {
+                let (RetryError::WithRetries { err: inner, .. } | RetryError::Error(inner)) = retry_err;
+                let taken = std::mem::replace(inner, MiddlewareError::Middleware(anyhow::anyhow!("")));
+                *inner = redact_middleware_error(taken);
+            }
+            MiddlewareError::Middleware(err)
+        }
+    }
+}
+
+/// The reqwest error behind `err`, looking through the retry middleware's wrapper.
+fn inner_reqwest_error(err: &MiddlewareError) -> Option<&reqwest::Error> {
+    match err {
+        MiddlewareError::Reqwest(err) => Some(err),
+        MiddlewareError::Middleware(err) => match err.downcast_ref::<RetryError>()? {
+            RetryError::WithRetries { err, .. } | RetryError::Error(err) => inner_reqwest_error(err),
+        },
+    }
+}
+
+impl DataTransport for HttpAdapter {
+    #[instrument(name="recieve_data",skip(self, source),fields(source = %Redacted(&source)))]
+    async fn receive_data(
+        &self,
+        source: url::Url,
+    ) -> Result<(DownloadInfo, impl Stream<Item = Result<Bytes, CliantError>>), CliantError>
+    {
+        self.get(source, None).await
+    }
+
+    #[instrument(name="recieve_range",skip(self, source),fields(source = %Redacted(&source)))]
+    async fn receive_range(
+        &self,
+        source: url::Url,
+        range: ByteRange,
+    ) -> Result<(DownloadInfo, impl Stream<Item = Result<Bytes, CliantError>>), CliantError>
+    {
+        self.get(source, Some(range)).await
+    }
+
+    #[instrument(name="download_info",skip(self, source),fields(source = %Redacted(&source)))]
+    async fn download_info(&self,source:url::Url)->Result<DownloadInfo,CliantError> {
+        debug!("getting total size of {}",Redacted(&source));
         
-        debug!("Got download size {} bytes.",size_info.unwrap());
-        Ok(size_info)
+        let resp=self
+            .authenticate(self.client.head(source.clone()).header(WANT_REPR_DIGEST, WANT_REPR_DIGEST_VALUE))
+            .send()
+            .await
+            .map_err(|err| self.map_send_error(err, &source))?;
+        self.observe_response(&resp);
+        self.check_proxy_status(&resp)?;
+        check_status(&resp)?;
+        debug!("Sent HTTP head request to {}",Redacted(&source));
+        response_info(&resp, &source)
 
     }
+
+    fn supports_ranges(&self)->bool{
+        true
+    }
+
+    fn connection_stats(&self)->ConnectionStats{
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn test_parse_content_range() {
+    let range = |first, last, complete_length| Some(ContentRange { first, last, complete_length });
+    assert_eq!(parse_content_range("bytes 0-499/1234"), range(0, 499, Some(1234)));
+    assert_eq!(parse_content_range("Bytes 500-999/*"), range(500, 999, None));
+    for invalid in ["bytes */1234", "bytes 500-499/1234", "bytes 0-1234/1234", "bytes +0-9/10", "bytes 0-/10", "items 0-9/10", "bytes 0-9"] {
+        assert_eq!(parse_content_range(invalid), None, "{invalid}");
+    }
+}
+
+#[test]
+fn test_parse_content_length() {
+    let parse = |values: &[&str]| {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(CONTENT_LENGTH, value.parse().unwrap());
+        }
+        parse_content_length(&headers)
+    };
+    assert_eq!(parse(&[]).unwrap(), None);
+    assert_eq!(parse(&["0"]).unwrap(), Some(0));
+    assert_eq!(parse(&[" 5000000000 "]).unwrap(), Some(5_000_000_000));
+    assert_eq!(parse(&["42", "42"]).unwrap(), Some(42));
+    assert_eq!(parse(&["42, 42"]).unwrap(), Some(42));
+    for invalid in [&["-1"][..], &["+42"], &["4 2"], &["0x10"], &["abc"], &[""], &["18446744073709551616"], &["42", "43"], &["42,"]] {
+        assert!(parse(invalid).is_err(), "{invalid:?} should be rejected");
+    }
 }
 
 #[tokio::test]
 async fn test_download() -> Result<()> {
-    use url::Url;
     use tokio_stream::StreamExt;
-    
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    let server = TestServer::start().await?;
+    server.route("/1MB.zip", Route::ok(test_body(64 * 1024)));
+    let adapter = HttpAdapter::new(HttpArgs::default())?;
+    let source = server.url("/1MB.zip");
+    let (_, mut stream) = adapter.receive_data(source).await?;
+    let next_stream: std::result::Result<Option<Bytes>, CliantError>=stream.try_next().await;
+    assert!(next_stream.is_ok());
+    assert!(next_stream.unwrap().is_some()); // safe to call unwrap here it won't panic.
+    Ok(())
+}
+
+/// The stream is returned with the headers, not once the body was read: a body
+/// of more messages than the channel holds used to hang the download.
+#[tokio::test]
+async fn test_body_streams_beyond_channel_capacity() -> Result<()> {
+    use tokio_stream::StreamExt;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    let server = TestServer::start().await?;
+    let chunks = 4 * STREAM_CHANNEL_MESSAGES;
+    let body = test_body(chunks * 1024);
+    let (route, gate) = Route::gated(body.clone(), 1024);
+    server.route("/large.bin", route);
+    let adapter = HttpAdapter::new(HttpArgs::default())?;
+    // Nothing of the body was sent yet.
+    let (info, mut stream) =
+        tokio::time::timeout(Duration::from_secs(10), adapter.receive_data(server.url("/large.bin"))).await??;
+    assert_eq!(info.total_bytes, Some(body.len() as u64));
+    gate.release(chunks);
+    let mut received = Vec::new();
+    while let Some(bytes) = stream.try_next().await? {
+        received.extend_from_slice(&bytes);
+    }
+    assert_eq!(received, body);
+    Ok(())
+}
+
+#[test]
+fn test_range_header() {
+    assert_eq!(range_header(ByteRange::Head(10)), "bytes=0-9");
+    assert_eq!(range_header(ByteRange::Tail(10)), "bytes=-10");
+    assert_eq!(range_header(ByteRange::Span { first: 100, last: 199 }), "bytes=100-199");
+}
+
+#[tokio::test]
+async fn test_download_through_forward_proxy() -> Result<()> {
+    use tokio_stream::StreamExt;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    // The test server acts as the proxy, forwarded requests carry the absolute url.
+    let proxy = TestServer::start().await?;
+    proxy.route("http://cliant.invalid/file.bin", Route::ok(test_body(1024)));
+    let adapter = HttpAdapter::new(HttpArgs {
+        proxy_url: Some(proxy.url("/").to_string()),
+        ..HttpArgs::default()
+    })?;
+    let source = url::Url::parse("http://cliant.invalid/file.bin")?;
+    let (_, mut stream) = adapter.receive_data(source).await?;
+    let mut received = Vec::new();
+    while let Some(bytes) = stream.try_next().await? {
+        received.extend_from_slice(&bytes);
+    }
+    assert_eq!(received, test_body(1024));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refused_connect_tunnel_names_proxy() -> Result<()> {
+    use crate::shared::test_server::{Route, TestServer};
+    use super::http::config::RetryArgs;
+
+    let proxy = TestServer::start().await?;
+    proxy.route("cliant.invalid:443", Route::status(407));
+    let adapter = HttpAdapter::new(HttpArgs {
+        proxy_url: Some(proxy.url("/").to_string()),
+        retry_args: RetryArgs::new(0, 0),
+        ..HttpArgs::default()
+    })?;
+    let source = url::Url::parse("https://cliant.invalid/file.bin")?;
+    let proxy_host = format!("127.0.0.1:{}", proxy.url("/").port().unwrap());
+    match adapter.download_info(source).await {
+        Err(CliantError::Proxy { proxy, reason }) => {
+            assert_eq!(proxy, proxy_host);
+            assert!(reason.contains("proxy authorization required"), "{reason}");
+        }
+        other => panic!("Expected a proxy error, got {other:?}"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_of_truncated_body() -> Result<()> {
+    use tokio_stream::StreamExt;
+    use async_tempfile::TempDir;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    let temp_dir = TempDir::new().await?;
+    let record_dir = temp_dir.dir_path().join("recording");
+    let server = TestServer::start().await?;
+    server.route("/file.bin", Route::ok(test_body(64 * 1024)));
+    let recorder = HttpAdapter::new(HttpArgs {
+        record: Some(record_dir.clone()),
+        record_body_limit: Some(4096),
+        ..HttpArgs::default()
+    })?;
+    let (info, stream) = recorder.receive_data(server.url("/file.bin")).await?;
+    assert_eq!(info.total_bytes, Some(64 * 1024), "The live response is not truncated");
+    drop(stream);
+
+    let replayer = HttpAdapter::new(HttpArgs { replay: Some(record_dir), ..HttpArgs::default() })?;
+    let (info, mut stream) = replayer.receive_data(server.url("/file.bin")).await?;
+    assert_eq!(info.total_bytes, Some(4096), "Content-Length must match the stored body");
+    let mut received = Vec::new();
+    while let Some(bytes) = stream.try_next().await? {
+        received.extend_from_slice(&bytes);
+    }
+    assert_eq!(received, test_body(64 * 1024).slice(..4096));
+    Ok(())
+}
+
+/// Writes `pem` to a file named after the test and process, removed by the caller.
+#[cfg(test)]
+fn proxy_cacert_file(name: &str, pem: &str) -> Result<std::path::PathBuf> {
+    let path = std::env::temp_dir().join(format!("cliant-{name}-{}.pem", std::process::id()));
+    std::fs::write(&path, pem)?;
+    Ok(path)
+}
+
+#[tokio::test]
+async fn test_download_through_tls_connect_proxy() -> Result<()> {
+    use tokio_stream::StreamExt;
+    use crate::shared::test_server::tls::{CA_PEM, TlsRelay};
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    let origin = TestServer::start().await?;
+    origin.route("/file.bin", Route::ok(test_body(1024)));
+    let front = TlsRelay::front(origin.addr()).await?;
+    let proxy = TlsRelay::connect_proxy(front.addr()).await?;
+    let cacert = proxy_cacert_file("tls-proxy-ca", CA_PEM)?;
+    // The origin's certificate comes from the same CA, trusted for downloads too.
+    let adapter = HttpAdapter::new(HttpArgs {
+        proxy_url: Some(format!("https://{}", proxy.addr())),
+        proxy_cacert: Some(cacert.clone()),
+        trust_proxy_cacert_for_downloads: true,
+        ..HttpArgs::default()
+    });
+    std::fs::remove_file(&cacert)?;
+    let adapter = adapter?;
+    let source = url::Url::parse("https://cliant.invalid/file.bin")?;
+    let (_, mut stream) = adapter.receive_data(source).await?;
+    let mut received = Vec::new();
+    while let Some(bytes) = stream.try_next().await? {
+        received.extend_from_slice(&bytes);
+    }
+    assert_eq!(received, test_body(1024));
+    assert_eq!(proxy.connects(), ["cliant.invalid:443"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_untrusted_tls_proxy_names_proxy() -> Result<()> {
+    use super::http::config::RetryArgs;
+    use crate::shared::test_server::tls::{OTHER_CA_PEM, TlsRelay};
+    use crate::shared::test_server::TestServer;
+
+    let origin = TestServer::start().await?;
+    let front = TlsRelay::front(origin.addr()).await?;
+    let proxy = TlsRelay::connect_proxy(front.addr()).await?;
+    let cacert = proxy_cacert_file("tls-proxy-other-ca", OTHER_CA_PEM)?;
+    let adapter = HttpAdapter::new(HttpArgs {
+        proxy_url: Some(format!("https://{}", proxy.addr())),
+        proxy_cacert: Some(cacert.clone()),
+        trust_proxy_cacert_for_downloads: true,
+        retry_args: RetryArgs::new(0, 0),
+        ..HttpArgs::default()
+    });
+    std::fs::remove_file(&cacert)?;
+    let source = url::Url::parse("https://cliant.invalid/file.bin")?;
+    match adapter?.download_info(source).await {
+        Err(CliantError::Proxy { proxy: proxy_host, reason }) => {
+            assert_eq!(proxy_host, proxy.addr().to_string());
+            assert!(reason.contains("certificate"), "{reason}");
+        }
+        other => panic!("Expected a proxy error, got {other:?}"),
+    }
+    assert!(proxy.connects().is_empty(), "No tunnel past a failed handshake");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreachable_proxy_names_proxy() -> Result<()> {
+    use super::http::config::RetryArgs;
+
+    // Nothing listens on a port freed right after binding it.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let proxy_host = listener.local_addr()?.to_string();
+    drop(listener);
+    let adapter = HttpAdapter::new(HttpArgs {
+        proxy_url: Some(format!("https://{proxy_host}")),
+        retry_args: RetryArgs::new(0, 0),
+        ..HttpArgs::default()
+    })?;
+    let source = url::Url::parse("http://cliant.invalid/file.bin")?;
+    match adapter.receive_data(source).await {
+        Err(CliantError::Proxy { proxy, .. }) => assert_eq!(proxy, proxy_host),
+        Err(other) => panic!("Expected a proxy error, got {other:?}"),
+        Ok(_) => panic!("Expected a proxy error, got a stream"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multipart_byteranges_body_is_rejected() -> Result<()> {
+    use crate::shared::test_server::{Route, TestServer};
+
+    let server = TestServer::start().await?;
+    let mut route = Route::ok(MULTIPART_BYTERANGES_FIXTURE);
+    route.status = 206;
+    route.headers.push((
+        "Content-Type".into(),
+        "multipart/byteranges; boundary=3d6b6a416f9b5".into(),
+    ));
+    server.route("/multipart.bin", route);
     let adapter = HttpAdapter::new(HttpArgs::default())?;
-    let source = Url::parse("http://speedtest.tele2.net/1MB.zip")?;
-    let stream_result = adapter.receive_data(source).await;
-    match stream_result {
-        Ok(mut stream) => {
-            let next_stream: std::result::Result<Option<Bytes>, CliantError>=stream.try_next().await;
-            assert!(next_stream.is_ok());
-            assert!(next_stream.unwrap().is_some()); // safe to call unwrap here it won't panic.
+    match adapter.receive_data(server.url("/multipart.bin")).await {
+        Err(CliantError::UnexpectedResponse { reason, .. }) => {
+            assert!(reason.contains("multipart/byteranges"), "{reason}");
         }
+        Err(other) => panic!("Expected an unexpected response error, got {other:?}"),
+        Ok(_) => panic!("A multipart/byteranges body must not be streamed to the file"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_non_bytes_content_range_is_rejected() -> Result<()> {
+    use crate::shared::test_server::{Route, TestServer};
+
+    let server = TestServer::start().await?;
+    let mut route = Route::ok("0123456789");
+    route.status = 206;
+    route.headers.push(("Content-Range".into(), "items 0-9/100".into()));
+    server.route("/items", route);
+    let adapter = HttpAdapter::new(HttpArgs::default())?;
+    assert!(matches!(
+        adapter.receive_data(server.url("/items")).await,
+        Err(CliantError::UnexpectedResponse { .. })
+    ));
+    Ok(())
+}
+
+/// A multipart/byteranges payload as sent by a real server (RFC 9110 section 14.6 example).
+#[cfg(test)]
+const MULTIPART_BYTERANGES_FIXTURE: &str = "--3d6b6a416f9b5\r\n\
+Content-Type: text/html\r\n\
+Content-Range: bytes 0-50/1270\r\n\
+\r\n\
+<!doctype html>\n<html>\n<head>\n    <title>Example Do\r\n\
+--3d6b6a416f9b5\r\n\
+Content-Type: text/html\r\n\
+Content-Range: bytes 100-150/1270\r\n\
+\r\n\
+eta http-equiv=\"Content-type\" content=\"text/html; c\r\n\
+--3d6b6a416f9b5--\r\n";
+
+#[tokio::test]
+async fn test_connection_stats_count_http1_responses() -> Result<()> {
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    let server = TestServer::start().await?;
+    server.route("/file.bin", Route::ok(test_body(1024)));
+    let adapter = HttpAdapter::new(HttpArgs::default())?;
+    adapter.download_info(server.url("/file.bin")).await?;
+    let _stream = adapter.receive_data(server.url("/file.bin")).await?;
+    let stats = adapter.connection_stats();
+    assert_eq!(stats.http_version_counts.get("HTTP/1.1"), Some(&2));
+    // The test server closes every connection after one response.
+    assert_eq!(stats.estimated_connections, 2);
+    assert!(stats.only_http1());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_info_above_4gib() -> Result<()> {
+    use crate::shared::test_server::{Route, TestServer};
+
+    // 5 GB doesn't fit a 32-bit usize, sizes must be u64 end to end.
+    let server = TestServer::start().await?;
+    let mut route = Route::status(200);
+    route.headers.push(("Content-Length".into(), "5000000000".into()));
+    server.route("/huge.iso", route);
+    let adapter = HttpAdapter::new(HttpArgs::default())?;
+    let info = adapter.download_info(server.url("/huge.iso")).await?;
+    assert_eq!(info.total_bytes, Some(5_000_000_000));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_status_codes_outside_the_retry_set_fail_fast() -> Result<()> {
+    use super::http::config::RetryArgs;
+    use crate::shared::test_server::{Route, TestServer};
+
+    let server = TestServer::start().await?;
+    server.script("/flaky.bin", [Route::status(409), Route::status(409), Route::ok("content")]);
+    let adapter = HttpAdapter::new(HttpArgs { retry_args: RetryArgs::new(3, 0), ..HttpArgs::default() })?;
+    match adapter.receive_data(server.url("/flaky.bin")).await {
+        Err(CliantError::UnexpectedResponse { reason, .. }) => assert!(reason.contains("409"), "{reason}"),
+        Err(other) => panic!("Expected the 409 to be reported, got {other:?}"),
+        Ok(_) => panic!("409 is not retried by default, the download must fail"),
+    }
+    assert_eq!(server.requests().len(), 1, "Fatal statuses must not be retried");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_on_http_retries_listed_status_codes() -> Result<()> {
+    use tokio_stream::StreamExt;
+    use super::http::config::RetryArgs;
+    use crate::shared::test_server::{Route, TestServer};
 
-        Err(err) => {
-            println!("something happened :{err}");
+    let server = TestServer::start().await?;
+    server.script("/flaky.bin", [Route::status(409), Route::status(500), Route::ok("content")]);
+    let retry_args = RetryArgs { retry_on_http: vec![409], ..RetryArgs::new(3, 0) };
+    let adapter = HttpAdapter::new(HttpArgs { retry_args, ..HttpArgs::default() })?;
+    let (_, mut stream) = adapter.receive_data(server.url("/flaky.bin")).await?;
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    assert_eq!(body, b"content");
+    assert_eq!(server.requests().len(), 3, "Expected a retried 409, a retried 500 and a success");
+    Ok(())
+}
+
+#[test]
+fn test_retry_status_codes_combine_flags_and_defaults() -> Result<()> {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        http_args: HttpArgs,
+    }
+    let cli = Cli::try_parse_from(["cliant", "--retry-on-http", "409", "--no-retry-on-http", "429,500"])?;
+    let codes = cli.http_args.retry_args.retry_status_codes();
+    assert!(codes.contains(&408) && codes.contains(&409) && codes.contains(&502) && codes.contains(&599));
+    assert!(!codes.contains(&429) && !codes.contains(&500) && !codes.contains(&404));
+    assert_eq!(codes.len(), 2 + 99);
+    for invalid in ["99", "600", "5xx"] {
+        assert!(Cli::try_parse_from(["cliant", "--retry-on-http", invalid]).is_err(), "{invalid} should be rejected");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_secrets_never_reach_logs_or_errors() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+    use tokio_stream::StreamExt;
+    use super::http::config::RetryArgs;
+    use crate::shared::test_server::{Route, TestServer};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
         }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SECRETS: &[&str] = &["hunter2", "urlpass", "tok-secret", "cookie-secret", "header-secret"];
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let server = TestServer::start().await?;
+    server.route("/file.bin?token=tok-secret", Route::ok("content"));
+    let args = HttpArgs {
+        username: Some("alice".into()),
+        password: Some(SecretString::from("hunter2")),
+        http_cookies: Some("session=cookie-secret".into()),
+        request_headers: Some("Authorization:Bearer header-secret".into()),
+        retry_args: RetryArgs::new(1, 0),
+        ..HttpArgs::default()
+    };
+    let adapter = HttpAdapter::new(args)?;
+    let mut source = server.url("/file.bin?token=tok-secret");
+    source.set_username("urluser").unwrap();
+    source.set_password(Some("urlpass")).unwrap();
+    adapter.download_info(source.clone()).await?;
+    let (_, mut stream) = adapter.receive_data(source).await?;
+    while stream.next().await.is_some() {}
+
+    // Nothing listens on a port freed right after binding it.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let mut unreachable = url::Url::parse(&format!(
+        "http://urluser:urlpass@{}/file.bin?token=tok-secret",
+        listener.local_addr()?
+    ))?;
+    drop(listener);
+    let Err(err) = adapter.receive_data(unreachable.clone()).await else {
+        panic!("Expected a connection error");
+    };
+    let errors = format!("{err}\n{err:?}");
+    unreachable.set_path("/other.bin");
+    let Err(err) = adapter.download_info(unreachable).await else {
+        panic!("Expected a connection error");
+    };
+    let errors = format!("{errors}\n{err}\n{err:?}");
+
+    let logs = String::from_utf8(captured.0.lock().unwrap().clone())?;
+    assert!(logs.contains("file.bin"), "Expected logs to be captured: {logs}");
+    for secret in SECRETS {
+        assert!(!logs.contains(secret), "{secret} leaked into the logs:\n{logs}");
+        assert!(!errors.contains(secret), "{secret} leaked into an error:\n{errors}");
     }
     Ok(())
 }
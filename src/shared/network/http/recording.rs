@@ -0,0 +1,272 @@
+//! Recording and offline replay of HTTP sessions.
+//!
+//! `--record <dir>` stores every request the adapter sends together with the
+//! full response into numbered body files plus an `index.json`, `--replay <dir>`
+//! answers requests from such a recording without touching the network.
+//! Both are reqwest middlewares installed below the retry middleware, so every
+//! attempt is recorded and a replayed session takes exactly the same retry
+//! decisions as the recorded one.
+//!
+//! Recordings are meant to be shared in bug reports: credentials in the stored
+//! request metadata are redacted (see `shared/redact.rs`), response bodies are
+//! stored verbatim. A body cut by `--record-body-limit` is marked truncated and
+//! replayed with its `Content-Length` rewritten to the stored length.
+//!
+//! **NB:** A recorded response is buffered in memory before it is handed to the
+//! caller, this is a debugging mode and not meant for very large downloads.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use http::Extensions;
+use reqwest::header::{CONTENT_LENGTH, HeaderName, HeaderValue, RANGE};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Error as MiddlewareError, Middleware, Next, Result as MiddlewareResult};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::shared::errors::CliantError;
+use crate::shared::redact::{Redact, redact_header_pairs};
+
+/// Name of the index file inside a recording directory.
+pub const INDEX_FILE: &str = "index.json";
+
+/// Content of `index.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecordingIndex {
+    pub exchanges: Vec<RecordedExchange>,
+}
+
+/// One request and the response it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    pub id: usize,
+    pub method: String,
+    /// Redacted request url, replay matches on this form.
+    pub url: String,
+    pub range: Option<String>,
+    pub request_headers: Vec<(String, String)>,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    /// Body file name relative to the recording directory.
+    pub body_file: String,
+    /// Length of the stored body, which is the truncated length when `body_truncated` is set.
+    pub body_len: usize,
+    pub body_truncated: bool,
+}
+
+/// Requests are matched by method, redacted url and Range header.
+type ExchangeKey = (String, String, Option<String>);
+
+impl RecordedExchange {
+    fn key(&self) -> ExchangeKey {
+        (self.method.clone(), self.url.clone(), self.range.clone())
+    }
+}
+
+fn request_key(req: &Request) -> ExchangeKey {
+    let range = req
+        .headers()
+        .get(RANGE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+    (req.method().to_string(), req.url().redact(), range)
+}
+
+fn describe_key((method, url, range): &ExchangeKey) -> String {
+    match range {
+        Some(range) => format!("{method} {url} (Range: {range})"),
+        None => format!("{method} {url}"),
+    }
+}
+
+fn build_response(
+    status: u16,
+    headers: impl IntoIterator<Item = (HeaderName, HeaderValue)>,
+    body: Bytes,
+) -> MiddlewareResult<Response> {
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    let response = builder.body(body).map_err(|err| MiddlewareError::Middleware(err.into()))?;
+    Ok(Response::from(response))
+}
+
+fn io_error(err: std::io::Error) -> MiddlewareError {
+    MiddlewareError::Middleware(err.into())
+}
+
+/// Writes every exchange passing through it into a recording directory.
+pub struct RecordingMiddleware {
+    dir: PathBuf,
+    body_limit: Option<usize>,
+    index: Mutex<RecordingIndex>,
+}
+
+impl RecordingMiddleware {
+    /// Creates `dir` if needed, an existing recording in it is overwritten.
+    pub fn new(dir: PathBuf, body_limit: Option<usize>) -> Result<Self, CliantError> {
+        std::fs::create_dir_all(&dir)?;
+        info!("Recording HTTP session into {}", dir.display());
+        Ok(Self { dir, body_limit, index: Mutex::new(RecordingIndex::default()) })
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RecordingMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> MiddlewareResult<Response> {
+        let (method, url, range) = request_key(&req);
+        let request_headers = redact_header_pairs(req.headers());
+        let resp = next.run(req, extensions).await?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = resp.bytes().await?;
+
+        let stored = match self.body_limit {
+            Some(limit) if body.len() > limit => body.slice(..limit),
+            _ => body.clone(),
+        };
+        let mut index = self.index.lock().await;
+        let id = index.exchanges.len() + 1;
+        let body_file = format!("{id:04}.body");
+        tokio::fs::write(self.dir.join(&body_file), &stored).await.map_err(io_error)?;
+        debug!("Recorded {method} {url} as exchange {id}");
+        index.exchanges.push(RecordedExchange {
+            id,
+            method,
+            url,
+            range,
+            request_headers,
+            status: status.as_u16(),
+            response_headers: redact_header_pairs(&headers),
+            body_file,
+            body_len: stored.len(),
+            body_truncated: stored.len() < body.len(),
+        });
+        // Rewrite the index after every exchange so an interrupted session is still replayable.
+        let index_json = serde_json::to_vec_pretty(&*index)
+            .map_err(|err| MiddlewareError::Middleware(err.into()))?;
+        tokio::fs::write(self.dir.join(INDEX_FILE), index_json).await.map_err(io_error)?;
+        drop(index);
+
+        let headers = headers.iter().map(|(name, value)| (name.clone(), value.clone()));
+        build_response(status.as_u16(), headers, body)
+    }
+}
+
+/// Answers requests from a recording, the network is never used.
+pub struct ReplayMiddleware {
+    dir: PathBuf,
+    exchanges: Vec<RecordedExchange>,
+    /// Number of responses already served per request key, retried requests
+    /// get the recorded responses in their original order.
+    served: Mutex<HashMap<ExchangeKey, usize>>,
+}
+
+impl ReplayMiddleware {
+    /// Loads the recording index from `dir`.
+    pub fn open(dir: PathBuf) -> Result<Self, CliantError> {
+        let index_path = dir.join(INDEX_FILE);
+        let index_json = std::fs::read(&index_path).map_err(|err| {
+            CliantError::ParseError(format!(
+                "Can't read recording index {}: {err}",
+                index_path.display()
+            ))
+        })?;
+        let index: RecordingIndex = serde_json::from_slice(&index_json).map_err(|err| {
+            CliantError::ParseError(format!(
+                "Invalid recording index {}: {err}",
+                index_path.display()
+            ))
+        })?;
+        info!(
+            "Replaying {} recorded HTTP exchanges from {}",
+            index.exchanges.len(),
+            dir.display()
+        );
+        Ok(Self { dir, exchanges: index.exchanges, served: Mutex::default() })
+    }
+
+    fn mismatch(&self, message: String) -> MiddlewareError {
+        MiddlewareError::Middleware(anyhow!("Replay of {} failed: {message}", self.dir.display()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for ReplayMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        _extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> MiddlewareResult<Response> {
+        let key = request_key(&req);
+        let candidates: Vec<&RecordedExchange> =
+            self.exchanges.iter().filter(|exchange| exchange.key() == key).collect();
+        if candidates.is_empty() {
+            let recorded: Vec<String> =
+                self.exchanges.iter().map(|exchange| describe_key(&exchange.key())).collect();
+            return Err(self.mismatch(format!(
+                "request {} is not present in the recording, recorded requests are: [{}]",
+                describe_key(&key),
+                recorded.join(", ")
+            )));
+        }
+
+        let exchange = {
+            let mut served = self.served.lock().await;
+            let count = served.entry(key.clone()).or_default();
+            let Some(exchange) = candidates.get(*count) else {
+                return Err(self.mismatch(format!(
+                    "request {} was sent {} times but the recording only holds {} responses for it",
+                    describe_key(&key),
+                    *count + 1,
+                    candidates.len()
+                )));
+            };
+            *count += 1;
+            *exchange
+        };
+
+        let body_path = self.dir.join(&exchange.body_file);
+        let body = tokio::fs::read(&body_path).await.map_err(|err| {
+            self.mismatch(format!("can't read body file {}: {err}", body_path.display()))
+        })?;
+        if body.len() != exchange.body_len {
+            return Err(self.mismatch(format!(
+                "body file {} holds {} bytes but exchange {} recorded {} bytes",
+                body_path.display(),
+                body.len(),
+                exchange.id,
+                exchange.body_len
+            )));
+        }
+        debug!("Replaying exchange {} for {}", exchange.id, describe_key(&key));
+
+        let headers = exchange.response_headers.iter().filter_map(|(name, value)| {
+            let name = HeaderName::try_from(name).ok()?;
+            // The recorded length announces bytes --record-body-limit dropped.
+            let value = if exchange.body_truncated && name == CONTENT_LENGTH {
+                HeaderValue::from(exchange.body_len)
+            } else {
+                HeaderValue::try_from(value).ok()?
+            };
+            Some((name, value))
+        });
+        if exchange.body_truncated {
+            warn!(
+                "Exchange {} was truncated to {} bytes by --record-body-limit, replaying that many",
+                exchange.id, exchange.body_len
+            );
+        }
+        build_response(exchange.status, headers, Bytes::from(body))
+    }
+}
@@ -0,0 +1,238 @@
+//! `--resolve HOST:PORT:ADDR[,ADDR...]`: the addresses to connect to for a
+//! host instead of asking DNS, like curl's option of the same name.
+//!
+//! IPv6 addresses are bracketed and may carry a zone, e.g
+//! `[fe80::1%eth0]` for a link-local address reached through `eth0`. The zone
+//! becomes the address' scope id: a numeric zone is used as is, an interface
+//! name is looked up with `if_nametoindex` on unix. Windows only has numeric
+//! zones.
+//!
+//! The overrides also apply to the proxy's host. A proxy url on a zoned
+//! address is reached the same way: hyper connects to an address in a url
+//! without asking the resolver, and without the zone, so the proxy is given
+//! an internal name that is resolved here to the zoned address.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+use reqwest::ClientBuilder;
+use tracing::info;
+use url::{Host, Url};
+
+use super::config::ProxyUrl;
+use crate::shared::errors::CliantError;
+use crate::shared::redact::Redact;
+
+/// Name a proxy on a zoned address is configured under, see [`zoned_proxy`].
+const ZONED_PROXY_HOST: &str = "zoned-proxy.cliant.invalid";
+
+/// A `--resolve` entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveEntry {
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<SocketAddr>,
+}
+
+impl FromStr for ResolveEntry {
+    type Err = CliantError;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| CliantError::ParseError(format!("Invalid --resolve entry {entry}: {reason}"));
+        let mut fields = entry.splitn(3, ':');
+        let (Some(host), Some(port), Some(addresses)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid("expected HOST:PORT:ADDR"));
+        };
+        if host.is_empty() || host.starts_with('[') {
+            return Err(invalid("the host must be a name"));
+        }
+        let port = port.parse::<u16>().map_err(|err| invalid(&format!("bad port {port:?}, {err}")))?;
+        let addresses = addresses
+            .split(',')
+            .map(|address| parse_address(address, port).map_err(|reason| invalid(&reason)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { host: host.to_ascii_lowercase(), port, addresses })
+    }
+}
+
+/// `192.0.2.1`, `[2001:db8::1]` or `[fe80::1%eth0]`.
+fn parse_address(address: &str, port: u16) -> Result<SocketAddr, String> {
+    let Some(bracketed) = address.strip_prefix('[') else {
+        if address.contains(':') {
+            return Err(format!("IPv6 addresses must be bracketed, e.g [{address}]"));
+        }
+        let ip = IpAddr::from_str(address).map_err(|err| format!("bad address {address:?}, {err}"))?;
+        return Ok(SocketAddr::new(ip, port));
+    };
+    let literal = bracketed.strip_suffix(']').ok_or_else(|| format!("missing ] in {address}"))?;
+    let (ip, zone) = match literal.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (literal, None),
+    };
+    let ip = Ipv6Addr::from_str(ip).map_err(|err| format!("bad IPv6 address {ip:?}, {err}"))?;
+    let scope_id = zone.map(scope_id).transpose()?.unwrap_or_default();
+    Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+/// The scope id of the zone `eth0` or `2`.
+fn scope_id(zone: &str) -> Result<u32, String> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Ok(index);
+    }
+    interface_index(zone)
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Result<u32, String> {
+    let unknown = || format!("unknown network interface {name:?} in the zone");
+    let name = std::ffi::CString::new(name).map_err(|_| unknown())?;
+    // SAFETY: `name` is NUL terminated.
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(unknown()),
+        index => Ok(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(name: &str) -> Result<u32, String> {
+    Err(format!(
+        "zones must be interface indexes on this platform, e.g %12 instead of %{name} (see `netsh interface ipv6 show interfaces`)"
+    ))
+}
+
+/// For a proxy on a zoned address, the url to configure instead, named
+/// [`ZONED_PROXY_HOST`], and the override connecting that name to the address.
+pub(super) fn zoned_proxy(proxy: &ProxyUrl) -> Result<Option<(Url, ResolveEntry)>, CliantError> {
+    let invalid = |reason: &str| CliantError::ParseError(format!("Invalid proxy url {}: {reason}", proxy.redact()));
+    let Some(zone) = &proxy.zone else {
+        return Ok(None);
+    };
+    let Some(Host::Ipv6(ip)) = proxy.url.host() else {
+        return Err(invalid("only IPv6 addresses have zones"));
+    };
+    let port = proxy.url.port_or_known_default().unwrap_or_default();
+    let scope_id = scope_id(zone).map_err(|reason| invalid(&reason))?;
+    let mut named = proxy.url.clone();
+    named.set_host(Some(ZONED_PROXY_HOST)).map_err(|err| invalid(&err.to_string()))?;
+    let entry = ResolveEntry {
+        host: ZONED_PROXY_HOST.into(),
+        port,
+        addresses: vec![SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id))],
+    };
+    Ok(Some((named, entry)))
+}
+
+/// Clap value parser for `--resolve`.
+pub fn parse_resolve(entry: &str) -> Result<ResolveEntry, String> {
+    entry.parse().map_err(|err: CliantError| err.to_string())
+}
+
+/// Connects to the `--resolve` addresses of their hosts.
+///
+/// Overrides match on the host alone: the url's port is the one connected to,
+/// an entry's port only documents which one it was meant for.
+pub(super) fn apply(mut builder: ClientBuilder, entries: &[ResolveEntry]) -> ClientBuilder {
+    for entry in entries {
+        info!("Resolving {} to {:?}", entry.host, entry.addresses);
+        builder = builder.resolve_to_addrs(&entry.host, &entry.addresses);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::network::DataTransport;
+    use crate::shared::network::http::HttpAdapter;
+    use crate::shared::network::http::config::HttpArgs;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_entries() -> anyhow::Result<()> {
+        let entry: ResolveEntry = "Mirror.example:443:192.0.2.1,[2001:db8::1]".parse()?;
+        assert_eq!(entry.host, "mirror.example");
+        assert_eq!(
+            entry.addresses,
+            [SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 443)), SocketAddr::from(("2001:db8::1".parse::<Ipv6Addr>()?, 443))]
+        );
+        let zoned: ResolveEntry = "router:80:[fe80::1%3]".parse()?;
+        assert!(matches!(zoned.addresses[0], SocketAddr::V6(address) if address.scope_id() == 3 && address.port() == 80));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_zone_names_are_interface_indexes() -> anyhow::Result<()> {
+        let entry: ResolveEntry = "router:443:[fe80::1%lo]".parse()?;
+        let SocketAddr::V6(address) = entry.addresses[0] else { panic!("{entry:?}") };
+        assert_eq!(address.scope_id(), interface_index("lo").unwrap());
+        let err = "router:443:[fe80::1%cliant-bogus0]".parse::<ResolveEntry>().unwrap_err();
+        assert!(err.to_string().contains("unknown network interface \"cliant-bogus0\""), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_entries() {
+        for (entry, reason) in [
+            ("host:443", "expected HOST:PORT:ADDR"),
+            ("host:https:192.0.2.1", "bad port"),
+            ("host:70000:192.0.2.1", "bad port"),
+            ("host:443:fe80::1", "must be bracketed"),
+            ("host:443:[fe80::1", "missing ]"),
+            ("host:443:[192.0.2.1]", "bad IPv6 address"),
+            ("host:443:192.0.2.1%eth0", "bad address"),
+            (":443:192.0.2.1", "must be a name"),
+        ] {
+            let err = parse_resolve(entry).unwrap_err();
+            assert!(err.contains(reason), "{entry}: {err}");
+        }
+    }
+
+    /// Fetches from a server on `ip` through a name only `--resolve` knows.
+    async fn fetch_resolved(ip: IpAddr, address: &str) -> anyhow::Result<()> {
+        let server = TestServer::start_on(ip).await?;
+        server.route("/file.bin", Route::ok(test_body(1024)));
+        let port = server.url("/").port().unwrap();
+        let adapter = HttpAdapter::new(HttpArgs {
+            resolve: vec![parse_resolve(&format!("cliant.invalid:{port}:{address}")).unwrap()],
+            ..HttpArgs::default()
+        })?;
+        let url = url::Url::parse(&format!("http://cliant.invalid:{port}/file.bin"))?;
+        let (info, _) = adapter.receive_data(url).await?;
+        assert_eq!(info.total_bytes, Some(1024));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_zoned_proxy_is_reached() -> anyhow::Result<()> {
+        let proxy = TestServer::start_on(IpAddr::V6(Ipv6Addr::LOCALHOST)).await?;
+        proxy.route("http://cliant.invalid/file.bin", Route::ok(test_body(1024)));
+        let port = proxy.url("/").port().unwrap();
+        let adapter = HttpAdapter::new(HttpArgs {
+            proxy_url: Some(format!("http://[::1%25lo]:{port}")),
+            ..HttpArgs::default()
+        })?;
+        let (info, _) = adapter.receive_data(url::Url::parse("http://cliant.invalid/file.bin")?).await?;
+        assert_eq!(info.total_bytes, Some(1024));
+        assert_eq!(proxy.requests().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zoned_proxy_is_named() -> anyhow::Result<()> {
+        let proxy = crate::shared::network::http::config::parse_proxy_url("http://[fe80::1%253]:3128")?;
+        let (url, entry) = zoned_proxy(&proxy)?.unwrap();
+        assert_eq!(url.as_str(), format!("http://{ZONED_PROXY_HOST}:3128/"));
+        assert_eq!(entry.host, ZONED_PROXY_HOST);
+        assert!(matches!(entry.addresses[..], [SocketAddr::V6(address)] if address.scope_id() == 3 && address.port() == 3128));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolved_names_reach_local_server() -> anyhow::Result<()> {
+        fetch_resolved(IpAddr::V4(Ipv4Addr::LOCALHOST), "127.0.0.1").await?;
+        fetch_resolved(IpAddr::V6(Ipv6Addr::LOCALHOST), "[::1]").await
+    }
+}
@@ -0,0 +1,90 @@
+//! Classification of HTTP responses and errors into retryable or not.
+//!
+//! Connection failures and timeouts are classified by reqwest-retry's defaults.
+//! Responses are retried only for the status codes in the configured set, which
+//! starts from [`default_retry_status_codes`] and is adjusted with
+//! `--retry-on-http` / `--no-retry-on-http`. Any other error status fails fast.
+
+use std::collections::BTreeSet;
+
+use reqwest_middleware::Error as MiddlewareError;
+use reqwest_retry::{Retryable, RetryableStrategy, default_on_request_failure};
+
+use crate::shared::errors::CliantError;
+
+/// Statuses retried unless `--no-retry-on-http` removes them, the ones
+/// reqwest-retry treats as transient: request timeout, too many requests and
+/// every server error.
+pub fn default_retry_status_codes() -> impl Iterator<Item = u16> {
+    [408, 429].into_iter().chain(500..=599)
+}
+
+/// Parses one HTTP status code, rejecting anything outside 100–599.
+pub fn parse_status_code(raw: &str) -> Result<u16, CliantError> {
+    let code: u16 = raw.trim().parse().map_err(|_| {
+        CliantError::ParseError(format!("Invalid HTTP status code '{raw}', expected a number."))
+    })?;
+    if !(100..=599).contains(&code) {
+        return Err(CliantError::ParseError(format!(
+            "Invalid HTTP status code {code}, status codes range from 100 to 599."
+        )));
+    }
+    Ok(code)
+}
+
+///Clap value parser for `--retry-on-http` and `--no-retry-on-http`.
+pub(super) fn validate_status_code(raw: &str) -> Result<u16, String> {
+    parse_status_code(raw).map_err(|err| err.to_string())
+}
+
+/// Retries responses whose status is in `codes`, lets everything else through.
+pub struct RetryOnStatus {
+    codes: BTreeSet<u16>,
+}
+
+impl RetryOnStatus {
+    pub fn new(codes: BTreeSet<u16>) -> Self {
+        Self { codes }
+    }
+}
+
+impl RetryableStrategy for RetryOnStatus {
+    fn handle(&self, res: &Result<reqwest::Response, MiddlewareError>) -> Option<Retryable> {
+        match res {
+            Ok(resp) if self.codes.contains(&resp.status().as_u16()) => Some(Retryable::Transient),
+            Ok(resp) if resp.status().is_success() => None,
+            Ok(_) => Some(Retryable::Fatal),
+            Err(err) => default_on_request_failure(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status_code_bounds() {
+        assert_eq!(parse_status_code("100").unwrap(), 100);
+        assert_eq!(parse_status_code(" 520 ").unwrap(), 520);
+        assert_eq!(parse_status_code("599").unwrap(), 599);
+        for invalid in ["99", "600", "0", "-1", "abc", "", "70000"] {
+            assert!(parse_status_code(invalid).is_err(), "{invalid:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_retry_on_status_classification() {
+        let strategy = RetryOnStatus::new(BTreeSet::from([409, 520]));
+        let classify = |status: u16| {
+            let resp = http::Response::builder().status(status).body("").unwrap();
+            strategy.handle(&Ok(reqwest::Response::from(resp)))
+        };
+        // `Retryable` has no Debug impl, so compare without assert_eq.
+        assert!(classify(520) == Some(Retryable::Transient));
+        assert!(classify(409) == Some(Retryable::Transient));
+        assert!(classify(500) == Some(Retryable::Fatal));
+        assert!(classify(404) == Some(Retryable::Fatal));
+        assert!(classify(200).is_none());
+    }
+}
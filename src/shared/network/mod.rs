@@ -1,16 +1,115 @@
+use std::time::SystemTime;
+
 use bytes::Bytes; 
 use anyhow::Result;
 use tokio_stream::Stream;
 use url::Url;
+use crate::shared::checksum::Checksum;
 use crate::shared::errors::CliantError;
+use stats::ConnectionStats;
 
 #[cfg(feature="local")]
 pub mod http;
 
 pub mod factory;
+pub mod stats;
+
+/// Metadata of a remote resource, fetched before its content.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadInfo {
+    pub total_bytes: Option<u64>,
+    /// The server's modification time of the resource, if it reported a valid one.
+    pub last_modified: Option<SystemTime>,
+    /// Digest the source announced for the content, verified once it is written.
+    pub checksum: Option<Checksum>,
+    /// Media type of the content, e.g `text/html; charset=utf-8`.
+    pub content_type: Option<String>,
+    /// Raw `Content-Disposition` (or equivalent) of the content, if the source sent one.
+    pub content_disposition: Option<String>,
+    /// Coding applied to the content in transit, e.g `gzip`; the content is passed on still encoded.
+    pub content_encoding: Option<String>,
+    /// The content is only the requested range of the resource.
+    pub partial: bool,
+    /// The window of the resource the content is, from a valid `Content-Range`.
+    pub content_range: Option<ContentRange>,
+    /// The source announced it serves byte ranges of the resource (`Accept-Ranges: bytes`).
+    pub accepts_ranges: bool,
+}
+
+/// Bytes `first` to `last`, both included, of a resource of `complete_length` bytes if known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub first: u64,
+    pub last: u64,
+    pub complete_length: Option<u64>,
+}
+
+/// A window of a resource to fetch instead of all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The first `n` bytes.
+    Head(u64),
+    /// The last `n` bytes.
+    Tail(u64),
+    /// Bytes `first` to `last`, both included.
+    Span { first: u64, last: u64 },
+}
 
 pub trait DataTransport:Send+Sync{
-    async fn receive_data(&self,source:Url) -> Result<impl Stream<Item = Result<Bytes,CliantError>>+Unpin,CliantError>;
-    async fn total_bytes(&self,source:Url)->Result<Option<usize>,CliantError> ;
+    /// Starts fetching `source`, returns what the response tells about the resource along with its content.
+    async fn receive_data(&self,source:Url) -> Result<(DownloadInfo,impl Stream<Item = Result<Bytes,CliantError>>+Unpin),CliantError>;
+    /// Like `receive_data` for a window of `source`. A source that can't serve ranges
+    /// sends all of the content, `DownloadInfo::partial` tells which one happened.
+    async fn receive_range(&self,source:Url,range:ByteRange) -> Result<(DownloadInfo,impl Stream<Item = Result<Bytes,CliantError>>+Unpin),CliantError>;
+    async fn download_info(&self,source:Url)->Result<DownloadInfo,CliantError>;
+    /// Whether `receive_range` can fetch windows of a resource, for downloading it in parts.
+    fn supports_ranges(&self)->bool;
+    /// Protocol versions and connections used so far.
+    fn connection_stats(&self)->ConnectionStats;
 }
 
+
+/// Splits `bytes` into zero-copy pieces of at most `max_len` bytes.
+pub fn split_chunk(mut bytes: Bytes, max_len: usize) -> impl Iterator<Item = Bytes> {
+    std::iter::from_fn(move || match bytes.len() {
+        0 => None,
+        len if len <= max_len => Some(std::mem::take(&mut bytes)),
+        _ => Some(bytes.split_to(max_len)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: usize = 1024 * 1024;
+
+    #[test]
+    fn test_split_chunk_is_zero_copy() {
+        let original = Bytes::from(vec![7u8; 16 * MIB]);
+        let base = original.as_ptr() as usize;
+        let pieces: Vec<Bytes> = split_chunk(original.clone(), MIB).collect();
+        assert_eq!(pieces.len(), 16);
+        for (i, piece) in pieces.iter().enumerate() {
+            assert_eq!(piece.len(), MIB);
+            // Every piece points into the original allocation.
+            assert_eq!(piece.as_ptr() as usize, base + i * MIB);
+        }
+    }
+
+    #[test]
+    fn test_split_chunk_reassembles() {
+        let original: Bytes = (0..(4 * MIB + 123)).map(|i| (i % 251) as u8).collect::<Vec<u8>>().into();
+        let pieces: Vec<Bytes> = split_chunk(original.clone(), MIB).collect();
+        assert_eq!(pieces.len(), 5);
+        assert_eq!(pieces.last().unwrap().len(), 123);
+        assert_eq!(pieces.concat(), original);
+    }
+
+    #[test]
+    fn test_split_chunk_small_and_empty() {
+        let small = Bytes::from_static(b"small");
+        assert_eq!(split_chunk(small.clone(), MIB).collect::<Vec<_>>(), [small]);
+        assert_eq!(split_chunk(Bytes::new(), MIB).count(), 0);
+    }
+}
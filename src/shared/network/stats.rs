@@ -0,0 +1,65 @@
+//! Protocol and connection usage of a transport, for diagnosing throughput.
+//!
+//! Protocol versions are counted exactly from every response. Connections are
+//! estimated by counting distinct local socket addresses, which undercounts
+//! when the OS reuses a local port for a later connection to the same peer.
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+
+use serde::Serialize;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ConnectionStats {
+    /// Responses per protocol version, e.g `{"HTTP/1.1": 2}`.
+    pub http_version_counts: BTreeMap<String, u64>,
+    /// Distinct connections seen, an estimate (see module docs).
+    pub estimated_connections: usize,
+    #[serde(skip)]
+    local_addrs: HashSet<SocketAddr>,
+}
+
+impl ConnectionStats {
+    /// Records one response, `local_addr` is the local end of its connection when known.
+    pub fn observe(&mut self, version: &str, local_addr: Option<SocketAddr>) {
+        *self.http_version_counts.entry(version.to_string()).or_default() += 1;
+        if let Some(local_addr) = local_addr {
+            self.local_addrs.insert(local_addr);
+            self.estimated_connections = self.local_addrs.len();
+        }
+    }
+
+    /// Whether responses were received and every one of them used HTTP/1.x.
+    pub fn only_http1(&self) -> bool {
+        !self.http_version_counts.is_empty()
+            && self.http_version_counts.keys().all(|version| version.starts_with("HTTP/1") || version.starts_with("HTTP/0"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_versions_and_connections() {
+        let mut stats = ConnectionStats::default();
+        let first: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let second: SocketAddr = "127.0.0.1:50001".parse().unwrap();
+        stats.observe("HTTP/1.1", Some(first));
+        stats.observe("HTTP/1.1", Some(first));
+        stats.observe("HTTP/2.0", Some(second));
+        stats.observe("HTTP/2.0", None);
+        assert_eq!(stats.http_version_counts["HTTP/1.1"], 2);
+        assert_eq!(stats.http_version_counts["HTTP/2.0"], 2);
+        assert_eq!(stats.estimated_connections, 2);
+        assert!(!stats.only_http1());
+    }
+
+    #[test]
+    fn test_only_http1() {
+        let mut stats = ConnectionStats::default();
+        assert!(!stats.only_http1(), "No responses is not a downgrade");
+        stats.observe("HTTP/1.1", None);
+        assert!(stats.only_http1());
+    }
+}
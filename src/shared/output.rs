@@ -0,0 +1,131 @@
+//! User-facing output on stdout and stderr that outlives its reader.
+//!
+//! `println!` panics once the reader of a pipe went away (`cliant ... 2>&1 |
+//! head -1`), turning a finished download into a crash report. Output here
+//! goes quiet instead: the first broken pipe silences the stream for the rest
+//! of the run, and the exit code still tells how the download went.
+//!
+//! `cliant cat` writes the content itself and stops fetching on a broken pipe.
+//! The progress bar ignores failed draws, and the log layer is built without
+//! its internal error reports, which would go to the same closed stderr.
+
+use std::fmt;
+use std::io::{self, ErrorKind, Write};
+use std::sync::{LazyLock, Mutex};
+
+use tracing::debug;
+
+static STDOUT: LazyLock<Mutex<Output<io::Stdout>>> = LazyLock::new(|| Mutex::new(Output::new(io::stdout())));
+static STDERR: LazyLock<Mutex<Output<io::Stderr>>> = LazyLock::new(|| Mutex::new(Output::new(io::stderr())));
+
+/// A stream that is silenced once its reader closed it.
+pub struct Output<W> {
+    writer: W,
+    closed: bool,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, closed: false }
+    }
+
+    /// Writes and flushes `args`, nothing once the reader is gone.
+    pub fn print(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        match self.writer.write_fmt(args).and_then(|()| self.writer.flush()) {
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                debug!("The reader closed the pipe, not printing anything more to it");
+                self.closed = true;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Prints `args` to stdout, other errors than a closed pipe are returned.
+pub fn stdout(args: fmt::Arguments<'_>) -> io::Result<()> {
+    STDOUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).print(args)
+}
+
+/// Prints `args` to stderr, where there is nowhere left to report a failure.
+pub fn stderr(args: fmt::Arguments<'_>) {
+    let _ = STDERR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).print(args);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts `capacity` bytes, then fails like a pipe whose reader exited.
+    struct ClosingPipe {
+        written: Vec<u8>,
+        capacity: usize,
+        attempts: usize,
+    }
+
+    impl Write for ClosingPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.attempts += 1;
+            let room = self.capacity - self.written.len();
+            if room == 0 {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+            let len = buf.len().min(room);
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_closed_pipe_silences_the_stream() {
+        let mut output = Output::new(ClosingPipe { written: Vec::new(), capacity: 8, attempts: 0 });
+        output.print(format_args!("line {}\n", 1)).unwrap();
+        output.print(format_args!("line {}\n", 2)).unwrap();
+        let attempts = output.writer.attempts;
+        for line in 3..10 {
+            output.print(format_args!("line {line}\n")).unwrap();
+        }
+        assert!(output.closed);
+        assert_eq!(output.writer.written, b"line 1\nl");
+        assert_eq!(output.writer.attempts, attempts, "Nothing is written once the pipe broke");
+    }
+
+    #[test]
+    fn test_reader_exiting_early() -> anyhow::Result<()> {
+        // The write end of a pipe whose reader is gone, as with `| head -1` once head exited.
+        let (reader, writer) = io::pipe()?;
+        drop(reader);
+        let mut output = Output::new(writer);
+        for _ in 0..3 {
+            output.print(format_args!("{}\n", "x".repeat(64 * 1024)))?;
+        }
+        assert!(output.closed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_errors_are_returned() {
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(ErrorKind::StorageFull.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut output = Output::new(Full);
+        assert_eq!(output.print(format_args!("report")).unwrap_err().kind(), ErrorKind::StorageFull);
+        assert!(!output.closed);
+    }
+}
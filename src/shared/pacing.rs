@@ -0,0 +1,71 @@
+//! Cooperative yielding for the chunk loops of a transfer.
+//!
+//! Every loop that moves chunks (the transport reading a body, the handler
+//! writing it in one stream or in parts) counts them with a [`ChunkPacer`],
+//! which hands the worker back to the scheduler every [`YIELD_EVERY_CHUNKS`]
+//! chunks. Tokio's own budget only preempts budgeted operations, e.g channel
+//! receives, not buffered writes or hashing that complete immediately.
+
+use crate::shared::constants::YIELD_EVERY_CHUNKS;
+
+/// Counts the chunks of a loop and yields every [`YIELD_EVERY_CHUNKS`] of them.
+#[derive(Debug, Default)]
+pub struct ChunkPacer {
+    chunks: u64,
+}
+
+impl ChunkPacer {
+    /// Call once per chunk, lets other tasks of the runtime run now and then.
+    pub async fn chunk(&mut self) {
+        self.chunks += 1;
+        if self.chunks.is_multiple_of(YIELD_EVERY_CHUNKS) {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// A loop whose chunks are always ready lets a task sharing its thread run
+    /// once every `YIELD_EVERY_CHUNKS` chunks, never without the pacer.
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ready_chunks_leave_room_for_other_tasks() {
+        const CHUNKS: u64 = 100 * YIELD_EVERY_CHUNKS;
+        let ticks = Arc::new(AtomicU64::new(0));
+        let ticker_ticks = ticks.clone();
+        let ticker = tokio::spawn(async move {
+            loop {
+                ticker_ticks.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let mut written = Vec::new();
+        let mut pacer = ChunkPacer::default();
+        let mut longest_stretch = 0;
+        let mut stretch = 0;
+        let mut seen = ticks.load(Ordering::Relaxed);
+        for chunk in 0..CHUNKS {
+            // A buffered write, ready at once like a chunk already in the channel.
+            written.extend_from_slice(&chunk.to_le_bytes());
+            pacer.chunk().await;
+            stretch += 1;
+            let now = ticks.load(Ordering::Relaxed);
+            if now != seen {
+                (seen, stretch) = (now, 0);
+            }
+            longest_stretch = longest_stretch.max(stretch);
+        }
+        ticker.abort();
+        assert_eq!(written.len() as u64, CHUNKS * 8);
+        assert!(
+            longest_stretch < YIELD_EVERY_CHUNKS,
+            "The ticker waited {longest_stretch} chunks, {} ticks in {CHUNKS} chunks",
+            ticks.load(Ordering::Relaxed)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Multipart downloads: splitting a resource into byte ranges that are fetched
+//! concurrently and written in place.
+//!
+//! A download is split when the transport can fetch ranges, the filesystem can
+//! write at an offset, the source announced `Accept-Ranges: bytes` and the size
+//! is known and covers at least two parts. The first part is read from the
+//! response of the initial GET, which is dropped once it is written, so a
+//! split download costs no extra request for its first part.
+//!
+//! [`MultipartArgs::decide`] consults every rule, even once one ruled the split
+//! out, and explains each, which `--explain-plan` prints.
+//!
+//! The part count is capped by `--max-parts` in [`MultipartArgs::plan`], the
+//! one place parts are planned: past the cap the part size grows instead.
+
+use std::fmt;
+
+use clap::Args;
+use tracing::info;
+
+use super::constants::{MAX_CONCURRENT_PARTS, MAX_PARTS, PART_SIZE};
+
+#[derive(Args, Debug, Clone)]
+pub struct MultipartArgs {
+    /// Size of the byte ranges a download is split into when the server accepts ranges.
+    /// Files smaller than two parts are downloaded in a single stream.
+    #[arg(long, value_name = "BYTES", default_value_t = PART_SIZE, value_parser = clap::value_parser!(u64).range(1..))]
+    pub part_size: u64,
+    /// Most parts a download is split into, larger parts than --part-size are used past it.
+    #[arg(long, value_name = "N", default_value_t = MAX_PARTS, value_parser = clap::value_parser!(u64).range(2..))]
+    pub max_parts: u64,
+    /// Split into parts of --part-size however many there are.
+    #[arg(long, conflicts_with = "max_parts")]
+    pub no_part_cap: bool,
+    /// Most parts downloaded at the same time.
+    #[arg(long, value_name = "N", default_value_t = MAX_CONCURRENT_PARTS, value_parser = parse_concurrency)]
+    pub max_concurrent_parts: usize,
+    /// Download in a single stream even when the server accepts ranges.
+    #[arg(long)]
+    pub no_multipart: bool,
+    /// Print why the download is or isn't split into parts, and how, before it starts.
+    #[arg(long)]
+    pub explain_plan: bool,
+}
+
+impl Default for MultipartArgs {
+    fn default() -> Self {
+        Self {
+            part_size: PART_SIZE,
+            max_parts: MAX_PARTS,
+            no_part_cap: false,
+            max_concurrent_parts: MAX_CONCURRENT_PARTS,
+            no_multipart: false,
+            explain_plan: false,
+        }
+    }
+}
+
+/// What the planner knows of a download once its response arrived.
+#[derive(Debug, Clone, Default)]
+pub struct PlanInputs {
+    pub total: Option<u64>,
+    pub transport_ranges: bool,
+    pub write_at: bool,
+    pub accepts_ranges: bool,
+    pub content_encoding: Option<String>,
+    pub converts_text: bool,
+    pub verifies_writes: bool,
+}
+
+/// A rule the planner consulted, what it saw and what it concluded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub rule: &'static str,
+    pub effect: String,
+}
+
+/// `size: 150 bytes < 200 bytes (2 parts of --part-size) → single stream`.
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.effect)
+    }
+}
+
+impl MultipartArgs {
+    /// The parts of the download described by `inputs`, `None` for a single
+    /// stream, and the reasons for either.
+    pub fn decide(&self, inputs: &PlanInputs) -> (Option<Vec<Part>>, Vec<Explanation>) {
+        // Converted text and --verify-writes need the content in order.
+        let checks = [
+            ("--no-multipart", !self.no_multipart, "not set", "set"),
+            ("transport", inputs.transport_ranges, "fetches byte ranges", "can't fetch byte ranges"),
+            ("filesystem", inputs.write_at, "writes at offsets", "can't write at offsets"),
+            ("Accept-Ranges", inputs.accepts_ranges, "bytes", "not announced, the server may ignore ranges"),
+            ("--text-mode", !inputs.converts_text, "no conversion", "converting, needs the content in order"),
+            ("--verify-writes", !inputs.verifies_writes, "not set", "set, needs the content in order"),
+        ];
+        let mut explanations = Vec::new();
+        let mut splits = true;
+        let mut explain = |rule: &'static str, allows: bool, effect: String| {
+            let effect = if allows { effect } else { format!("{effect} → single stream") };
+            explanations.push(Explanation { rule, effect });
+            splits &= allows;
+        };
+        for (rule, allows, allowing, refusing) in checks {
+            explain(rule, allows, if allows { allowing } else { refusing }.into());
+        }
+        match &inputs.content_encoding {
+            Some(encoding) => explain("Content-Encoding", false, format!("{encoding}, ranges would be of the encoded body")),
+            None => explain("Content-Encoding", true, "none".into()),
+        }
+        let threshold = self.part_size.saturating_mul(2);
+        match inputs.total {
+            None => explain("size", false, "unknown".into()),
+            Some(total) if total < threshold => {
+                explain("size", false, format!("{total} bytes < {threshold} bytes (2 parts of --part-size)"))
+            }
+            Some(total) => explain("size", true, format!("{total} bytes, at least 2 parts of {} bytes", self.part_size)),
+        }
+        if !splits {
+            return (None, explanations);
+        }
+        let parts = self.plan(inputs.total);
+        if let (Some(parts), Some(total)) = (&parts, inputs.total) {
+            let part_size = self.part_size_for(total);
+            let raised = match part_size > self.part_size {
+                true => format!(" (--part-size {} raised to fit --max-parts {})", self.part_size, self.max_parts),
+                false => String::new(),
+            };
+            let concurrency = self.max_concurrent_parts.min(parts.len());
+            explanations.push(Explanation {
+                rule: "parts",
+                effect: format!(
+                    "{} of up to {part_size} bytes{raised}, {concurrency} at a time (--max-concurrent-parts {}) → multipart",
+                    parts.len(),
+                    self.max_concurrent_parts
+                ),
+            });
+        }
+        (parts, explanations)
+    }
+
+    /// The parts of a download of `total` bytes, `None` when it is better fetched in one stream.
+    pub fn plan(&self, total: Option<u64>) -> Option<Vec<Part>> {
+        let total = total?;
+        if self.no_multipart || total < self.part_size.saturating_mul(2) {
+            return None;
+        }
+        let part_size = self.part_size_for(total);
+        if part_size > self.part_size {
+            info!(
+                "Using {part_size} byte parts (capped at {} parts) instead of the requested {}",
+                self.max_parts, self.part_size
+            );
+        }
+        Some(split(total, part_size))
+    }
+
+    /// `--part-size`, raised for a download of `total` bytes to fit in `--max-parts`.
+    pub fn part_size_for(&self, total: u64) -> u64 {
+        match self.no_part_cap {
+            true => self.part_size,
+            false => self.part_size.max(total.div_ceil(self.max_parts)),
+        }
+    }
+}
+
+/// A byte range of the resource, `first` and `last` included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl Part {
+    pub fn size(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+/// Consecutive parts of `part_size` bytes covering `total` bytes, the last one shorter.
+fn split(total: u64, part_size: u64) -> Vec<Part> {
+    (0..total.div_ceil(part_size))
+        .map(|index| {
+            let first = index * part_size;
+            Part { first, last: (first + part_size).min(total) - 1 }
+        })
+        .collect()
+}
+
+fn parse_concurrency(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("at least one part must be downloaded at a time".into()),
+        Ok(concurrency) => Ok(concurrency),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parts_cover_the_resource() {
+        let args = MultipartArgs { part_size: 100, ..MultipartArgs::default() };
+        let parts = args.plan(Some(250)).unwrap();
+        assert_eq!(
+            parts,
+            [Part { first: 0, last: 99 }, Part { first: 100, last: 199 }, Part { first: 200, last: 249 }]
+        );
+        assert_eq!(parts.iter().map(Part::size).sum::<u64>(), 250);
+        assert_eq!(args.plan(Some(200)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_single_stream_cases() {
+        let args = MultipartArgs { part_size: 100, ..MultipartArgs::default() };
+        assert_eq!(args.plan(None), None, "An unknown size can't be split");
+        assert_eq!(args.plan(Some(199)), None, "Less than two parts");
+        assert_eq!(MultipartArgs { no_multipart: true, ..args }.plan(Some(1000)), None);
+        assert!(parse_concurrency("0").is_err());
+    }
+
+    fn explained(args: &MultipartArgs, inputs: &PlanInputs) -> (Option<Vec<Part>>, String) {
+        let (parts, explanations) = args.decide(inputs);
+        let rules: Vec<_> = explanations.iter().map(|explanation| explanation.rule).collect();
+        for rule in ["--no-multipart", "transport", "filesystem", "Accept-Ranges", "Content-Encoding", "--text-mode", "--verify-writes", "size"] {
+            assert!(rules.contains(&rule), "{rule} is not explained: {rules:?}");
+        }
+        let rendered = explanations.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n");
+        (parts, rendered)
+    }
+
+    #[test]
+    fn test_decisions_are_explained() {
+        let args = MultipartArgs { part_size: 100, max_concurrent_parts: 4, ..MultipartArgs::default() };
+        let capable = PlanInputs { transport_ranges: true, write_at: true, accepts_ranges: true, ..PlanInputs::default() };
+
+        let (parts, tiny) = explained(&args, &PlanInputs { total: Some(150), ..capable.clone() });
+        assert_eq!(parts, None);
+        assert!(tiny.ends_with("size: 150 bytes < 200 bytes (2 parts of --part-size) → single stream"), "{tiny}");
+        assert_eq!(tiny.matches("→").count(), 1, "Only the size refused the split: {tiny}");
+
+        let (parts, huge) = explained(&args, &PlanInputs { total: Some(1000), ..capable.clone() });
+        assert_eq!(parts.map(|parts| parts.len()), Some(10));
+        assert_eq!(
+            huge,
+            "--no-multipart: not set\n\
+             transport: fetches byte ranges\n\
+             filesystem: writes at offsets\n\
+             Accept-Ranges: bytes\n\
+             --text-mode: no conversion\n\
+             --verify-writes: not set\n\
+             Content-Encoding: none\n\
+             size: 1000 bytes, at least 2 parts of 100 bytes\n\
+             parts: 10 of up to 100 bytes, 4 at a time (--max-concurrent-parts 4) → multipart"
+        );
+
+        let (parts, unranged) = explained(&args, &PlanInputs { total: Some(1000), accepts_ranges: false, ..capable });
+        assert_eq!(parts, None);
+        assert!(unranged.contains("Accept-Ranges: not announced, the server may ignore ranges → single stream"), "{unranged}");
+        assert!(!unranged.contains("parts:"), "{unranged}");
+    }
+
+    #[test]
+    fn test_part_count_is_capped() {
+        let args = MultipartArgs { part_size: 1, max_concurrent_parts: 4, ..MultipartArgs::default() };
+        let total = 5 * 1024 * 1024 * 1024;
+        let parts = args.plan(Some(total)).unwrap();
+        assert_eq!(parts.len() as u64, MAX_PARTS);
+        assert_eq!(parts.iter().map(Part::size).sum::<u64>(), total);
+        assert_eq!(parts[0].size(), total.div_ceil(MAX_PARTS));
+
+        let inputs = PlanInputs { total: Some(total), transport_ranges: true, write_at: true, accepts_ranges: true, ..PlanInputs::default() };
+        let (_, explanations) = args.decide(&inputs);
+        assert_eq!(
+            explanations.last().unwrap().to_string(),
+            "parts: 2048 of up to 2621440 bytes (--part-size 1 raised to fit --max-parts 2048), 4 at a time (--max-concurrent-parts 4) → multipart"
+        );
+        let capped = MultipartArgs { part_size: 100, max_parts: 10, ..args };
+        assert_eq!(capped.plan(Some(1000)).unwrap().len(), 10, "Exactly at the cap, the size is kept");
+        assert_eq!(capped.part_size_for(1000), 100);
+        assert_eq!(capped.plan(Some(1001)).unwrap().len(), 10);
+        assert_eq!(capped.part_size_for(1001), 101);
+    }
+
+    #[test]
+    fn test_no_part_cap() {
+        let args = MultipartArgs { part_size: 100, max_parts: 10, no_part_cap: true, ..MultipartArgs::default() };
+        assert_eq!(args.plan(Some(100_000)).unwrap().len(), 1000);
+        assert_eq!(args.part_size_for(100_000), 100);
+    }
+
+    /// The 200 GB file of the default 256 KiB parts that used to plan ~800,000 of them.
+    #[test]
+    fn test_huge_download_plans_quickly() {
+        let args = MultipartArgs { part_size: 256 * 1024, ..MultipartArgs::default() };
+        let total = 200_000_000_000;
+        let started = std::time::Instant::now();
+        let parts = args.plan(Some(total)).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100), "Planning took {:?}", started.elapsed());
+        assert_eq!(parts.len() as u64, MAX_PARTS);
+        assert_eq!(args.part_size_for(total), 97_656_250);
+        assert_eq!(parts.last().unwrap().last, total - 1);
+    }
+}
@@ -1,10 +1,16 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{io::Read, path::PathBuf, sync::Arc, time::Instant};
 use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
-use tokio::sync::RwLock;
-use tracing::info;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, trace};
 
-use crate::shared::errors::CliantError;
+use crate::shared::{
+    checksum::Checksum,
+    errors::CliantError,
+    write_check::{ReadBack, WritesVerified},
+    eta::RateSamples,
+    i18n::{MessageKey, tr, tr_plural},
+};
 
 
 
@@ -24,16 +30,23 @@ pub struct CliProgressTracker {
     progress_bar: Arc<RwLock<ProgressBar>>,
     download_path:PathBuf,
     download_name:String,
-    total_bytes: Option<usize>,
+    total_bytes: Option<u64>,
+    rate_samples: Mutex<RateSamples>,
+    /// Summary line of a successful checksum verification.
+    checksum_line: std::sync::Mutex<Option<String>>,
+    /// Summary line of a successful `--verify-writes` read-back, with its cost.
+    writes_line: std::sync::Mutex<Option<String>>,
+    /// Bytes transferred, kept once the bar is repurposed for a [`Phase`].
+    transferred: std::sync::Mutex<Option<u64>>,
 }
 impl CliProgressTracker {
     // Create a new progress tracker
     /// # Parameters
     /// * `total_bytes` - Total size of the download in bytes
     /// * `dowload_path` - Path to the download.
-    pub fn new(total_bytes: Option<usize>,download_path:PathBuf) -> Result<Self,CliantError> {
-        let progress = ProgressBar::new(total_bytes.unwrap_or(0) as u64);
-        progress.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}) \n\n {msg}")
+    pub fn new(total_bytes: Option<u64>,download_path:PathBuf) -> Result<Self,CliantError> {
+        let progress = ProgressBar::new(total_bytes.unwrap_or(0));
+        progress.set_style(ProgressStyle::with_template("{prefix}[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}) \n\n {msg}")
     .unwrap()
     .progress_chars("##-"));
         let download_name=download_path.file_name().ok_or(CliantError::ParseError(format!("Invalid download path {}, can't get file name",download_path.display())))?.to_string_lossy().to_string();
@@ -42,8 +55,123 @@ impl CliProgressTracker {
             download_path,
             download_name,
             total_bytes,
+            rate_samples: Mutex::new(RateSamples::default()),
+            checksum_line: std::sync::Mutex::default(),
+            writes_line: std::sync::Mutex::default(),
+            transferred: std::sync::Mutex::default(),
         })
     }
+
+    /// Repurposes the bar for `phase`, which processes `total` bytes once the
+    /// transfer is over, so hashing a large file isn't a silent wait.
+    pub async fn phase(&self, phase: Phase, total: Option<u64>) -> PhaseProgress {
+        let progress_bar = self.progress_bar.read().await;
+        self.transferred.lock().unwrap().get_or_insert(progress_bar.position());
+        // Resetting also restarts the rate estimate, the transfer's rate says nothing about the disk's.
+        progress_bar.reset();
+        progress_bar.set_length(total.unwrap_or(0));
+        progress_bar.set_prefix(format!("{} ", phase.label()));
+        progress_bar.set_message("");
+        debug!(?phase, total, download_name = self.download_name, "Phase started");
+        PhaseProgress { bar: progress_bar.clone() }
+    }
+
+    /// Adds the verified checksum to the completion summary.
+    pub fn checksum_verified(&self, checksum: &Checksum) {
+        let line = tr(
+            MessageKey::ChecksumVerified,
+            &[("algorithm", checksum.algorithm.name()), ("origin", checksum.origin)],
+        );
+        *self.checksum_line.lock().unwrap() = Some(line);
+    }
+
+    /// Adds the `--verify-writes` read-back and what it cost to the completion summary.
+    pub fn writes_verified(&self, verified: &WritesVerified) {
+        let mode = match verified.mode {
+            ReadBack::Cached => "cached",
+            ReadBack::Direct => "direct",
+        };
+        let line = tr(
+            MessageKey::WritesVerified,
+            &[
+                ("bytes", &verified.bytes.to_string()),
+                ("millis", &verified.elapsed.as_millis().to_string()),
+                ("mode", mode),
+            ],
+        );
+        *self.writes_line.lock().unwrap() = Some(line);
+    }
+
+    /// The lines shown once the download completed.
+    fn summary(&self, position: u64) -> String {
+        let download_path = self.download_path.display().to_string();
+        let mut summary = format!(
+            "\n {}\n {}\n {}\n",
+            tr(MessageKey::DownloadCompleted, &[("name", &self.download_name)]),
+            tr(MessageKey::DownloadPath, &[("path", &download_path)]),
+            tr_plural(MessageKey::BytesWritten, position, &[]),
+        );
+        for line in [&self.checksum_line, &self.writes_line] {
+            if let Some(line) = line.lock().unwrap().as_ref() {
+                summary.push_str(&format!(" {line}\n"));
+            }
+        }
+        summary
+    }
+}
+
+/// Work done after the transfer that reads the whole download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Hashing the file for its checksum.
+    Verifying,
+    /// `--verify-writes` reading the file back.
+    ReadingBack,
+}
+
+impl Phase {
+    fn label(self) -> String {
+        match self {
+            Self::Verifying => tr(MessageKey::PhaseVerifying, &[]),
+            Self::ReadingBack => tr(MessageKey::PhaseReadingBack, &[]),
+        }
+    }
+}
+
+/// Ticks the bar of a [`Phase`], from the blocking pool as well.
+#[derive(Clone)]
+pub struct PhaseProgress {
+    bar: ProgressBar,
+}
+
+impl PhaseProgress {
+    /// Progress that is shown nowhere.
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn hidden() -> Self {
+        Self { bar: ProgressBar::hidden() }
+    }
+
+    pub fn advance(&self, bytes: u64) {
+        self.bar.inc(bytes);
+    }
+
+    /// Wraps `inner` so every read advances the phase by what it read.
+    pub fn reader<R: Read>(self, inner: R) -> PhaseReader<R> {
+        PhaseReader { inner, progress: self }
+    }
+}
+
+pub struct PhaseReader<R> {
+    inner: R,
+    progress: PhaseProgress,
+}
+
+impl<R: Read> Read for PhaseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        Ok(read)
+    }
 }
 
 impl ProgressTracker for CliProgressTracker {
@@ -51,20 +179,26 @@ impl ProgressTracker for CliProgressTracker {
     async fn update(&self,bytes_written: usize){
         let progress = self.progress_bar.write().await;
         progress.inc(bytes_written as u64);
+        // Without a known total there is nothing to estimate the remaining time against.
+        let Some(total_bytes) = self.total_bytes else {
+            return;
+        };
+        let mut rate_samples = self.rate_samples.lock().await;
+        if rate_samples.observe(Instant::now(), progress.position()) {
+            let remaining = total_bytes.saturating_sub(progress.position());
+            if let Some(eta) = rate_samples.estimate(remaining) {
+                trace!(eta_secs = eta.eta_secs, eta_secs_low = eta.eta_secs_low, eta_secs_high = eta.eta_secs_high, "Updated remaining time estimate");
+                progress.set_message(eta.compact());
+            }
+        }
     }
     
     async fn finish(&self) {
-        // Prepare completion message before acquiring lock
-        let colored_string = format!(
-            "\n Download '{}' Completed.\n File path: {}\n",
-            self.download_name,
-            self.download_path.display()
-        )
-        .purple();
-
         // Acquire lock only for finish operation
         {
             let progress_bar = self.progress_bar.read().await;
+            let transferred = self.transferred.lock().unwrap().unwrap_or(progress_bar.position());
+            let colored_string = self.summary(transferred).purple();
             progress_bar.finish_and_clear();
             progress_bar.finish_with_message(colored_string.to_string());
         }
@@ -79,6 +213,89 @@ impl ProgressTracker for CliProgressTracker {
     }
     
     async fn start(&self) {
-        todo!()
+        // Reset the clock so time spent negotiating with the server isn't
+        // counted against the transfer rate.
+        let progress_bar = self.progress_bar.read().await;
+        progress_bar.reset_elapsed();
+        debug!(download_name = self.download_name, "Progress tracking started");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::constants::MAX_CHUNK_SIZE;
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        /// Progress past 4 GiB must neither wrap nor truncate, whatever the pointer width.
+        #[test]
+        fn test_progress_accounting_above_4gib(
+            extra in 0u64..(1 << 40),
+            chunk in (64 * 1024)..=MAX_CHUNK_SIZE,
+        ) {
+            let chunks = ((1u64 << 32) / chunk as u64) + 2;
+            let total = chunks * chunk as u64 + extra;
+            let tracker = CliProgressTracker::new(Some(total), PathBuf::from("huge.iso")).unwrap();
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let position = runtime.block_on(async {
+                for _ in 0..chunks {
+                    tracker.update(chunk).await;
+                }
+                tracker.progress_bar.read().await.position()
+            });
+            prop_assert_eq!(position, chunks * chunk as u64);
+            prop_assert!(position > u32::MAX as u64);
+            prop_assert_eq!(tracker.total_bytes, Some(total));
+            prop_assert_eq!(total.saturating_sub(position), extra);
+        }
+    }
+
+    /// A reader handing out a few bytes per call, like a slow disk.
+    struct Trickle(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_phase_ticks_every_read() {
+        let tracker = CliProgressTracker::new(Some(100), PathBuf::from("/tmp/file.bin")).unwrap();
+        tracker.update(100).await;
+        let progress = tracker.phase(Phase::Verifying, Some(10)).await;
+        let bar = tracker.progress_bar.read().await.clone();
+        assert_eq!((bar.position(), bar.length()), (0, Some(10)));
+        assert_eq!(bar.prefix(), format!("{} ", Phase::Verifying.label()));
+
+        let mut reader = progress.reader(Trickle(std::io::Cursor::new(vec![7; 10])));
+        let mut buffer = [0; 64];
+        let mut positions = Vec::new();
+        while reader.read(&mut buffer).unwrap() > 0 {
+            positions.push(bar.position());
+        }
+        assert_eq!(positions, [3, 6, 9, 10]);
+        // The summary still counts what was transferred, not what was hashed.
+        tracker.phase(Phase::ReadingBack, Some(10)).await;
+        assert_eq!(*tracker.transferred.lock().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn test_summary_reports_verified_checksum() {
+        use crate::shared::checksum::ChecksumAlgorithm;
+
+        let tracker = CliProgressTracker::new(Some(11), PathBuf::from("/tmp/file.bin")).unwrap();
+        assert!(!tracker.summary(11).contains("Repr-Digest"));
+        tracker.checksum_verified(&Checksum {
+            algorithm: ChecksumAlgorithm::Sha256,
+            expected: vec![],
+            origin: "Repr-Digest",
+        });
+        let summary = tracker.summary(11);
+        assert!(summary.contains("sha-256") && summary.contains("Repr-Digest"), "{summary}");
     }
 }
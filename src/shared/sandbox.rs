@@ -0,0 +1,157 @@
+//! Opt-in Landlock sandbox for the download process (`--sandbox`, Linux only).
+//!
+//! Cliant parses attacker-controlled data and writes files, the sandbox limits
+//! the damage a bug there can do: writes are confined to the output
+//! directory (and the `--record` directory), reads to the files the download
+//! needs plus the system locations used for name resolution and TLS.
+//!
+//! **NB:** Landlock restricts the calling thread and the threads it spawns
+//! afterwards, so the sandbox must be applied before the tokio runtime starts.
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use landlock::{
+    ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetError,
+    RulesetStatus, path_beneath_rules,
+};
+use tracing::{info, warn};
+
+use crate::shared::errors::CliantError;
+
+/// Landlock ABI the rules are written against, V3 adds truncation control.
+const ABI_VERSION: ABI = ABI::V3;
+
+/// Read-only system locations needed for name resolution, TLS and the runtime.
+/// Missing paths are skipped.
+const SYSTEM_READ_PATHS: &[&str] = &[
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/nsswitch.conf",
+    "/etc/host.conf",
+    "/etc/gai.conf",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ca-certificates",
+    "/usr/share/ca-certificates",
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/proc/self",
+    "/sys/fs/cgroup",
+    "/dev/null",
+    "/dev/urandom",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SandboxMode {
+    /// Refuse to run when the kernel can't enforce the sandbox.
+    Strict,
+    /// Run unsandboxed with a warning when the kernel lacks Landlock.
+    BestEffort,
+}
+
+/// Paths the sandboxed process may access besides `SYSTEM_READ_PATHS`.
+#[derive(Debug, Default, Clone)]
+pub struct SandboxPaths {
+    pub writable: Vec<PathBuf>,
+    pub readable: Vec<PathBuf>,
+}
+
+/// Restricts the current thread and every thread it spawns from now on.
+///
+/// Returns whether the sandbox is fully enforced, which is always the case in
+/// `Strict` mode.
+pub fn restrict_current_thread(mode: SandboxMode, paths: &SandboxPaths) -> Result<bool, CliantError> {
+    let sandbox_error = |err: RulesetError| CliantError::Fatal(format!("Can't set up the sandbox: {err}"));
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(ABI_VERSION))
+        .map_err(sandbox_error)?
+        .create()
+        .map_err(sandbox_error)?
+        .add_rules(path_beneath_rules(SYSTEM_READ_PATHS, AccessFs::from_read(ABI_VERSION)))
+        .map_err(sandbox_error)?
+        .add_rules(path_beneath_rules(&paths.readable, AccessFs::from_read(ABI_VERSION)))
+        .map_err(sandbox_error)?
+        .add_rules(path_beneath_rules(&paths.writable, AccessFs::from_all(ABI_VERSION)))
+        .map_err(sandbox_error)?
+        .restrict_self()
+        .map_err(sandbox_error)?;
+
+    match (status.ruleset, mode) {
+        (RulesetStatus::FullyEnforced, _) => {
+            info!(writable = ?paths.writable, "Sandbox enforced");
+            Ok(true)
+        }
+        (_, SandboxMode::Strict) => Err(CliantError::Fatal(
+            "This kernel can't fully enforce the Landlock sandbox, use --sandbox=best-effort to run anyway.".into(),
+        )),
+        (ruleset, SandboxMode::BestEffort) => {
+            warn!(?ruleset, "The Landlock sandbox is not fully enforced by this kernel");
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::save_to_local::{cli::LocalArgs, handler::handle};
+    use crate::shared::network::{factory::TransportType, http::config::HttpArgs};
+    use crate::shared::post_process::PostProcessArgs;
+    use crate::shared::text_mode::TextModeArgs;
+    use crate::shared::parts::MultipartArgs;
+    use crate::shared::test_server::{Route, TestServer, test_body};
+
+    #[test]
+    fn test_sandboxed_download_cannot_write_elsewhere() -> anyhow::Result<()> {
+        let allowed = std::env::temp_dir().join(format!("cliant-sandbox-{}", std::process::id()));
+        let forbidden = std::env::temp_dir().join(format!("cliant-outside-{}", std::process::id()));
+        std::fs::create_dir_all(&allowed)?;
+        std::fs::create_dir_all(&forbidden)?;
+
+        // Landlock is per thread, keep the restriction away from the test harness threads.
+        let (sandbox_allowed, sandbox_forbidden) = (allowed.clone(), forbidden.clone());
+        let outcome = std::thread::spawn(move || -> anyhow::Result<Option<std::io::Error>> {
+            let args_for = |url| LocalArgs {
+                url,
+                output: sandbox_allowed.join("download.bin"),
+                http_args: HttpArgs::default(),
+                transport: TransportType::Http,
+                no_use_server_times: false,
+                save_metadata: false,
+                text_mode: TextModeArgs::default(),
+                verify_writes: None,
+                multipart: MultipartArgs::default(),
+                post_process: PostProcessArgs::default(),
+            };
+            let paths = args_for(url::Url::parse("http://127.0.0.1/")?).sandbox_paths()?;
+            if !restrict_current_thread(SandboxMode::BestEffort, &paths)? {
+                return Ok(None);
+            }
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(async {
+                let server = TestServer::start().await?;
+                server.route("/file.bin", Route::ok(test_body(4096)));
+                handle(args_for(server.url("/file.bin"))).await
+            })?;
+            assert_eq!(std::fs::read(sandbox_allowed.join("download.bin"))?, test_body(4096));
+            Ok(std::fs::write(sandbox_forbidden.join("escape.bin"), b"escaped").err())
+        })
+        .join()
+        .unwrap()?;
+
+        let _ = std::fs::remove_dir_all(&allowed);
+        let written_outside = forbidden.join("escape.bin").exists();
+        let _ = std::fs::remove_dir_all(&forbidden);
+        match outcome {
+            None => eprintln!("Landlock is not supported by this kernel, skipping"),
+            Some(err) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+                assert!(!written_outside);
+            }
+        }
+        Ok(())
+    }
+}
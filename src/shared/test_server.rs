@@ -0,0 +1,365 @@
+//! In-process HTTP/1.1 server used by the test suite and `--self-test`.
+//!
+//! Tests register routes on a [`TestServer`] bound to a random local port
+//! instead of depending on public endpoints, so the suite runs offline and
+//! every response (status, headers, body) is under the test's control.
+//! Every request the server receives is recorded for later assertions.
+//! Routes advertising `Accept-Ranges: bytes` answer `Range` requests with a
+//! `206 Partial Content` slice of their body.
+//! A gated route ([`Route::gated`]) sends its body one chunk per permit the
+//! test releases, so a download can be parked at exactly chunk N, and counts
+//! the responses whose client hung up before their body was sent.
+//! The server task is aborted when the `TestServer` is dropped.
+//! [`tls`] puts TLS in front of it, as an origin or as an https:// proxy.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use url::Url;
+
+#[cfg(test)]
+pub mod tls;
+
+/// A canned response served for a path.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+    /// Holds the body back until the test releases it, see [`Route::gated`].
+    pub gate: Option<Gate>,
+    /// `Authorization` header value without which the route answers 401.
+    pub authorization: Option<String>,
+}
+
+impl Route {
+    /// A `200 OK` response carrying `body`.
+    pub fn ok(body: impl Into<Bytes>) -> Self {
+        Self { status: 200, headers: vec![], body: body.into(), gate: None, authorization: None }
+    }
+
+    /// An empty response with the given status code.
+    pub fn status(status: u16) -> Self {
+        Self { status, headers: vec![], body: Bytes::new(), gate: None, authorization: None }
+    }
+
+    /// A `200 OK` response whose headers are sent at once and whose body is
+    /// sent in `chunk_size` chunks, each waiting for a [`Gate::release`].
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn gated(body: impl Into<Bytes>, chunk_size: usize) -> (Self, Gate) {
+        let gate = Gate { permits: Arc::new(Semaphore::new(0)), chunk_size: chunk_size.max(1), hang_ups: Arc::default() };
+        (Self { gate: Some(gate.clone()), ..Self::ok(body) }, gate)
+    }
+
+    /// This route for requests with http basic authentication as `username`, 401 for the others.
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn with_basic_auth(self, username: &str, password: &str) -> Self {
+        use base64::Engine;
+
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+        Self { authorization: Some(format!("Basic {credentials}")), ..self }
+    }
+}
+
+/// The test's handle on a gated route, shared by every response of the route.
+#[derive(Debug, Clone)]
+pub struct Gate {
+    permits: Arc<Semaphore>,
+    chunk_size: usize,
+    hang_ups: Arc<AtomicUsize>,
+}
+
+impl Gate {
+    /// Lets `chunks` more chunks of the body through.
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn release(&self, chunks: usize) {
+        self.permits.add_permits(chunks);
+    }
+
+    /// Responses whose client closed the connection while they waited for a release.
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn hang_ups(&self) -> usize {
+        self.hang_ups.load(Ordering::SeqCst)
+    }
+}
+
+/// A request as seen by the server.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    #[cfg_attr(not(test), allow(dead_code))] // Only asserted on by tests.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Responses per path, served in order; the last one repeats.
+type Routes = Arc<Mutex<HashMap<String, VecDeque<Route>>>>;
+type Requests = Arc<Mutex<Vec<RecordedRequest>>>;
+
+pub struct TestServer {
+    addr: SocketAddr,
+    routes: Routes,
+    requests: Requests,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Binds to a random port on localhost and starts serving.
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_on(IpAddr::V4(Ipv4Addr::LOCALHOST)).await
+    }
+
+    /// Binds to a random port on `ip` and starts serving.
+    pub async fn start_on(ip: IpAddr) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((ip, 0)).await?;
+        let addr = listener.local_addr()?;
+        let routes: Routes = Arc::default();
+        let requests: Requests = Arc::default();
+        let (task_routes, task_requests) = (routes.clone(), requests.clone());
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let routes = task_routes.clone();
+                let requests = task_requests.clone();
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, routes, requests).await;
+                });
+            }
+        });
+        Ok(Self { addr, routes, requests, handle })
+    }
+
+    /// Serves `route` for every request to `path`.
+    pub fn route(&self, path: &str, route: Route) {
+        self.script(path, [route]);
+    }
+
+    /// Serves `routes` one per request to `path` in order, the last one for
+    /// every request after that, e.g two failures followed by a success.
+    ///
+    /// A path prefixed with a method, e.g `HEAD /file.bin`, takes precedence
+    /// over the bare path for requests with that method.
+    pub fn script(&self, path: &str, routes: impl IntoIterator<Item = Route>) {
+        self.routes.lock().unwrap().insert(path.to_string(), routes.into_iter().collect());
+    }
+
+    /// Absolute url of `path` on this server.
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://{}{path}", self.addr)).unwrap()
+    }
+
+    /// The address this server listens on.
+    #[cfg_attr(not(test), allow(dead_code))] // Only used by tests.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// All requests received so far, in arrival order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Deterministic, non-repeating-looking content of `len` bytes.
+pub fn test_body(len: usize) -> Bytes {
+    (0..len).map(|i| (i.wrapping_mul(31) % 251) as u8).collect::<Vec<u8>>().into()
+}
+
+/// Handles one request per connection, the response always closes it.
+///
+/// Routes are matched on the request target as sent, so the server doubles as a
+/// forward proxy (`GET http://host/path`) or a CONNECT proxy (`CONNECT host:443`).
+async fn serve_connection(
+    mut stream: TcpStream,
+    routes: Routes,
+    requests: Requests,
+) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let mut request_line = head.split("\r\n").next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = head
+        .split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let header = |wanted: &str| headers.iter().find(|(name, _)| name == wanted).map(|(_, value)| value.clone());
+    let (range, authorization) = (header("range"), header("authorization"));
+    requests.lock().unwrap().push(RecordedRequest {
+        method: method.clone(),
+        path: path.clone(),
+        headers,
+    });
+
+    let route = {
+        let mut routes = routes.lock().unwrap();
+        let key = format!("{method} {path}");
+        let key = if routes.contains_key(&key) { key } else { path.clone() };
+        routes
+            .get_mut(&key)
+            .and_then(|script| if script.len() > 1 { script.pop_front() } else { script.front().cloned() })
+            .unwrap_or(Route { body: Bytes::from_static(b"not found"), ..Route::status(404) })
+    };
+    let route = match &route.authorization {
+        Some(expected) if authorization.as_ref() != Some(expected) => Route {
+            headers: vec![("WWW-Authenticate".into(), "Basic realm=\"cliant\"".into())],
+            ..Route::status(401)
+        },
+        _ => route,
+    };
+    let route = match range {
+        Some(range) if route.status == 200 && accepts_ranges(&route) => partial(route, &range),
+        _ => route,
+    };
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\n",
+        route.status,
+        reason_phrase(route.status),
+    );
+    // A route may announce its own length, e.g a HEAD-only route for a huge file.
+    if !route.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+        response.push_str(&format!("Content-Length: {}\r\n", route.body.len()));
+    }
+    for (name, value) in &route.headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    if method != "HEAD" {
+        match &route.gate {
+            Some(gate) => {
+                for chunk in route.body.chunks(gate.chunk_size) {
+                    let mut unread = [0u8; 1];
+                    tokio::select! {
+                        permit = gate.permits.acquire() => permit?.forget(),
+                        // The client sends nothing after its request, a read completes once it hung up.
+                        _ = stream.read(&mut unread) => {
+                            gate.hang_ups.fetch_add(1, Ordering::SeqCst);
+                            return Ok(());
+                        }
+                    }
+                    stream.write_all(chunk).await?;
+                    stream.flush().await?;
+                }
+            }
+            None => stream.write_all(&route.body).await?,
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn accepts_ranges(route: &Route) -> bool {
+    route.headers.iter().any(|(name, value)| name.eq_ignore_ascii_case("accept-ranges") && value == "bytes")
+}
+
+/// The `206` answer of `route` to a single `bytes=first-last` or `bytes=-suffix` range.
+fn partial(route: Route, range: &str) -> Route {
+    let len = route.body.len();
+    let bounds = range.strip_prefix("bytes=").and_then(|spec| spec.split_once('-')).and_then(|spec| match spec {
+        ("", suffix) => suffix.parse::<usize>().ok().map(|suffix| (len.saturating_sub(suffix), len)),
+        (first, "") => first.parse().ok().map(|first| (first, len)),
+        (first, last) => Some((first.parse().ok()?, last.parse::<usize>().ok()?.saturating_add(1).min(len))),
+    });
+    let Some((start, end)) = bounds.filter(|(start, end)| start < end) else {
+        return Route::status(416);
+    };
+    let mut headers = route.headers;
+    headers.push(("Content-Range".into(), format!("bytes {start}-{}/{len}", end - 1)));
+    Route { status: 206, headers, body: route.body.slice(start..end), ..route }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        520 => "Unknown Error",
+        407 => "Proxy Authentication Required",
+        _ => "Status",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Reads `response` until `len` bytes arrived in total.
+    async fn read_until(response: &mut reqwest::Response, received: &mut Vec<u8>, len: usize) -> anyhow::Result<()> {
+        while received.len() < len {
+            let chunk = response.chunk().await?.ok_or_else(|| anyhow::anyhow!("body ended at {} bytes", received.len()))?;
+            received.extend_from_slice(&chunk);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gated_route_sends_only_released_chunks() -> anyhow::Result<()> {
+        let server = TestServer::start().await?;
+        let body = test_body(10 * 1024);
+        let (route, gate) = Route::gated(body.clone(), 1024);
+        server.route("/gated.bin", route);
+        let mut response = reqwest::get(server.url("/gated.bin")).await?;
+        assert_eq!(response.content_length(), Some(10 * 1024));
+
+        let mut received = Vec::new();
+        gate.release(3);
+        read_until(&mut response, &mut received, 3 * 1024).await?;
+        // Parked at chunk 3 until the next release.
+        assert!(tokio::time::timeout(Duration::from_millis(100), response.chunk()).await.is_err());
+        assert_eq!(received.len(), 3 * 1024);
+
+        gate.release(7);
+        read_until(&mut response, &mut received, body.len()).await?;
+        assert_eq!(received, body);
+        assert!(response.chunk().await?.is_none());
+        assert_eq!(gate.hang_ups(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gated_route_counts_hang_ups() -> anyhow::Result<()> {
+        let server = TestServer::start().await?;
+        let (route, gate) = Route::gated(test_body(10 * 1024), 1024);
+        server.route("/gated.bin", route);
+        let mut response = reqwest::get(server.url("/gated.bin")).await?;
+        gate.release(1);
+        read_until(&mut response, &mut Vec::new(), 1024).await?;
+        drop(response);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while gate.hang_ups() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
+}
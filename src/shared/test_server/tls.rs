@@ -0,0 +1,114 @@
+//! TLS in front of a [`TestServer`](super::TestServer), for tests of https://
+//! proxies.
+//!
+//! A [`TlsRelay`] terminates TLS with a certificate for `127.0.0.1`,
+//! `localhost` and `cliant.invalid` signed by [`CA_PEM`], and pipes the
+//! decrypted bytes to an upstream address: either as a plain TLS front or as
+//! an https:// proxy that accepts a `CONNECT` before tunneling. Chaining a
+//! proxy to a front gives a download through a TLS proxy to a TLS origin.
+//! A connection whose handshake fails is dropped, the relay keeps serving.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, copy_bidirectional};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// The CA that signed the relays' certificate.
+pub const CA_PEM: &str = include_str!("certs/ca.pem");
+/// A CA unrelated to the relays' certificate.
+pub const OTHER_CA_PEM: &str = include_str!("certs/other-ca.pem");
+const SERVER_PEM: &str = include_str!("certs/server.pem");
+const SERVER_KEY_PEM: &str = include_str!("certs/server-key.pem");
+
+pub struct TlsRelay {
+    addr: SocketAddr,
+    connects: Arc<Mutex<Vec<String>>>,
+    handle: JoinHandle<()>,
+}
+
+impl TlsRelay {
+    /// Terminates TLS and pipes every connection to `upstream`.
+    pub async fn front(upstream: SocketAddr) -> anyhow::Result<Self> {
+        Self::start(upstream, false).await
+    }
+
+    /// An https:// proxy tunneling every `CONNECT`, whatever its target, to `upstream`.
+    pub async fn connect_proxy(upstream: SocketAddr) -> anyhow::Result<Self> {
+        Self::start(upstream, true).await
+    }
+
+    async fn start(upstream: SocketAddr, proxy: bool) -> anyhow::Result<Self> {
+        let certificates = CertificateDer::pem_slice_iter(SERVER_PEM.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(SERVER_KEY_PEM.as_bytes())?;
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)?;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let connects: Arc<Mutex<Vec<String>>> = Arc::default();
+        let task_connects = connects.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (acceptor, connects) = (acceptor.clone(), task_connects.clone());
+                tokio::spawn(async move {
+                    let _ = relay(acceptor, stream, upstream, proxy.then_some(connects)).await;
+                });
+            }
+        });
+        Ok(Self { addr, connects, handle })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Targets of the `CONNECT` requests tunneled so far, e.g `cliant.invalid:443`.
+    pub fn connects(&self) -> Vec<String> {
+        self.connects.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TlsRelay {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn relay(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    upstream: SocketAddr,
+    connects: Option<Arc<Mutex<Vec<String>>>>,
+) -> anyhow::Result<()> {
+    let mut client = acceptor.accept(stream).await?;
+    if let Some(connects) = connects {
+        // The client waits for the answer before sending anything past the head.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            if client.read(&mut byte).await? == 0 {
+                return Ok(());
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let Some(target) = head.strip_prefix("CONNECT ").and_then(|line| line.split_whitespace().next()) else {
+            client.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n").await?;
+            return Ok(());
+        };
+        connects.lock().unwrap().push(target.to_string());
+        client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+    }
+    let mut upstream = TcpStream::connect(upstream).await?;
+    copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! `--text-mode`: line ending normalization and UTF-8 byte order mark removal
+//! for text downloads, e.g a CSV with a BOM breaking a parser or a shell
+//! script with CRLF line endings.
+//!
+//! The conversion is applied to the stream as it is written, after checksums
+//! are computed: digests announced by the server describe what it sent, not
+//! the converted file. Content that turns out to be binary (a NUL byte in its
+//! first chunk) is written as is with a warning, whatever its declared type.
+
+use bytes::Bytes;
+use clap::{Args, ValueEnum};
+use tracing::{info, warn};
+
+use super::network::DownloadInfo;
+
+/// `application/*` subtypes that are text.
+const TEXT_APPLICATION_TYPES: &[&str] = &[
+    "json", "xml", "javascript", "ecmascript", "yaml", "x-yaml", "toml", "ndjson", "x-ndjson", "sql", "x-sh",
+];
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct TextModeArgs {
+    /// Normalize the line endings of text downloads and strip a leading UTF-8 BOM.
+    /// `native` (the default) is `lf`, or `crlf` on Windows.
+    #[arg(long, value_enum, value_name = "ENDING", num_args = 0..=1, default_missing_value = "native")]
+    pub text_mode: Option<LineEnding>,
+    /// Apply --text-mode whatever the content type, e.g for scripts served as application/octet-stream.
+    #[arg(long, requires = "text_mode")]
+    pub assume_text: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Native,
+}
+
+impl LineEnding {
+    fn resolve(self) -> Self {
+        match self {
+            Self::Native if cfg!(windows) => Self::Crlf,
+            Self::Native => Self::Lf,
+            ending => ending,
+        }
+    }
+}
+
+impl TextModeArgs {
+    /// The converter for a download described by `info`, if it is to be converted.
+    pub fn converter(&self, info: &DownloadInfo) -> Option<TextConverter> {
+        let ending = self.text_mode?;
+        let mime = info
+            .content_type
+            .as_deref()
+            .map(|content_type| content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+        if !self.assume_text && !mime.as_deref().is_some_and(is_text) {
+            info!(
+                "--text-mode: leaving {} content as is, --assume-text converts it anyway",
+                mime.as_deref().unwrap_or("untyped")
+            );
+            return None;
+        }
+        Some(TextConverter::new(ending))
+    }
+}
+
+/// Whether `mime`, a lowercase media type without parameters, is text.
+pub fn is_text(mime: &str) -> bool {
+    let Some((kind, subtype)) = mime.split_once('/') else {
+        return false;
+    };
+    kind == "text"
+        || (kind == "application"
+            && (subtype.ends_with("+json") || subtype.ends_with("+xml") || TEXT_APPLICATION_TYPES.contains(&subtype)))
+}
+
+#[derive(Debug)]
+enum State {
+    /// Collecting the first bytes, enough to recognize a BOM.
+    Start(Vec<u8>),
+    Converting,
+    /// The content is binary and passed through.
+    Binary,
+}
+
+/// Converts line endings of a stream of chunks, pairs split across chunks included.
+#[derive(Debug)]
+pub struct TextConverter {
+    ending: LineEnding,
+    state: State,
+    /// The last byte seen was a CR, possibly at the end of the previous chunk.
+    after_cr: bool,
+    converted: u64,
+    stripped_bom: bool,
+}
+
+impl TextConverter {
+    pub fn new(ending: LineEnding) -> Self {
+        Self { ending: ending.resolve(), state: State::Start(Vec::new()), after_cr: false, converted: 0, stripped_bom: false }
+    }
+
+    /// Converts the next chunk, the result may hold back a trailing CR until the next one.
+    pub fn push(&mut self, chunk: &[u8]) -> Bytes {
+        match &mut self.state {
+            State::Start(start) => {
+                start.extend_from_slice(chunk);
+                if start.len() < UTF8_BOM.len() {
+                    return Bytes::new();
+                }
+                let start = std::mem::take(start);
+                self.begin(start)
+            }
+            State::Converting => self.convert(chunk).into(),
+            State::Binary => Bytes::copy_from_slice(chunk),
+        }
+    }
+
+    /// What is left once the content ended.
+    pub fn finish(&mut self) -> Bytes {
+        let mut rest = match &mut self.state {
+            State::Start(start) => {
+                let start = std::mem::take(start);
+                self.begin(start).to_vec()
+            }
+            _ => Vec::new(),
+        };
+        if std::mem::take(&mut self.after_cr) && self.ending == LineEnding::Lf {
+            rest.push(b'\r');
+        }
+        rest.into()
+    }
+
+    /// What was changed, for the completion log.
+    pub fn report(&self) -> String {
+        let ending = match self.ending {
+            LineEnding::Crlf => "LF→CRLF",
+            _ => "CRLF→LF",
+        };
+        let bom = if self.stripped_bom { ", stripped the UTF-8 byte order mark" } else { "" };
+        format!("--text-mode converted {} {ending}{bom}", self.converted)
+    }
+
+    fn begin(&mut self, start: Vec<u8>) -> Bytes {
+        if start.contains(&0) {
+            warn!("--text-mode: the content looks binary, writing it unconverted");
+            self.state = State::Binary;
+            return start.into();
+        }
+        self.state = State::Converting;
+        let text = match start.strip_prefix(UTF8_BOM) {
+            Some(text) => {
+                self.stripped_bom = true;
+                text
+            }
+            None => &start,
+        };
+        self.convert(text).into()
+    }
+
+    fn convert(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len() + chunk.len() / 32);
+        for &byte in chunk {
+            match self.ending {
+                LineEnding::Crlf => {
+                    if byte == b'\n' && !self.after_cr {
+                        out.push(b'\r');
+                        self.converted += 1;
+                    }
+                    out.push(byte);
+                }
+                // A CR is held back until the next byte tells whether it starts a CRLF.
+                _ => {
+                    if self.after_cr {
+                        if byte == b'\n' {
+                            self.converted += 1;
+                        } else {
+                            out.push(b'\r');
+                        }
+                    }
+                    if byte != b'\r' {
+                        out.push(byte);
+                    }
+                }
+            }
+            self.after_cr = byte == b'\r';
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(ending: LineEnding, chunks: &[&[u8]]) -> (Vec<u8>, TextConverter) {
+        let mut converter = TextConverter::new(ending);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend_from_slice(&converter.push(chunk));
+        }
+        out.extend_from_slice(&converter.finish());
+        (out, converter)
+    }
+
+    #[test]
+    fn test_crlf_split_across_chunks() {
+        let (out, converter) = convert(LineEnding::Lf, &[b"a,b\r", b"\nc,d\r", b"\r\n", b"e\r"]);
+        // Lone CRs are kept, the last one even at the very end.
+        assert_eq!(out, b"a,b\nc,d\r\ne\r");
+        assert_eq!(converter.report(), "--text-mode converted 2 CRLF→LF");
+
+        let (out, converter) = convert(LineEnding::Crlf, &[b"one\r", b"\ntwo\n", b"\nthree"]);
+        assert_eq!(out, b"one\r\ntwo\r\n\r\nthree");
+        assert_eq!(converter.converted, 2);
+    }
+
+    #[test]
+    fn test_bom_is_stripped() {
+        let (out, converter) = convert(LineEnding::Lf, &[b"\xEF", b"\xBB\xBF", b""]);
+        assert_eq!(out, b"");
+        assert!(converter.report().ends_with("stripped the UTF-8 byte order mark"));
+        let (out, _) = convert(LineEnding::Lf, &[b"\xEF\xBB\xBFid\r\n"]);
+        assert_eq!(out, b"id\n");
+        let (out, _) = convert(LineEnding::Lf, &[b"a"]);
+        assert_eq!(out, b"a");
+    }
+
+    #[test]
+    fn test_binary_content_is_left_alone() {
+        let binary: &[u8] = b"\xEF\xBB\xBF\x00\x01\r\n\x02";
+        let (out, converter) = convert(LineEnding::Lf, &[&binary[..4], &binary[4..]]);
+        assert_eq!(out, binary);
+        assert_eq!(converter.converted, 0);
+    }
+
+    #[test]
+    fn test_flags() {
+        #[derive(clap::Parser)]
+        struct Cli {
+            #[command(flatten)]
+            text: TextModeArgs,
+        }
+        let parse = |args: &[&str]| <Cli as clap::Parser>::try_parse_from(args).map(|cli| cli.text.text_mode);
+        assert_eq!(parse(&["cliant"]).unwrap(), None);
+        assert_eq!(parse(&["cliant", "--text-mode"]).unwrap(), Some(LineEnding::Native));
+        assert_eq!(parse(&["cliant", "--text-mode", "crlf"]).unwrap(), Some(LineEnding::Crlf));
+        assert!(parse(&["cliant", "--assume-text"]).is_err());
+    }
+
+    #[test]
+    fn test_only_text_types_are_converted() {
+        let args = TextModeArgs { text_mode: Some(LineEnding::Lf), assume_text: false };
+        let typed = |content_type: &str| DownloadInfo { content_type: Some(content_type.into()), ..DownloadInfo::default() };
+        assert!(args.converter(&typed("text/csv; charset=utf-8")).is_some());
+        assert!(args.converter(&typed("application/x-sh")).is_some());
+        assert!(args.converter(&typed("application/octet-stream")).is_none());
+        assert!(args.converter(&DownloadInfo::default()).is_none());
+        let assumed = TextModeArgs { assume_text: true, ..args };
+        assert!(assumed.converter(&typed("application/octet-stream")).is_some());
+        assert!(TextModeArgs::default().converter(&typed("text/plain")).is_none());
+    }
+}
@@ -0,0 +1,223 @@
+//! `--verify-writes`: reads a download back from disk once it is written and
+//! compares it with what Cliant wrote, for storage that silently returns other
+//! data than it was given (failing USB sticks, overlayfs quirks).
+//!
+//! What goes to the writer, after any `--text-mode` conversion, is hashed in
+//! [`VERIFY_WRITES_WINDOW`] windows. Once the writer is closed the file is read
+//! back through its own handle, one window at a time with a single read buffer,
+//! so the read-back costs the same memory whatever the file size.
+//! `--verify-writes=direct` syncs the file and drops it from the page cache
+//! first, so the comparison reads the medium rather than the kernel's copy.
+//! There is a single stream and no part to retry, a mismatch fails the download.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use ring::digest::{Context, Digest, SHA256};
+
+use super::blocking_guard;
+use super::constants::VERIFY_WRITES_WINDOW;
+use super::errors::CliantError;
+use super::progress_tracker::PhaseProgress;
+
+const READ_BUFFER: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReadBack {
+    /// Read the file back, possibly from the page cache.
+    Cached,
+    /// Sync the file and drop it from the page cache before reading it back (Linux).
+    Direct,
+}
+
+/// What a successful read-back cost, for the completion summary.
+#[derive(Debug, Clone, Copy)]
+pub struct WritesVerified {
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub mode: ReadBack,
+}
+
+/// Hashes everything written to a file, window by window.
+pub struct WriteRecorder {
+    mode: ReadBack,
+    windows: Vec<Digest>,
+    current: Context,
+    in_window: u64,
+    written: u64,
+}
+
+impl WriteRecorder {
+    pub fn new(mode: ReadBack) -> Self {
+        Self { mode, windows: Vec::new(), current: Context::new(&SHA256), in_window: 0, written: 0 }
+    }
+
+    /// Records `bytes` as the next bytes written to the file.
+    pub fn record(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let take = (VERIFY_WRITES_WINDOW - self.in_window).min(bytes.len() as u64) as usize;
+            self.current.update(&bytes[..take]);
+            self.in_window += take as u64;
+            self.written += take as u64;
+            bytes = &bytes[take..];
+            if self.in_window == VERIFY_WRITES_WINDOW {
+                let full = std::mem::replace(&mut self.current, Context::new(&SHA256));
+                self.windows.push(full.finish());
+                self.in_window = 0;
+            }
+        }
+    }
+
+    /// Reads `path` back and compares it with everything recorded, call it once the file is closed.
+    pub async fn verify(mut self, path: PathBuf, progress: PhaseProgress) -> Result<WritesVerified, CliantError> {
+        if self.in_window > 0 {
+            self.windows.push(self.current.finish());
+        }
+        let (mode, windows, written) = (self.mode, self.windows, self.written);
+        blocking_guard::run("Reading the download back", move || read_back(&path, mode, &windows, written, progress)).await?
+    }
+}
+
+fn read_back(
+    path: &Path,
+    mode: ReadBack,
+    windows: &[Digest],
+    written: u64,
+    progress: PhaseProgress,
+) -> std::io::Result<Result<WritesVerified, CliantError>> {
+    let started = Instant::now();
+    let file = std::fs::File::open(path)?;
+    if mode == ReadBack::Direct {
+        drop_cached(&file)?;
+    }
+    let on_disk = file.metadata()?.len();
+    if on_disk != written {
+        return Ok(Err(mismatch(path, format!("the file holds {on_disk} bytes, {written} were written"))));
+    }
+    let mut file = progress.reader(file);
+    let mut buffer = vec![0; READ_BUFFER];
+    for (index, expected) in windows.iter().enumerate() {
+        let start = index as u64 * VERIFY_WRITES_WINDOW;
+        let end = (start + VERIFY_WRITES_WINDOW).min(written);
+        let mut context = Context::new(&SHA256);
+        let mut remaining = end - start;
+        while remaining > 0 {
+            let read = &mut buffer[..remaining.min(READ_BUFFER as u64) as usize];
+            file.read_exact(read)?;
+            context.update(read);
+            remaining -= read.len() as u64;
+        }
+        if context.finish().as_ref() != expected.as_ref() {
+            return Ok(Err(mismatch(path, format!("bytes {start}-{} differ", end - 1))));
+        }
+    }
+    Ok(Ok(WritesVerified { bytes: written, elapsed: started.elapsed(), mode }))
+}
+
+fn mismatch(path: &Path, reason: String) -> CliantError {
+    CliantError::StorageMismatch { path: path.display().to_string(), device: device(path), reason }
+}
+
+/// Flushes `file` to the medium and evicts its pages, so the next reads hit the storage.
+#[cfg(target_os = "linux")]
+fn drop_cached(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    file.sync_all()?;
+    // SAFETY: the descriptor stays open for the duration of the call.
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        err => Err(std::io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached(file: &std::fs::File) -> std::io::Result<()> {
+    tracing::warn!("--verify-writes=direct can't bypass the page cache on this platform, the file may be read back from memory");
+    file.sync_all()
+}
+
+/// The mount source and filesystem `path` lives on, e.g `/dev/sdb1 (vfat, device 8:17)`.
+#[cfg(target_os = "linux")]
+fn device(path: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return "unknown device".into();
+    };
+    let id = format!("{}:{}", libc::major(metadata.dev()), libc::minor(metadata.dev()));
+    // Fields: mount id, parent id, major:minor, root, mount point, options... - type source options.
+    let mounts = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    mounts
+        .lines()
+        .find_map(|line| {
+            if line.split(' ').nth(2)? != id {
+                return None;
+            }
+            let (_, fields) = line.split_once(" - ")?;
+            let mut fields = fields.split(' ');
+            let (kind, source) = (fields.next()?, fields.next()?);
+            Some(format!("{source} ({kind}, device {id})"))
+        })
+        .unwrap_or_else(|| format!("device {id}"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn device(_path: &Path) -> String {
+    "unknown device".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::test_server::test_body;
+    use async_tempfile::TempDir;
+
+    /// Records `content` in uneven chunks and writes it to a file of the returned directory.
+    async fn recorded(content: &[u8], mode: ReadBack) -> anyhow::Result<(TempDir, PathBuf, WriteRecorder)> {
+        let dir = TempDir::new().await?;
+        let path = dir.dir_path().join("written.bin");
+        tokio::fs::write(&path, content).await?;
+        let mut recorder = WriteRecorder::new(mode);
+        for chunk in content.chunks(300_007) {
+            recorder.record(chunk);
+        }
+        Ok((dir, path, recorder))
+    }
+
+    #[tokio::test]
+    async fn test_identical_content_verifies() -> anyhow::Result<()> {
+        let content = test_body(VERIFY_WRITES_WINDOW as usize + 12_345);
+        for mode in [ReadBack::Cached, ReadBack::Direct] {
+            let (_dir, path, recorder) = recorded(&content, mode).await?;
+            let verified = recorder.verify(path, PhaseProgress::hidden()).await?;
+            assert_eq!(verified.bytes, content.len() as u64);
+            assert_eq!(verified.mode, mode);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_storage_differences_are_located() -> anyhow::Result<()> {
+        let content = test_body(VERIFY_WRITES_WINDOW as usize + 12_345);
+        let window = VERIFY_WRITES_WINDOW;
+
+        // A byte flipped by the storage in the second window.
+        let (_dir, path, recorder) = recorded(&content, ReadBack::Direct).await?;
+        let mut stored = content.to_vec();
+        stored[window as usize + 7] ^= 0x20;
+        tokio::fs::write(&path, &stored).await?;
+        let err = recorder.verify(path, PhaseProgress::hidden()).await.unwrap_err().to_string();
+        assert!(err.starts_with("Storage returned different data than written to"), "{err}");
+        assert!(err.contains(&format!("bytes {window}-{} differ", content.len() - 1)), "{err}");
+        assert!(err.contains("device"), "{err}");
+
+        let (_dir, path, recorder) = recorded(&content, ReadBack::Cached).await?;
+        tokio::fs::write(&path, &content[..1000]).await?;
+        let err = recorder.verify(path, PhaseProgress::hidden()).await.unwrap_err().to_string();
+        assert!(err.contains(&format!("the file holds 1000 bytes, {} were written", content.len())), "{err}");
+        Ok(())
+    }
+}